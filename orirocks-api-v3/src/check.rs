@@ -0,0 +1,141 @@
+use std::fmt::{Display, Formatter};
+use crate::{split_resource, Value, ValueError, ValueType};
+
+impl ValueType {
+  /// Checks that `value` conforms to this type.
+  /// The returned error points at the first offending element, e.g. `[2].name`.
+  /// Integers are accepted where floats are expected.
+  pub fn check(&self, value: &Value) -> Result<(), ValueError> {
+    match (self, value) {
+      (ValueType::Integer, Value::Integer(_)) => Ok(()),
+      (ValueType::Float, Value::Float(_) | Value::Integer(_)) => Ok(()),
+      (ValueType::String, Value::String(_)) => Ok(()),
+      (ValueType::Bool, Value::Bool(_)) => Ok(()),
+      (ValueType::Array { inner }, Value::Array(values)) => {
+        for (i, v) in values.iter().enumerate() {
+          inner.check(v).map_err(|v| v.at_index(i))?;
+        }
+        Ok(())
+      }
+      (ValueType::Dict { inner }, Value::Dict(values)) => {
+        for (k, v) in values {
+          inner.check(v).map_err(|v| v.at_key(k))?;
+        }
+        Ok(())
+      }
+      (ValueType::Optional { .. }, Value::Null) => Ok(()),
+      (ValueType::Optional { inner }, v) => inner.check(v),
+      (ValueType::Enum { values }, Value::String(v)) => {
+        if values.contains(v) {
+          Ok(())
+        } else {
+          Err(ValueError::new(format!("expected {}, found `{}`", self, v)))
+        }
+      }
+      (ValueType::Record { fields }, Value::Dict(values)) => {
+        if let Some(unknown) = values.keys().find(|v| !fields.contains_key(*v)) {
+          return Err(ValueError::new("unknown field").at_key(unknown));
+        }
+        for (k, ty) in fields {
+          match values.get(k) {
+            Some(v) => ty.check(v).map_err(|v| v.at_key(k))?,
+            None if ty.is_optional() => {},
+            None => return Err(ValueError::missing(k))
+          }
+        }
+        Ok(())
+      }
+      (ValueType::Path, Value::String(v)) => {
+        if split_resource(v).is_some() {
+          Ok(())
+        } else {
+          Err(ValueError::new(format!("expected resource location like `src:path/to/file`, found `{}`", v)))
+        }
+      }
+      (ty, v) => Err(ValueError::type_mismatch(&ty.to_string(), v))
+    }
+  }
+
+  /// Returns true if values of this type may be left out
+  pub fn is_optional(&self) -> bool {
+    matches!(self, ValueType::Optional { .. })
+  }
+
+  /// Returns true if values of this type are single values that can be written as a string
+  pub fn is_scalar(&self) -> bool {
+    match self {
+      ValueType::Integer | ValueType::Float | ValueType::String | ValueType::Bool | ValueType::Enum { .. } | ValueType::Path => true,
+      ValueType::Optional { inner } => inner.is_scalar(),
+      _ => false
+    }
+  }
+
+  /// Returns true if every value of type `other` also conforms to this type
+  pub fn accepts(&self, other: &ValueType) -> bool {
+    match (self, other) {
+      (a, b) if a == b => true,
+      (ValueType::Float, ValueType::Integer) => true,
+      (ValueType::String, ValueType::Enum { .. } | ValueType::Path) => true,
+      (ValueType::Enum { values }, ValueType::Enum { values: other }) => other.iter().all(|v| values.contains(v)),
+      (ValueType::Optional { inner }, ValueType::Optional { inner: other }) => inner.accepts(other),
+      (ValueType::Optional { inner }, other) => inner.accepts(other),
+      (ValueType::Array { inner }, ValueType::Array { inner: other }) => inner.accepts(other),
+      (ValueType::Dict { inner }, ValueType::Dict { inner: other }) => inner.accepts(other),
+      (ValueType::Dict { inner }, ValueType::Record { fields }) => fields.values().all(|v| inner.accepts(v)),
+      (ValueType::Record { fields }, ValueType::Record { fields: other }) => {
+        other.iter().all(|(k, v)| fields.get(k).map(|f| f.accepts(v)).unwrap_or(false)) &&
+          fields.iter().all(|(k, v)| v.is_optional() || other.get(k).map(|v| !v.is_optional()).unwrap_or(false))
+      }
+      _ => false
+    }
+  }
+
+  /// Collects every resource location (value of type `path`) inside `value`.
+  /// `value` is assumed to have been checked against this type; parts that do not conform are skipped.
+  pub fn resources<'a>(&self, value: &'a Value) -> Vec<&'a str> {
+    let mut out = vec![];
+    self.collect_resources(value, &mut out);
+    out
+  }
+
+  fn collect_resources<'a>(&self, value: &'a Value, out: &mut Vec<&'a str>) {
+    match (self, value) {
+      (ValueType::Path, Value::String(v)) => out.push(v),
+      (ValueType::Optional { inner }, v) => inner.collect_resources(v, out),
+      (ValueType::Array { inner }, Value::Array(values)) => {
+        values.iter().for_each(|v| inner.collect_resources(v, out));
+      }
+      (ValueType::Dict { inner }, Value::Dict(values)) => {
+        values.values().for_each(|v| inner.collect_resources(v, out));
+      }
+      (ValueType::Record { fields }, Value::Dict(values)) => {
+        for (k, ty) in fields {
+          if let Some(v) = values.get(k) {
+            ty.collect_resources(v, out);
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+impl Display for ValueType {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ValueType::Integer => f.write_str("integer"),
+      ValueType::Float => f.write_str("float"),
+      ValueType::String => f.write_str("string"),
+      ValueType::Bool => f.write_str("bool"),
+      ValueType::Array { inner } => write!(f, "array of {}", inner),
+      ValueType::Dict { inner } => write!(f, "dict of {}", inner),
+      ValueType::Optional { inner } => write!(f, "optional {}", inner),
+      ValueType::Enum { values } => {
+        let values = values.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>();
+        write!(f, "one of {}", values.join(", "))
+      }
+      ValueType::Record { .. } => f.write_str("record"),
+      ValueType::Path => f.write_str("resource location")
+    }
+  }
+}
\ No newline at end of file
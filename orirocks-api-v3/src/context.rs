@@ -0,0 +1,149 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Severity of a log line emitted by a plugin
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace
+}
+
+/// Progress of a long-running plugin operation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Progress {
+  /// `done` bytes out of `total` have been processed. `total` is `None` if it is not known.
+  Bytes { done: u64, total: Option<u64> },
+  /// A percentage between 0 and 100
+  Percent(f64)
+}
+
+/// Identifies what a plugin is currently working on
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Tag {
+  pub artifact: String,
+  pub step: Option<String>
+}
+
+/// Receives log lines and progress reports from plugins. This is implemented by the host.
+pub trait Reporter: Send + Sync {
+  fn log(&self, tag: &Tag, level: LogLevel, message: &str);
+  fn progress(&self, tag: &Tag, progress: Progress);
+}
+
+/// A token that is set when the host wants the current operation to stop,
+/// either because the user aborted it or because a timeout expired.
+/// Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+  cancelled: Arc<AtomicBool>,
+  deadline: Option<Instant>
+}
+
+impl CancellationToken {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Creates a token that is additionally considered cancelled once `timeout` has elapsed.
+  pub fn with_timeout(timeout: Duration) -> Self {
+    CancellationToken {
+      cancelled: Arc::new(AtomicBool::new(false)),
+      deadline: Some(Instant::now() + timeout)
+    }
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst) || self.is_timed_out()
+  }
+
+  pub fn is_timed_out(&self) -> bool {
+    self.deadline.map(|v| Instant::now() >= v).unwrap_or(false)
+  }
+
+  /// Returns an error describing why the operation was stopped, if it was.
+  /// Intended to be used with `?` inside plugin loops.
+  pub fn check(&self) -> Result<(), String> {
+    if self.cancelled.load(Ordering::SeqCst) {
+      Err("operation cancelled".into())
+    } else if self.is_timed_out() {
+      Err("operation timed out".into())
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// A handle back into the host, passed to every plugin call.
+pub struct Context<'a> {
+  tag: Tag,
+  reporter: &'a dyn Reporter,
+  token: CancellationToken
+}
+
+impl<'a> Context<'a> {
+  pub fn new(artifact: String, reporter: &'a dyn Reporter, token: CancellationToken) -> Self {
+    Context {
+      tag: Tag { artifact, step: None },
+      reporter,
+      token
+    }
+  }
+
+  /// Creates a context for a single step of the current artifact
+  pub fn for_step(&self, step: String) -> Context<'a> {
+    Context {
+      tag: Tag { artifact: self.tag.artifact.clone(), step: Some(step) },
+      reporter: self.reporter,
+      token: self.token.clone()
+    }
+  }
+
+  pub fn tag(&self) -> &Tag {
+    &self.tag
+  }
+
+  pub fn log(&self, level: LogLevel, message: &str) {
+    self.reporter.log(&self.tag, level, message);
+  }
+
+  pub fn error(&self, message: &str) {
+    self.log(LogLevel::Error, message);
+  }
+
+  pub fn warn(&self, message: &str) {
+    self.log(LogLevel::Warn, message);
+  }
+
+  pub fn info(&self, message: &str) {
+    self.log(LogLevel::Info, message);
+  }
+
+  pub fn debug(&self, message: &str) {
+    self.log(LogLevel::Debug, message);
+  }
+
+  pub fn progress(&self, progress: Progress) {
+    self.reporter.progress(&self.tag, progress);
+  }
+
+  pub fn cancellation(&self) -> &CancellationToken {
+    &self.token
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.token.is_cancelled()
+  }
+
+  /// Shorthand for `self.cancellation().check()`
+  pub fn check_cancelled(&self) -> Result<(), String> {
+    self.token.check()
+  }
+}
\ No newline at end of file
@@ -0,0 +1,247 @@
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::vec;
+use serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use crate::{CmpFloat, Value, ValueError};
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+    f.write_str("any value")
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+    Value::deserialize(deserializer)
+  }
+
+  fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+    Ok(Value::Bool(v))
+  }
+
+  fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+    Ok(Value::Integer(v))
+  }
+
+  fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+    i64::try_from(v)
+      .map(Value::Integer)
+      .map_err(|_| E::custom(format!("integer {} is too large", v)))
+  }
+
+  fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+    Ok(Value::Float(CmpFloat::new(v)))
+  }
+
+  fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+    Ok(Value::String(v.to_string()))
+  }
+
+  fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+    Ok(Value::String(v))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+    let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(v) = seq.next_element()? {
+      values.push(v);
+    }
+    Ok(Value::Array(values))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+    let mut values = BTreeMap::new();
+    while let Some((k, v)) = map.next_entry::<String, Value>()? {
+      if values.insert(k.clone(), v).is_some() {
+        return Err(de::Error::custom(format!("duplicate key `{}`", k)));
+      }
+    }
+    Ok(Value::Dict(values))
+  }
+
+  /// Core tags such as `!!str` are resolved by the YAML parser, so only unknown tags end up here
+  fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Value, A::Error> {
+    let (tag, _) = data.variant::<String>()?;
+    Err(de::Error::custom(format!("unsupported tag `!{}`", tag)))
+  }
+}
+
+impl<'de> Deserialize<'de> for Value {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(ValueVisitor)
+  }
+}
+
+/// Deserializes a `Value` into any type implementing `Deserialize`.
+/// Errors carry the path of the offending field.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, ValueError> {
+  T::deserialize(value)
+}
+
+/// Deserializes a set of plugin options into a plugin-defined struct
+pub fn from_options<T: DeserializeOwned>(options: HashMap<String, Value>) -> Result<T, ValueError> {
+  from_value(Value::Dict(options.into_iter().collect()))
+}
+
+impl de::Error for ValueError {
+  fn custom<T: Display>(msg: T) -> Self {
+    ValueError::new(msg.to_string())
+  }
+}
+
+impl<'de> IntoDeserializer<'de, ValueError> for Value {
+  type Deserializer = Self;
+
+  fn into_deserializer(self) -> Self::Deserializer {
+    self
+  }
+}
+
+impl<'de> Deserializer<'de> for Value {
+  type Error = ValueError;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    match self {
+      Value::Null => visitor.visit_unit(),
+      Value::Bool(v) => visitor.visit_bool(v),
+      Value::Integer(v) => visitor.visit_i64(v),
+      Value::Float(v) => visitor.visit_f64(v.inner),
+      Value::String(v) => visitor.visit_string(v),
+      Value::Array(v) => visitor.visit_seq(SeqDeserializer { iter: v.into_iter(), index: 0 }),
+      Value::Dict(v) => visitor.visit_map(MapDeserializer { iter: v.into_iter(), key: None, value: None })
+    }
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    match self {
+      Value::Null => visitor.visit_none(),
+      v => visitor.visit_some(v)
+    }
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+    match self {
+      Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+      Value::Dict(v) if v.len() == 1 => {
+        let (variant, value) = v.into_iter().next().unwrap();
+        visitor.visit_enum(EnumDeserializer { variant, value })
+      }
+      v => Err(ValueError::type_mismatch("string or single-key dict", &v))
+    }
+  }
+
+  forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+  }
+}
+
+struct SeqDeserializer {
+  iter: vec::IntoIter<Value>,
+  index: usize
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+  type Error = ValueError;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+    match self.iter.next() {
+      Some(value) => {
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(value).map(Some).map_err(|v| v.at_index(index))
+      }
+      None => Ok(None)
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.iter.len())
+  }
+}
+
+struct MapDeserializer {
+  iter: btree_map::IntoIter<String, Value>,
+  key: Option<String>,
+  value: Option<Value>
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+  type Error = ValueError;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+    match self.iter.next() {
+      Some((key, value)) => {
+        self.value = Some(value);
+        self.key = Some(key.clone());
+        seed.deserialize(key.into_deserializer()).map(Some)
+      }
+      None => Ok(None)
+    }
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+    let key = self.key.take().unwrap_or_default();
+    let value = self.value.take().ok_or_else(|| ValueError::new("value requested before key"))?;
+    seed.deserialize(value).map_err(|v| v.at_key(&key))
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.iter.len())
+  }
+}
+
+struct EnumDeserializer {
+  variant: String,
+  value: Value
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+  type Error = ValueError;
+  type Variant = VariantDeserializer;
+
+  fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error> {
+    let variant = seed.deserialize(self.variant.clone().into_deserializer())?;
+    Ok((variant, VariantDeserializer { variant: self.variant, value: self.value }))
+  }
+}
+
+struct VariantDeserializer {
+  variant: String,
+  value: Value
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+  type Error = ValueError;
+
+  fn unit_variant(self) -> Result<(), Self::Error> {
+    Err(ValueError::new(format!("expected unit variant `{}`, found {}", self.variant, self.value.type_name())))
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+    seed.deserialize(self.value).map_err(|v| v.at_key(&self.variant))
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+    Deserializer::deserialize_seq(self.value, visitor).map_err(|v| v.at_key(&self.variant))
+  }
+
+  fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+    Deserializer::deserialize_map(self.value, visitor).map_err(|v| v.at_key(&self.variant))
+  }
+}
\ No newline at end of file
@@ -0,0 +1,101 @@
+//! Helpers for hashing and reading artifact files, shared by the providers
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Algorithm of the digest of an artifact that is a file, the SHA-256 of its contents
+pub const FILE_DIGEST: &str = "sha256";
+/// Algorithm of the digest of an artifact that is a directory, the SHA-256 of the `sha256sum` listing of its files
+pub const TREE_DIGEST: &str = "tree-sha256";
+
+/// Formats bytes as lowercase hex
+pub fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+/// The SHA-256 digest of `bytes` in hex
+pub fn sha256_hex(bytes: &[u8]) -> String {
+  hex(ring::digest::digest(&ring::digest::SHA256, bytes).as_ref())
+}
+
+/// The SHA-256 digest of a file in hex, and its size. The file is read in chunks.
+pub fn sha256_file(path: &Path) -> io::Result<(String, u64)> {
+  let mut file = File::open(path)?;
+  let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+  let mut buf = vec![0u8; CHUNK_SIZE];
+  let mut size = 0;
+  loop {
+    let n = file.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    ctx.update(&buf[..n]);
+    size += n as u64;
+  }
+  Ok((hex(ctx.finish().as_ref()), size))
+}
+
+/// Reads until `buf` is full or the reader is exhausted, so that every chunk but the last has the same size
+pub fn read_chunk(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    match reader.read(&mut buf[filled..])? {
+      0 => break,
+      n => filled += n
+    }
+  }
+  Ok(filled)
+}
+
+/// Lists the files of a directory recursively, by their `/`-separated path within it
+pub fn list_files(dir: &Path) -> io::Result<BTreeMap<String, PathBuf>> {
+  fn walk(dir: &Path, prefix: &str, files: &mut BTreeMap<String, PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+      let entry = entry?;
+      let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+      if entry.file_type()?.is_dir() {
+        walk(&entry.path(), &format!("{}/", path), files)?;
+      } else {
+        files.insert(path, entry.path());
+      }
+    }
+    Ok(())
+  }
+
+  let mut files = BTreeMap::new();
+  walk(dir, "", &mut files)?;
+  Ok(files)
+}
+
+/// Formats the hex SHA-256 digests of files by name in the format of `sha256sum`
+pub fn format_sums(sums: &BTreeMap<String, String>) -> String {
+  sums.iter().map(|(name, digest)| format!("{}  {}\n", digest, name)).collect()
+}
+
+/// The digest of a directory from the hex SHA-256 digests of its files, such as `tree-sha256:9f86d0...`
+pub fn tree_digest(sums: &BTreeMap<String, String>) -> String {
+  format!("{}:{}", TREE_DIGEST, sha256_hex(format_sums(sums).as_bytes()))
+}
+
+/// The digest of an artifact, `sha256:<hex>` for a file or `tree-sha256:<hex>` for a directory
+pub fn artifact_digest(path: &Path) -> io::Result<String> {
+  if !path.is_dir() {
+    return Ok(format!("{}:{}", FILE_DIGEST, sha256_file(path)?.0));
+  }
+  let sums = list_files(path)?.into_iter()
+    .map(|(name, path)| Ok((name, sha256_file(&path)?.0)))
+    .collect::<io::Result<BTreeMap<_, _>>>()?;
+  Ok(tree_digest(&sums))
+}
+
+/// Splits an artifact digest into its algorithm and hex digest. `None` if it is not a valid artifact digest.
+pub fn parse_digest(digest: &str) -> Option<(&str, &str)> {
+  let (algorithm, hex) = digest.split_once(':')?;
+  let valid = (algorithm == FILE_DIGEST || algorithm == TREE_DIGEST)
+    && hex.len() == 64 && hex.bytes().all(|v| matches!(v, b'0'..=b'9' | b'a'..=b'f'));
+  valid.then_some((algorithm, hex))
+}
\ No newline at end of file
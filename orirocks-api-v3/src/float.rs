@@ -18,9 +18,9 @@ impl CmpFloat {
   }
 }
 
-impl Into<f64> for CmpFloat {
-  fn into(self) -> f64 {
-    self.inner
+impl From<CmpFloat> for f64 {
+  fn from(f: CmpFloat) -> Self {
+    f.inner
   }
 }
 
@@ -28,9 +28,7 @@ impl PartialEq<Self> for CmpFloat {
   fn eq(&self, other: &Self) -> bool {
     if self.inner.is_nan() && other.inner.is_nan() {
       true
-    } else if self.inner.is_nan() {
-      false
-    } else if other.inner.is_nan() {
+    } else if self.inner.is_nan() || other.inner.is_nan() {
       false
     } else {
       self.inner.eq(&other.inner)
@@ -42,7 +40,13 @@ impl Eq for CmpFloat { }
 
 impl PartialOrd for CmpFloat {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-    Some(if self.inner.is_nan() && other.inner.is_nan() {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for CmpFloat {
+  fn cmp(&self, other: &Self) -> Ordering {
+    if self.inner.is_nan() && other.inner.is_nan() {
       Ordering::Equal
     } else if self.inner.is_nan() {
       Ordering::Less
@@ -50,13 +54,7 @@ impl PartialOrd for CmpFloat {
       Ordering::Greater
     } else {
       self.inner.partial_cmp(&other.inner).unwrap()
-    })
-  }
-}
-
-impl Ord for CmpFloat {
-  fn cmp(&self, other: &Self) -> Ordering {
-    self.partial_cmp(other).unwrap()
+    }
   }
 }
 
@@ -1,13 +1,28 @@
 mod float;
+mod context;
+mod value;
+mod de;
+mod check;
+mod resource;
+pub mod digest;
 
 pub use crate::float::CmpFloat;
+pub use crate::context::{CancellationToken, Context, LogLevel, Progress, Reporter, Tag};
+pub use crate::value::{OptionsExt, ValueError};
+pub use crate::de::{from_options, from_value};
+pub use crate::resource::split_resource;
 
 use std::collections::{BTreeMap, HashMap};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+/// A value in a document.
+/// Deserialization maps YAML nodes by their resolved type rather than by trying each variant in turn:
+/// `~`, `null` and empty values are `Null`, quoted scalars and scalars tagged `!!str` are always `String`s,
+/// and numbers without a fractional part or exponent are `Integer`s.
+#[derive(Serialize, Clone, Debug, Eq, PartialEq, Hash)]
 #[serde(untagged)]
 pub enum Value {
+  Null,
   Bool(bool),
   Integer(i64),
   Float(CmpFloat),
@@ -30,36 +45,76 @@ pub enum ValueType {
   #[serde(rename = "array")]
   Array { inner: Box<ValueType> },
   #[serde(rename = "dict")]
-  Dict { inner: Box<ValueType> }
+  Dict { inner: Box<ValueType> },
+  /// A value that may be left out
+  #[serde(rename = "optional")]
+  Optional { inner: Box<ValueType> },
+  /// A string that must be one of `values`
+  #[serde(rename = "enum")]
+  Enum { values: Vec<String> },
+  /// A dict with a fixed set of named, typed fields. Fields of type `optional` may be left out.
+  #[serde(rename = "record")]
+  Record { fields: BTreeMap<String, ValueType> },
+  /// A string that is a resource location, such as `src:assets/script.js`.
+  /// Values of this type are tracked as dependencies of the build.
+  #[serde(rename = "path")]
+  Path
 }
 
 /// Represents an object that can construct Environments
 pub trait EnvironmentProvider {
   /// Retrieves the name of the environment provider
   fn name(&self) -> &str;
+  /// Describes the options accepted by `create`, usually as a `ValueType::Record`.
+  /// If `None` is returned, options are not checked.
+  fn options_schema(&self) -> Option<ValueType> {
+    None
+  }
+  /// Describes the options accepted by the action `name`. If `None` is returned, options are not checked.
+  fn action_schema(&self, _name: &str) -> Option<ValueType> {
+    None
+  }
+  /// Checks what `options_schema` cannot describe, such as the range of a number. Called before anything is built,
+  /// and by `orirocks check`, with options that match the schema.
+  fn check_options(&self, _options: &HashMap<String, Value>) -> Result<(), String> {
+    Ok(())
+  }
+  /// Whether environments of this provider can run the action `name`.
+  /// Steps with unsupported actions are reported before anything is built.
+  fn supports_action(&self, _name: &str) -> bool {
+    true
+  }
   /// Constructs an environment from this provider.
   /// `base` is a base image that is recieved from the previous environment provider
   /// `dependencies` is a mapping from resource locations to real filepaths.
   /// This ensures that if a plugin step depends on anything, it is declared here to aid dependency resolution.
   /// `options` is a plugin-defined set of options.
-  fn create(&self, base: String, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String>;
+  /// `ctx` is used to report logs and progress back to the host, and to check for cancellation.
+  fn create(&self, ctx: &Context, base: String, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String>;
 }
 
 /// Represents an Environment provided by an EnvironmentProvider
 pub trait Environment {
   /// Performs an action in this environment.
   /// `name` and `options` specify the name of the actions and plugin-defined options.
-  fn action(&mut self, name: &str, options: HashMap<String, Value>) -> Result<(), String>;
+  fn action(&mut self, ctx: &Context, name: &str, options: HashMap<String, Value>) -> Result<(), String>;
   /// Finish executing this environment and clean it up.
   /// `path` is the filepath in which to save the result.
-  fn finish(self, path: &str) -> Result<(), String>;
+  /// If the build is aborted, the environment is dropped instead, and should clean up after itself.
+  fn finish(self: Box<Self>, ctx: &Context, path: &str) -> Result<(), String>;
 }
 
 /// Represents a possible method of deployment defined in this plugin
 pub trait DeploymentProvider {
   /// Returns the name of the deployment provider
   fn name(&self) -> &str;
+  /// Describes the options accepted by `deploy`, usually as a `ValueType::Record`.
+  /// If `None` is returned, options are not checked.
+  fn options_schema(&self) -> Option<ValueType> {
+    None
+  }
   /// Executes a deployment. `dependencies` is the same as the parameter in `EnvironmentProvider`,
-  /// and `options` is a plugin-defined set of options.
-  fn deploy(&self, dependencies: HashMap<String, String>, options: HashMap<String, String>) -> Result<(), String>;
+  /// mapping `artifact:name` to the built artifacts as well as resources referenced by the options.
+  /// `options` is a plugin-defined set of options, like the options of `EnvironmentProvider::create`.
+  fn deploy(&self, ctx: &Context, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<(), String>;
 }
\ No newline at end of file
@@ -0,0 +1,10 @@
+/// Splits a resource location such as `src:assets/script.js` into its scheme and path.
+/// Schemes consist of the characters `[a-zA-Z0-9_]`. The `src` scheme refers to files in the project.
+pub fn split_resource(location: &str) -> Option<(&str, &str)> {
+  let (scheme, path) = location.split_once(':')?;
+  if !scheme.is_empty() && scheme.chars().all(|v| v.is_ascii_alphanumeric() || v == '_') {
+    Some((scheme, path))
+  } else {
+    None
+  }
+}
\ No newline at end of file
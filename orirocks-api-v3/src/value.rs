@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+use crate::{CmpFloat, Value};
+use crate::de::from_value;
+
+/// An error produced when a `Value` does not have the expected shape
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValueError {
+  /// Path to the offending value, such as `tags.name` or `disks[2]`. Empty if it is the root value.
+  pub path: String,
+  pub message: String
+}
+
+impl ValueError {
+  pub fn new(message: impl Into<String>) -> Self {
+    ValueError {
+      path: String::new(),
+      message: message.into()
+    }
+  }
+
+  pub fn type_mismatch(expected: &str, found: &Value) -> Self {
+    Self::new(format!("expected {}, found {}", expected, found.type_name()))
+  }
+
+  pub fn missing(key: &str) -> Self {
+    Self::new("missing required value").at_key(key)
+  }
+
+  /// Prefixes the path of this error with a dictionary key
+  pub fn at_key(mut self, key: &str) -> Self {
+    self.path = if self.path.is_empty() {
+      key.to_string()
+    } else if self.path.starts_with('[') {
+      format!("{}{}", key, self.path)
+    } else {
+      format!("{}.{}", key, self.path)
+    };
+    self
+  }
+
+  /// Prefixes the path of this error with an array index
+  pub fn at_index(mut self, index: usize) -> Self {
+    self.path = if self.path.is_empty() || self.path.starts_with('[') {
+      format!("[{}]{}", index, self.path)
+    } else {
+      format!("[{}].{}", index, self.path)
+    };
+    self
+  }
+}
+
+impl Display for ValueError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    if self.path.is_empty() {
+      f.write_str(&self.message)
+    } else {
+      write!(f, "`{}`: {}", self.path, self.message)
+    }
+  }
+}
+
+impl std::error::Error for ValueError { }
+
+/// Allows plugins to use `?` on `ValueError`s, since the plugin API reports errors as strings
+impl From<ValueError> for String {
+  fn from(err: ValueError) -> Self {
+    err.to_string()
+  }
+}
+
+impl Value {
+  /// Returns a human-readable name for the type of this value
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::Null => "null",
+      Value::Bool(_) => "bool",
+      Value::Integer(_) => "integer",
+      Value::Float(_) => "float",
+      Value::Array(_) => "array",
+      Value::String(_) => "string",
+      Value::Dict(_) => "dict"
+    }
+  }
+
+  pub fn is_null(&self) -> bool {
+    matches!(self, Value::Null)
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Value::Bool(v) => Some(*v),
+      _ => None
+    }
+  }
+
+  pub fn as_int(&self) -> Option<i64> {
+    match self {
+      Value::Integer(v) => Some(*v),
+      _ => None
+    }
+  }
+
+  /// Returns the value as a float. Integers are converted.
+  pub fn as_float(&self) -> Option<f64> {
+    match self {
+      Value::Integer(v) => Some(*v as f64),
+      Value::Float(v) => Some(v.inner),
+      _ => None
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::String(v) => Some(v),
+      _ => None
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Value]> {
+    match self {
+      Value::Array(v) => Some(v),
+      _ => None
+    }
+  }
+
+  pub fn as_dict(&self) -> Option<&BTreeMap<String, Value>> {
+    match self {
+      Value::Dict(v) => Some(v),
+      _ => None
+    }
+  }
+}
+
+macro_rules! impl_from {
+  ($($ty:ty => $variant:ident($conv:expr)),* $(,)?) => {
+    $(
+      impl From<$ty> for Value {
+        fn from(v: $ty) -> Self {
+          Value::$variant($conv(v))
+        }
+      }
+    )*
+  };
+}
+
+impl_from! {
+  bool => Bool(|v| v),
+  i64 => Integer(|v| v),
+  i32 => Integer(i64::from),
+  u32 => Integer(i64::from),
+  f64 => Float(CmpFloat::new),
+  CmpFloat => Float(|v| v),
+  String => String(|v| v),
+  &str => String(String::from),
+  PathBuf => String(|v: PathBuf| v.to_string_lossy().into_owned()),
+  &Path => String(|v: &Path| v.to_string_lossy().into_owned()),
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+  fn from(v: Option<T>) -> Self {
+    v.map(Into::into).unwrap_or(Value::Null)
+  }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+  fn from(v: Vec<T>) -> Self {
+    Value::Array(v.into_iter().map(Into::into).collect())
+  }
+}
+
+impl<T: Into<Value>> From<BTreeMap<String, T>> for Value {
+  fn from(v: BTreeMap<String, T>) -> Self {
+    Value::Dict(v.into_iter().map(|(k, v)| (k, v.into())).collect())
+  }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+  fn from(v: HashMap<String, T>) -> Self {
+    Value::Dict(v.into_iter().map(|(k, v)| (k, v.into())).collect())
+  }
+}
+
+impl TryFrom<Value> for bool {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    v.as_bool().ok_or_else(|| ValueError::type_mismatch("bool", &v))
+  }
+}
+
+impl TryFrom<Value> for i64 {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    v.as_int().ok_or_else(|| ValueError::type_mismatch("integer", &v))
+  }
+}
+
+macro_rules! impl_try_from_int {
+  ($($ty:ty),*) => {
+    $(
+      impl TryFrom<Value> for $ty {
+        type Error = ValueError;
+
+        fn try_from(v: Value) -> Result<Self, Self::Error> {
+          let int = i64::try_from(v)?;
+          <$ty>::try_from(int)
+            .map_err(|_| ValueError::new(format!("integer {} is out of range for {}", int, stringify!($ty))))
+        }
+      }
+    )*
+  };
+}
+
+impl_try_from_int!(i32, u32, u64, usize);
+
+impl TryFrom<Value> for f64 {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    v.as_float().ok_or_else(|| ValueError::type_mismatch("float", &v))
+  }
+}
+
+impl TryFrom<Value> for String {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    match v {
+      Value::String(v) => Ok(v),
+      v => Err(ValueError::type_mismatch("string", &v))
+    }
+  }
+}
+
+impl TryFrom<Value> for PathBuf {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    String::try_from(v).map(PathBuf::from)
+  }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    match v {
+      Value::Array(v) => Ok(v),
+      v => Err(ValueError::type_mismatch("array", &v))
+    }
+  }
+}
+
+impl TryFrom<Value> for BTreeMap<String, Value> {
+  type Error = ValueError;
+
+  fn try_from(v: Value) -> Result<Self, Self::Error> {
+    match v {
+      Value::Dict(v) => Ok(v),
+      v => Err(ValueError::type_mismatch("dict", &v))
+    }
+  }
+}
+
+/// Typed accessors for plugin options. Errors name the option that was missing or mistyped.
+pub trait OptionsExt {
+  fn get_value(&self, key: &str) -> Option<&Value>;
+
+  fn get_required(&self, key: &str) -> Result<&Value, ValueError> {
+    self.get_value(key).ok_or_else(|| ValueError::missing(key))
+  }
+
+  fn get_str(&self, key: &str) -> Result<&str, ValueError> {
+    let v = self.get_required(key)?;
+    v.as_str().ok_or_else(|| ValueError::type_mismatch("string", v).at_key(key))
+  }
+
+  fn get_int(&self, key: &str) -> Result<i64, ValueError> {
+    let v = self.get_required(key)?;
+    v.as_int().ok_or_else(|| ValueError::type_mismatch("integer", v).at_key(key))
+  }
+
+  fn get_float(&self, key: &str) -> Result<f64, ValueError> {
+    let v = self.get_required(key)?;
+    v.as_float().ok_or_else(|| ValueError::type_mismatch("float", v).at_key(key))
+  }
+
+  fn get_bool(&self, key: &str) -> Result<bool, ValueError> {
+    let v = self.get_required(key)?;
+    v.as_bool().ok_or_else(|| ValueError::type_mismatch("bool", v).at_key(key))
+  }
+
+  fn get_path(&self, key: &str) -> Result<PathBuf, ValueError> {
+    self.get_str(key).map(PathBuf::from)
+  }
+
+  fn get_array(&self, key: &str) -> Result<&[Value], ValueError> {
+    let v = self.get_required(key)?;
+    v.as_array().ok_or_else(|| ValueError::type_mismatch("array", v).at_key(key))
+  }
+
+  fn get_dict(&self, key: &str) -> Result<&BTreeMap<String, Value>, ValueError> {
+    let v = self.get_required(key)?;
+    v.as_dict().ok_or_else(|| ValueError::type_mismatch("dict", v).at_key(key))
+  }
+
+  /// Deserializes an option into any type, such as `Vec<String>` or a plugin-defined struct
+  fn get_as<T: DeserializeOwned>(&self, key: &str) -> Result<T, ValueError> {
+    from_value(self.get_required(key)?.clone()).map_err(|v| v.at_key(key))
+  }
+
+  /// Like `get_as`, but returns `None` if the option is not present or null
+  fn get_opt<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ValueError> {
+    self.get_value(key)
+      .filter(|v| !v.is_null())
+      .map(|v| from_value(v.clone()).map_err(|v| v.at_key(key)))
+      .transpose()
+  }
+}
+
+impl OptionsExt for HashMap<String, Value> {
+  fn get_value(&self, key: &str) -> Option<&Value> {
+    self.get(key)
+  }
+}
+
+impl OptionsExt for BTreeMap<String, Value> {
+  fn get_value(&self, key: &str) -> Option<&Value> {
+    self.get(key)
+  }
+}
\ No newline at end of file
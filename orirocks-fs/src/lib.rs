@@ -0,0 +1,387 @@
+//! A deployment provider that publishes artifacts into a directory tree, such as a local directory or an NFS share.
+//!
+//! ```yaml
+//! !deploy
+//!   name: share
+//!   provider: directory
+//!   artifacts: [app]
+//!   path: /mnt/images
+//!   layout: "{artifact}/{digest}/{file}"
+//!   retention: 3
+//! ```
+//!
+//! `layout` is a path relative to `path`, `{artifact}/{digest}/{file}` by default, with these placeholders:
+//! - `{deployment}` and `{artifact}`: names of the deployment and of the artifact
+//! - `{digest}` and `{short_digest}`: the hex part of the digest of the artifact, the same as in the artifact store,
+//!   and its first 12 characters. For an artifact that is a directory, this is the SHA-256 of its `SHA256SUMS`.
+//! - `{file}`: path of a file within the artifact, which must be in the last component.
+//!   An artifact that is a single file is named after the artifact.
+//!
+//! The directory that receives the files of an artifact is a version of it. A new version is staged in a temporary
+//! directory next to it and renamed into place, so readers never see a partial version. Files of a version that already
+//! exists, such as one shared with other artifacts, are copied to temporary files and renamed into place one by one,
+//! leaving files that are already in place alone. Every version has a `SHA256SUMS` in the format of `sha256sum` listing
+//! the files it contains, and records when it was last deployed in `.orirocks-deployed`.
+//! With `retention`, only that many versions of every artifact are kept: the one just deployed, and the most recently
+//! deployed others. This needs a layout whose last directory contains a digest and whose version directory contains
+//! `{artifact}`, like the default, so that versions of different artifacts are never mistaken for each other.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use orirocks_api_v3::{Context, DeploymentProvider, OptionsExt, Progress, Value, ValueType};
+use orirocks_api_v3::digest::{format_sums, list_files, sha256_file, tree_digest, FILE_DIGEST};
+
+const DEFAULT_LAYOUT: &str = "{artifact}/{digest}/{file}";
+/// Name of the checksum manifest written to every version
+pub const CHECKSUM_FILE: &str = "SHA256SUMS";
+/// Name of the file that records when a version was last deployed, in nanoseconds since the Unix epoch
+pub const DEPLOYED_FILE: &str = ".orirocks-deployed";
+const DIGEST_LEN: usize = 64;
+const SHORT_DIGEST_LEN: usize = 12;
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default, Debug, Clone)]
+pub struct DirectoryDeploymentProvider;
+
+impl DeploymentProvider for DirectoryDeploymentProvider {
+  fn name(&self) -> &str {
+    "directory"
+  }
+
+  fn options_schema(&self) -> Option<ValueType> {
+    let optional = |v| ValueType::Optional { inner: Box::new(v) };
+    Some(ValueType::Record {
+      fields: BTreeMap::from([
+        ("path".into(), ValueType::String),
+        ("layout".into(), optional(ValueType::String)),
+        ("retention".into(), optional(ValueType::Integer))
+      ])
+    })
+  }
+
+  fn deploy(&self, ctx: &Context, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<(), String> {
+    let root = options.get_path("path")?;
+    let layout = Layout::parse(options.get_opt::<String>("layout")?.as_deref().unwrap_or(DEFAULT_LAYOUT))
+      .map_err(|v| format!("`layout`: {}", v))?;
+    let retention = options.get_opt::<i64>("retention")?;
+    if let Some(keep) = retention {
+      if keep < 1 {
+        return Err("`retention`: at least one version must be kept".into());
+      }
+      if !layout.is_versioned() {
+        return Err("`retention`: the last directory of the layout must contain `{digest}` or `{short_digest}`".into());
+      }
+      if !layout.names_artifact() {
+        return Err("`retention`: the directories of the layout must contain `{artifact}`".into());
+      }
+    }
+    let mut artifacts = dependencies.iter()
+      .filter_map(|(k, v)| Some((k.strip_prefix("artifact:")?, Path::new(v))))
+      .collect::<Vec<_>>();
+    artifacts.sort();
+    for (artifact, path) in artifacts {
+      ctx.check_cancelled()?;
+      let version = publish(ctx, &root, &layout, artifact, path)?;
+      if let Some(keep) = retention {
+        prune(ctx, &layout, artifact, &version, keep as usize)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+  Literal(String),
+  Deployment,
+  Artifact,
+  Digest,
+  ShortDigest,
+  File
+}
+
+/// Values of the placeholders of a layout, other than `{file}`
+struct Vars<'a> {
+  deployment: &'a str,
+  artifact: &'a str,
+  digest: &'a str
+}
+
+/// A parsed `layout` option. The last component names files, the others name the version directory.
+#[derive(Clone, Debug)]
+struct Layout {
+  dirs: Vec<Vec<Segment>>,
+  file: Vec<Segment>
+}
+
+impl Layout {
+  fn parse(layout: &str) -> Result<Layout, String> {
+    let mut components = layout.split('/')
+      .map(|component| match component {
+        "" | "." | ".." => Err(format!("`{}` must be a relative path without empty, `.` or `..` components", layout)),
+        _ => Self::parse_component(component)
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let file = components.pop().unwrap();
+    if !file.contains(&Segment::File) || components.iter().flatten().any(|v| *v == Segment::File) {
+      return Err(format!("`{}` must contain `{{file}}` in its last component only", layout));
+    }
+    Ok(Layout { dirs: components, file })
+  }
+
+  fn parse_component(component: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    let mut rest = component;
+    while let Some(start) = rest.find('{') {
+      if start > 0 {
+        segments.push(Segment::Literal(rest[..start].to_string()));
+      }
+      let end = rest[start..].find('}')
+        .ok_or_else(|| format!("unclosed placeholder in `{}`", component))? + start;
+      segments.push(match &rest[start + 1..end] {
+        "deployment" => Segment::Deployment,
+        "artifact" => Segment::Artifact,
+        "digest" => Segment::Digest,
+        "short_digest" => Segment::ShortDigest,
+        "file" => Segment::File,
+        name => return Err(format!("unknown placeholder `{{{}}}`", name))
+      });
+      rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+      segments.push(Segment::Literal(rest.to_string()));
+    }
+    Ok(segments)
+  }
+
+  /// Whether the name of the version directory changes with the contents of the artifact
+  fn is_versioned(&self) -> bool {
+    self.dirs.last().is_some_and(|v| v.iter().any(|v| matches!(v, Segment::Digest | Segment::ShortDigest)))
+  }
+
+  /// Whether the path of the version directory differs between artifacts
+  fn names_artifact(&self) -> bool {
+    self.dirs.iter().flatten().any(|v| *v == Segment::Artifact)
+  }
+
+  fn render(segments: &[Segment], vars: &Vars, file: &str) -> String {
+    segments.iter()
+      .map(|v| match v {
+        Segment::Literal(v) => v,
+        Segment::Deployment => vars.deployment,
+        Segment::Artifact => vars.artifact,
+        Segment::Digest => vars.digest,
+        Segment::ShortDigest => &vars.digest[..SHORT_DIGEST_LEN],
+        Segment::File => file
+      })
+      .collect()
+  }
+
+  /// Path of the version directory, relative to the root
+  fn version(&self, vars: &Vars) -> PathBuf {
+    self.dirs.iter().map(|v| Self::render(v, vars, "")).collect()
+  }
+
+  /// Path of a file of the artifact, relative to the version directory
+  fn file(&self, vars: &Vars, file: &str) -> String {
+    Self::render(&self.file, vars, file)
+  }
+
+  /// Whether `name` is the name of a version directory of the artifact in `vars`, with any digest.
+  /// The digest in `vars` is ignored.
+  fn is_version(&self, vars: &Vars, name: &str) -> bool {
+    let mut rest = name;
+    for segment in self.dirs.last().into_iter().flatten() {
+      let len = match segment {
+        Segment::Digest => DIGEST_LEN,
+        Segment::ShortDigest => SHORT_DIGEST_LEN,
+        v => {
+          let expected = Self::render(std::slice::from_ref(v), vars, "");
+          match rest.strip_prefix(expected.as_str()) {
+            Some(v) => rest = v,
+            None => return false
+          }
+          continue;
+        }
+      };
+      match rest.get(..len) {
+        Some(digest) if digest.bytes().all(|v| v.is_ascii_hexdigit()) => rest = &rest[len..],
+        _ => return false
+      }
+    }
+    rest.is_empty()
+  }
+}
+
+/// A path next to `path` to write to before renaming it into place. Hidden, so that readers can skip it.
+fn temp_path(path: &Path) -> PathBuf {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  path.with_file_name(format!(".{}.tmp-{}-{}", name, process::id(), TEMP_COUNTER.fetch_add(1, Ordering::SeqCst)))
+}
+
+/// Writes a file by writing a temporary file, which is removed on failure, and renaming it to `to`
+fn write_atomic(to: &Path, write: impl FnOnce(&mut File, &Path) -> Result<(), String>) -> Result<(), String> {
+  let temp = temp_path(to);
+  let result = (|| {
+    let mut file = File::create(&temp).map_err(|v| format!("could not create `{}`: {}", temp.display(), v))?;
+    write(&mut file, &temp)?;
+    file.sync_all().map_err(|v| format!("could not write `{}`: {}", temp.display(), v))?;
+    fs::rename(&temp, to).map_err(|v| format!("could not rename `{}` to `{}`: {}", temp.display(), to.display(), v))
+  })();
+  if result.is_err() {
+    let _ = fs::remove_file(&temp);
+  }
+  result
+}
+
+/// Copies a file while reporting progress, keeping its permissions
+fn copy_with_progress(ctx: &Context, from: &Path, to: &Path) -> Result<(), String> {
+  write_atomic(to, |dest, temp| {
+    let mut src = File::open(from).map_err(|v| format!("could not open `{}`: {}", from.display(), v))?;
+    let metadata = src.metadata().map_err(|v| format!("could not read `{}`: {}", from.display(), v))?;
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    let mut done = 0u64;
+    loop {
+      ctx.check_cancelled()?;
+      let n = src.read(&mut buf).map_err(|v| format!("could not read `{}`: {}", from.display(), v))?;
+      if n == 0 {
+        break;
+      }
+      dest.write_all(&buf[..n]).map_err(|v| format!("could not write `{}`: {}", temp.display(), v))?;
+      done += n as u64;
+      ctx.progress(Progress::Bytes { done, total: Some(metadata.len()) });
+    }
+    dest.set_permissions(metadata.permissions()).map_err(|v| format!("could not write `{}`: {}", temp.display(), v))
+  })
+}
+
+/// Whether a file in a version directory was written by the provider rather than published
+fn is_bookkeeping(name: &str) -> bool {
+  name == CHECKSUM_FILE || name == DEPLOYED_FILE || name.rsplit('/').next().is_some_and(|v| v.starts_with('.') && v.contains(".tmp-"))
+}
+
+fn write_checksums(path: &Path, sums: &BTreeMap<String, String>) -> Result<(), String> {
+  write_atomic(path, |file, temp| {
+    file.write_all(format_sums(sums).as_bytes()).map_err(|v| format!("could not write `{}`: {}", temp.display(), v))
+  })
+}
+
+/// Copies the files of an artifact into a new directory, `version`, which must not exist yet
+fn stage_version(ctx: &Context, version: &Path, files: &BTreeMap<String, &Path>, sums: &BTreeMap<String, String>) -> Result<(), String> {
+  let parent = version.parent().unwrap();
+  fs::create_dir_all(parent).map_err(|v| format!("could not create `{}`: {}", parent.display(), v))?;
+  let staging = temp_path(version);
+  let result = (|| {
+    for (file, src) in files {
+      ctx.check_cancelled()?;
+      let dest = staging.join(file);
+      fs::create_dir_all(dest.parent().unwrap())
+        .map_err(|v| format!("could not create `{}`: {}", dest.parent().unwrap().display(), v))?;
+      copy_with_progress(ctx, src, &dest)?;
+    }
+    write_checksums(&staging.join(CHECKSUM_FILE), sums)?;
+    fs::rename(&staging, version).map_err(|v| format!("could not rename `{}` to `{}`: {}", staging.display(), version.display(), v))
+  })();
+  if result.is_err() {
+    let _ = fs::remove_dir_all(&staging);
+  }
+  result
+}
+
+/// Copies the files of an artifact into an existing directory, `version`, and lists every file it now contains in its `SHA256SUMS`
+fn update_version(ctx: &Context, version: &Path, files: &BTreeMap<String, &Path>, sums: &BTreeMap<String, String>) -> Result<(), String> {
+  for (file, src) in files {
+    ctx.check_cancelled()?;
+    let dest = version.join(file);
+    if dest.is_file() && sha256_file(&dest).ok().map(|v| v.0).as_ref() == Some(&sums[file]) {
+      ctx.debug(&format!("`{}` is up to date", dest.display()));
+      continue;
+    }
+    fs::create_dir_all(dest.parent().unwrap())
+      .map_err(|v| format!("could not create `{}`: {}", dest.parent().unwrap().display(), v))?;
+    copy_with_progress(ctx, src, &dest)?;
+  }
+  let read_error = |v: io::Error| format!("could not read `{}`: {}", version.display(), v);
+  let current = list_files(version).map_err(read_error)?.into_iter()
+    .filter(|(name, _)| !is_bookkeeping(name))
+    .map(|(name, path)| match sums.get(&name) {
+      Some(digest) => Ok((name, digest.clone())),
+      None => Ok((name, sha256_file(&path).map_err(read_error)?.0))
+    })
+    .collect::<Result<BTreeMap<_, _>, String>>()?;
+  write_checksums(&version.join(CHECKSUM_FILE), &current)
+}
+
+/// Publishes the files of an artifact into its version directory, which is returned
+fn publish(ctx: &Context, root: &Path, layout: &Layout, artifact: &str, path: &Path) -> Result<PathBuf, String> {
+  let read_error = |v: io::Error| format!("could not read artifact `{}`: {}", artifact, v);
+  let files = match path.is_dir() {
+    true => list_files(path).map_err(read_error)?,
+    false => BTreeMap::from([(artifact.to_string(), path.to_path_buf())])
+  };
+  let sums = files.iter()
+    .map(|(name, path)| Ok((name.clone(), sha256_file(path)?.0)))
+    .collect::<io::Result<BTreeMap<_, _>>>()
+    .map_err(read_error)?;
+  let digest = match path.is_dir() {
+    true => tree_digest(&sums),
+    false => format!("{}:{}", FILE_DIGEST, sums[artifact])
+  };
+  let digest = digest.split_once(':').unwrap().1;
+  let vars = Vars { deployment: &ctx.tag().artifact, artifact, digest };
+  let version = root.join(layout.version(&vars));
+  ctx.info(&format!("publishing `{}` to `{}`", artifact, version.display()));
+
+  // by their path within the version
+  let published = files.iter().map(|(name, src)| (layout.file(&vars, name), src.as_path())).collect::<BTreeMap<_, _>>();
+  let published_sums = files.keys().map(|name| (layout.file(&vars, name), sums[name].clone())).collect::<BTreeMap<_, _>>();
+  if version.is_dir() {
+    update_version(ctx, &version, &published, &published_sums)?;
+  } else {
+    stage_version(ctx, &version, &published, &published_sums)?;
+  }
+  let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+  write_atomic(&version.join(DEPLOYED_FILE), |file, temp| {
+    file.write_all(now.to_string().as_bytes()).map_err(|v| format!("could not write `{}`: {}", temp.display(), v))
+  })?;
+  Ok(version)
+}
+
+/// When a version was last deployed. Versions published before this was recorded count as the oldest.
+fn deployed_at(version: &Path) -> u128 {
+  fs::read_to_string(version.join(DEPLOYED_FILE)).ok()
+    .and_then(|v| v.trim().parse().ok())
+    .unwrap_or(0)
+}
+
+/// Removes the versions of an artifact beyond the `keep` most recent ones, never removing `version`
+fn prune(ctx: &Context, layout: &Layout, artifact: &str, version: &Path, keep: usize) -> Result<(), String> {
+  let (parent, current) = match (version.parent(), version.file_name()) {
+    (Some(parent), Some(current)) => (parent, current),
+    _ => return Ok(())
+  };
+  let vars = Vars { deployment: &ctx.tag().artifact, artifact, digest: "" };
+  let list_error = |v: io::Error| format!("could not list `{}`: {}", parent.display(), v);
+  let mut others = vec![];
+  for entry in fs::read_dir(parent).map_err(list_error)? {
+    let entry = entry.map_err(list_error)?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if entry.file_name() == current || !entry.file_type().map_err(list_error)?.is_dir() || !layout.is_version(&vars, &name) {
+      continue;
+    }
+    others.push((deployed_at(&entry.path()), entry.path()));
+  }
+  others.sort_by(|a, b| b.cmp(a));
+  for (_, path) in others.into_iter().skip(keep - 1) {
+    ctx.info(&format!("removing old version `{}`", path.display()));
+    fs::remove_dir_all(&path).map_err(|v| format!("could not remove `{}`: {}", path.display(), v))?;
+  }
+  Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,409 @@
+//! A deployment provider that pushes artifacts to an OCI distribution registry.
+//!
+//! ```yaml
+//! !deploy
+//!   name: registry
+//!   provider: oci
+//!   artifacts: [app]
+//!   registry: https://registry.example.com
+//!   repository: images/{name}
+//!   tags: ["1.0-{variant}", "latest-{variant}"]
+//!   username: ${vars.registry_user}
+//!   password: ${vars.registry_password}
+//! ```
+//!
+//! An artifact that is an OCI image layout, a directory with an `oci-layout` file, is pushed with all of its manifests.
+//! Any other file, such as a disk image, is pushed as an artifact manifest in the style of ORAS: a single layer holding
+//! the file, with `artifact_type` as the `artifactType` of the manifest.
+//!
+//! `repository` and `tags` may contain `{name}`, the name of the build, and `{variant}`, the values of the matrix
+//! variant joined by `-`, such as `x86_64` for `app[arch=x86_64]`. `tags` defaults to `latest`.
+//!
+//! Blobs that the registry already has are skipped, others are uploaded in chunks of `chunk_size` bytes.
+//! With `username` and `password`, requests use basic auth, and if the registry asks for a bearer token, one is
+//! requested from the registry's token service with these credentials. A fixed bearer token can be given as `token`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use orirocks_api_v3::{Context, DeploymentProvider, OptionsExt, Progress, Value, ValueType};
+use orirocks_api_v3::digest::{read_chunk, sha256_file, sha256_hex};
+use serde_json::json;
+
+const DEFAULT_ARTIFACT_TYPE: &str = "application/vnd.orirocks.disk.v1";
+const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+const LAYER_MEDIA_TYPE: &str = "application/octet-stream";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+#[derive(Default, Debug, Clone)]
+pub struct OciDeploymentProvider;
+
+impl DeploymentProvider for OciDeploymentProvider {
+  fn name(&self) -> &str {
+    "oci"
+  }
+
+  fn options_schema(&self) -> Option<ValueType> {
+    let optional = |v| ValueType::Optional { inner: Box::new(v) };
+    Some(ValueType::Record {
+      fields: BTreeMap::from([
+        ("registry".into(), ValueType::String),
+        ("repository".into(), ValueType::String),
+        ("tags".into(), optional(ValueType::Array { inner: Box::new(ValueType::String) })),
+        ("artifact_type".into(), optional(ValueType::String)),
+        ("chunk_size".into(), optional(ValueType::Integer)),
+        ("username".into(), optional(ValueType::String)),
+        ("password".into(), optional(ValueType::String)),
+        ("token".into(), optional(ValueType::String))
+      ])
+    })
+  }
+
+  fn deploy(&self, ctx: &Context, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<(), String> {
+    let registry = options.get_str("registry")?;
+    let repository = options.get_str("repository")?;
+    let tags = options.get_opt::<Vec<String>>("tags")?.unwrap_or_else(|| vec!["latest".into()]);
+    let artifact_type = options.get_opt::<String>("artifact_type")?.unwrap_or_else(|| DEFAULT_ARTIFACT_TYPE.into());
+    let chunk_size = match options.get_opt::<i64>("chunk_size")? {
+      Some(size) if size < 1 => return Err("`chunk_size`: must be positive".into()),
+      Some(size) => size as usize,
+      None => DEFAULT_CHUNK_SIZE
+    };
+    let credentials = match (options.get_opt::<String>("username")?, options.get_opt::<String>("password")?) {
+      (Some(username), Some(password)) => Some((username, password)),
+      (None, None) => None,
+      _ => return Err("`username` and `password` must be given together".into())
+    };
+    let mut artifacts = dependencies.iter()
+      .filter_map(|(k, v)| Some((k.strip_prefix("artifact:")?, Path::new(v))))
+      .collect::<Vec<_>>();
+    artifacts.sort();
+    for (artifact, path) in artifacts {
+      ctx.check_cancelled()?;
+      let repository = render(repository, artifact);
+      if !is_repository(&repository) {
+        return Err(format!("`repository`: `{}` is not a valid repository name", repository));
+      }
+      let tags = tags.iter().map(|v| render(v, artifact)).collect::<Vec<_>>();
+      if let Some(tag) = tags.iter().find(|v| !is_tag(v)) {
+        return Err(format!("`tags`: `{}` is not a valid tag", tag));
+      }
+      let mut client = Client {
+        agent: ureq::Agent::new(),
+        base: base_url(registry),
+        repository,
+        credentials: credentials.clone(),
+        token: options.get_opt::<String>("token")?,
+        fixed_token: options.get_value("token").is_some_and(|v| !v.is_null()),
+        chunk_size
+      };
+      ctx.info(&format!("pushing `{}` to `{}/{}`", artifact, client.base, client.repository));
+      if path.join("oci-layout").is_file() {
+        client.push_layout(ctx, path, &tags)?;
+      } else if path.is_dir() {
+        return Err(format!("artifact `{}` is a directory, but not an OCI image layout", artifact));
+      } else {
+        client.push_file(ctx, artifact, path, &artifact_type, &tags)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Substitutes `{name}` and `{variant}` for an artifact such as `app[arch=x86_64]`
+fn render(template: &str, artifact: &str) -> String {
+  let (name, variant) = match artifact.split_once('[') {
+    Some((name, selectors)) => (name, selectors.trim_end_matches(']')
+      .split(',')
+      .map(|v| v.split_once('=').map(|v| v.1).unwrap_or(v))
+      .collect::<Vec<_>>()
+      .join("-")),
+    None => (artifact, String::new())
+  };
+  template.replace("{name}", name).replace("{variant}", &variant)
+}
+
+/// Checks a repository name: path components of lowercase letters and digits, separated by `.`, `_`, `__` or `-`
+fn is_repository(s: &str) -> bool {
+  s.split('/').all(|component| {
+    let bytes = component.as_bytes();
+    !component.is_empty()
+      && component.bytes().all(|v| v.is_ascii_lowercase() || v.is_ascii_digit() || b"._-".contains(&v))
+      && bytes[0].is_ascii_alphanumeric()
+      && bytes[bytes.len() - 1].is_ascii_alphanumeric()
+  })
+}
+
+fn is_tag(s: &str) -> bool {
+  (1..=128).contains(&s.len())
+    && !s.starts_with(['.', '-'])
+    && s.bytes().all(|v| v.is_ascii_alphanumeric() || b"._-".contains(&v))
+}
+
+fn base_url(registry: &str) -> String {
+  let registry = registry.trim_end_matches('/');
+  match registry.contains("://") {
+    true => registry.to_string(),
+    false => format!("https://{}", registry)
+  }
+}
+
+fn digest_bytes(bytes: &[u8]) -> String {
+  format!("sha256:{}", sha256_hex(bytes))
+}
+
+/// Parses the parameters of a `WWW-Authenticate: Bearer` challenge, such as `realm="...",scope="..."`
+fn parse_challenge(header: &str) -> Option<BTreeMap<String, String>> {
+  let (scheme, mut rest) = header.trim().split_once(' ')?;
+  if !scheme.eq_ignore_ascii_case("bearer") {
+    return None;
+  }
+  let mut params = BTreeMap::new();
+  loop {
+    rest = rest.trim_start_matches([' ', ',']);
+    let Some((key, value)) = rest.split_once('=') else {
+      break;
+    };
+    let (value, next) = match value.strip_prefix('"') {
+      Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+      None => value.split_once(',').unwrap_or((value, ""))
+    };
+    params.insert(key.trim().to_ascii_lowercase(), value.to_string());
+    rest = next;
+  }
+  Some(params)
+}
+
+/// A connection to one repository of a registry
+struct Client {
+  agent: ureq::Agent,
+  base: String,
+  repository: String,
+  credentials: Option<(String, String)>,
+  /// Bearer token to send, either given as an option or obtained from the token service
+  token: Option<String>,
+  /// Whether `token` was given as an option, and so must not be replaced
+  fixed_token: bool,
+  chunk_size: usize
+}
+
+impl Client {
+  fn basic_auth(&self) -> Option<String> {
+    self.credentials.as_ref().map(|(user, password)| format!("Basic {}", BASE64.encode(format!("{}:{}", user, password))))
+  }
+
+  fn authorization(&self) -> Option<String> {
+    match &self.token {
+      Some(token) => Some(format!("Bearer {}", token)),
+      None => self.basic_auth()
+    }
+  }
+
+  /// Sends a request, answering a bearer challenge of the registry once
+  fn send(&mut self, method: &str, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response, String> {
+    let mut retried = false;
+    loop {
+      let mut request = self.agent.request(method, url);
+      for (k, v) in headers {
+        request = request.set(k, v);
+      }
+      if let Some(auth) = self.authorization() {
+        request = request.set("Authorization", &auth);
+      }
+      let response = match request.send_bytes(body) {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => return Err(format!("{} `{}` failed: {}", method, url, err))
+      };
+      if response.status() == 401 && !retried && !self.fixed_token {
+        if let Some(challenge) = response.header("WWW-Authenticate").and_then(parse_challenge) {
+          self.token = Some(self.fetch_token(&challenge)?);
+          retried = true;
+          continue;
+        }
+      }
+      return Ok(response);
+    }
+  }
+
+  /// Requests a bearer token from the token service named by a challenge
+  fn fetch_token(&self, challenge: &BTreeMap<String, String>) -> Result<String, String> {
+    let realm = challenge.get("realm").ok_or("the registry asked for a bearer token without naming a `realm`")?;
+    let mut request = self.agent.get(realm);
+    for key in ["service", "scope"] {
+      if let Some(value) = challenge.get(key) {
+        request = request.query(key, value);
+      }
+    }
+    if let Some(auth) = self.basic_auth() {
+      request = request.set("Authorization", &auth);
+    }
+    let response = request.call().map_err(|v| format!("could not get a token from `{}`: {}", realm, v))?;
+    let body = response.into_string().map_err(|v| v.to_string())
+      .and_then(|v| serde_json::from_str::<serde_json::Value>(&v).map_err(|v| v.to_string()))
+      .map_err(|v| format!("could not get a token from `{}`: {}", realm, v))?;
+    body.get("token").or_else(|| body.get("access_token"))
+      .and_then(|v| v.as_str())
+      .map(String::from)
+      .ok_or_else(|| format!("could not get a token from `{}`: the response has no token", realm))
+  }
+
+  fn url(&self, path: &str) -> String {
+    format!("{}/v2/{}/{}", self.base, self.repository, path)
+  }
+
+  /// Resolves the `Location` of an upload, which may be relative to the registry
+  fn location(&self, response: &ureq::Response) -> Result<String, String> {
+    let location = response.header("Location").ok_or("the registry did not return an upload location")?;
+    Ok(match location.starts_with("http://") || location.starts_with("https://") {
+      true => location.to_string(),
+      false => format!("{}/{}", self.base, location.trim_start_matches('/'))
+    })
+  }
+
+  fn expect(response: ureq::Response, status: u16, what: &str) -> Result<ureq::Response, String> {
+    if response.status() == status {
+      return Ok(response);
+    }
+    let code = response.status();
+    let body = response.into_string().unwrap_or_default();
+    Err(format!("could not {}: the registry responded with status {}: {}", what, code, body.trim()))
+  }
+
+  /// Uploads a blob in chunks, unless the registry already has it
+  fn push_blob(&mut self, ctx: &Context, digest: &str, size: u64, reader: &mut dyn Read) -> Result<(), String> {
+    let what = format!("push blob `{}`", digest);
+    let response = self.send("HEAD", &self.url(&format!("blobs/{}", digest)), &[], &[])?;
+    match response.status() {
+      200 => {
+        ctx.debug(&format!("blob `{}` already exists", digest));
+        return Ok(());
+      }
+      404 => {}
+      _ => return Self::expect(response, 404, &what).map(|_| ())
+    }
+    let response = self.send("POST", &self.url("blobs/uploads/"), &[], &[])?;
+    let mut location = self.location(&Self::expect(response, 202, &what)?)?;
+    let mut buf = vec![0u8; self.chunk_size.min(size as usize).max(1)];
+    let mut done = 0u64;
+    loop {
+      ctx.check_cancelled()?;
+      let n = read_chunk(reader, &mut buf).map_err(|v| format!("could not {}: {}", what, v))?;
+      if n == 0 {
+        break;
+      }
+      let range = format!("{}-{}", done, done + n as u64 - 1);
+      let response = self.send("PATCH", &location, &[("Content-Type", "application/octet-stream"), ("Content-Range", &range)], &buf[..n])?;
+      location = self.location(&Self::expect(response, 202, &what)?)?;
+      done += n as u64;
+      ctx.progress(Progress::Bytes { done, total: Some(size) });
+    }
+    let separator = if location.contains('?') { '&' } else { '?' };
+    let response = self.send("PUT", &format!("{}{}digest={}", location, separator, digest), &[], &[])?;
+    Self::expect(response, 201, &what).map(|_| ())
+  }
+
+  fn push_manifest(&mut self, reference: &str, media_type: &str, manifest: &[u8]) -> Result<(), String> {
+    let response = self.send("PUT", &self.url(&format!("manifests/{}", reference)), &[("Content-Type", media_type)], manifest)?;
+    Self::expect(response, 201, &format!("push manifest `{}`", reference)).map(|_| ())
+  }
+
+  /// Pushes a file as the only layer of an artifact manifest
+  fn push_file(&mut self, ctx: &Context, artifact: &str, path: &Path, artifact_type: &str, tags: &[String]) -> Result<(), String> {
+    let (digest, size) = sha256_file(path).map_err(|v| format!("could not read artifact `{}`: {}", artifact, v))?;
+    let digest = format!("sha256:{}", digest);
+    let mut file = File::open(path).map_err(|v| format!("could not read artifact `{}`: {}", artifact, v))?;
+    self.push_blob(ctx, &digest, size, &mut file)?;
+    let config = b"{}";
+    self.push_blob(ctx, &digest_bytes(config), config.len() as u64, &mut Cursor::new(config))?;
+    let manifest = json!({
+      "schemaVersion": 2,
+      "mediaType": MANIFEST_MEDIA_TYPE,
+      "artifactType": artifact_type,
+      "config": { "mediaType": EMPTY_MEDIA_TYPE, "digest": digest_bytes(config), "size": config.len() },
+      "layers": [{
+        "mediaType": LAYER_MEDIA_TYPE,
+        "digest": digest,
+        "size": size,
+        "annotations": { TITLE_ANNOTATION: artifact }
+      }]
+    });
+    let manifest = serde_json::to_vec(&manifest).unwrap();
+    for tag in tags {
+      self.push_manifest(tag, MANIFEST_MEDIA_TYPE, &manifest)?;
+    }
+    Ok(())
+  }
+
+  /// Pushes an OCI image layout. Its index is pushed under the tags, or its manifest if it only has one.
+  fn push_layout(&mut self, ctx: &Context, layout: &Path, tags: &[String]) -> Result<(), String> {
+    let index = read_file(&layout.join("index.json"))?;
+    let manifests = parse_json(&layout.join("index.json"), &index)?["manifests"].as_array().cloned()
+      .ok_or("`index.json` of the image layout has no `manifests`")?;
+    for descriptor in &manifests {
+      self.push_manifest_tree(ctx, layout, descriptor)?;
+    }
+    let (media_type, manifest) = match manifests.as_slice() {
+      [descriptor] => {
+        let (digest, media_type) = descriptor_fields(descriptor)?;
+        (media_type, read_file(&blob_path(layout, digest)?)?)
+      }
+      _ => (INDEX_MEDIA_TYPE, index)
+    };
+    for tag in tags {
+      self.push_manifest(tag, media_type, &manifest)?;
+    }
+    Ok(())
+  }
+
+  /// Pushes the blobs a manifest refers to, the manifests of an index, and then the manifest itself by its digest
+  fn push_manifest_tree(&mut self, ctx: &Context, layout: &Path, descriptor: &serde_json::Value) -> Result<(), String> {
+    let (digest, media_type) = descriptor_fields(descriptor)?;
+    let path = blob_path(layout, digest)?;
+    let bytes = read_file(&path)?;
+    let manifest = parse_json(&path, &bytes)?;
+    if let Some(children) = manifest["manifests"].as_array() {
+      for child in children {
+        self.push_manifest_tree(ctx, layout, child)?;
+      }
+    } else {
+      let blobs = manifest["config"].as_object().into_iter()
+        .chain(manifest["layers"].as_array().into_iter().flatten().filter_map(|v| v.as_object()));
+      for blob in blobs {
+        let digest = blob.get("digest").and_then(|v| v.as_str()).ok_or_else(|| format!("`{}` has a descriptor without `digest`", path.display()))?;
+        let blob_path = blob_path(layout, digest)?;
+        let mut file = File::open(&blob_path).map_err(|v| format!("could not open `{}`: {}", blob_path.display(), v))?;
+        let size = file.metadata().map_err(|v| format!("could not read `{}`: {}", blob_path.display(), v))?.len();
+        self.push_blob(ctx, digest, size, &mut file)?;
+      }
+    }
+    self.push_manifest(digest, media_type, &bytes)
+  }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+  fs::read(path).map_err(|v| format!("could not read `{}`: {}", path.display(), v))
+}
+
+fn parse_json(path: &Path, bytes: &[u8]) -> Result<serde_json::Value, String> {
+  serde_json::from_slice(bytes).map_err(|v| format!("could not parse `{}`: {}", path.display(), v))
+}
+
+fn descriptor_fields(descriptor: &serde_json::Value) -> Result<(&str, &str), String> {
+  let digest = descriptor["digest"].as_str().ok_or("a descriptor of the image layout has no `digest`")?;
+  let media_type = descriptor["mediaType"].as_str().unwrap_or(MANIFEST_MEDIA_TYPE);
+  Ok((digest, media_type))
+}
+
+/// Path of a blob in an image layout, such as `blobs/sha256/9f86d0...`
+fn blob_path(layout: &Path, digest: &str) -> Result<PathBuf, String> {
+  match digest.split_once(':') {
+    Some((algorithm, hex)) if !algorithm.contains(['/', '.']) && !hex.contains(['/', '.']) => Ok(layout.join("blobs").join(algorithm).join(hex)),
+    _ => Err(format!("`{}` is not a valid digest", digest))
+  }
+}
\ No newline at end of file
@@ -1,6 +1,19 @@
-use std::collections::HashMap;
-use orirocks_api_v3::{Environment, EnvironmentProvider, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use orirocks_api_v3::{Context, Environment, EnvironmentProvider, OptionsExt, Progress, Value, ValueType};
 
+/// Size of a blank disk when no base image or `disk_size` is given
+const DEFAULT_DISK_SIZE: u64 = 8 * 1024 * 1024 * 1024;
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+static WORK_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a disk, either blank or copied from the base image, and saves it as the output.
+/// Running actions in a virtual machine is not implemented yet, so artifacts built with it cannot have steps.
 #[derive(Default, Debug, Clone)]
 pub struct QemuEnvironmentProvider;
 
@@ -9,22 +22,100 @@ impl EnvironmentProvider for QemuEnvironmentProvider {
     "qemu"
   }
 
-  fn create(&self, base: String, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String> {
-    todo!()
+  fn options_schema(&self) -> Option<ValueType> {
+    Some(ValueType::Record {
+      fields: BTreeMap::from([
+        ("disk_size".into(), ValueType::Optional { inner: Box::new(ValueType::Integer) })
+      ])
+    })
+  }
+
+  fn check_options(&self, options: &HashMap<String, Value>) -> Result<(), String> {
+    match options.get_opt::<u64>("disk_size")? {
+      Some(0) => Err("`disk_size` must be a positive integer".into()),
+      _ => Ok(())
+    }
+  }
+
+  fn supports_action(&self, _name: &str) -> bool {
+    false
+  }
+
+  fn create(&self, ctx: &Context, base: String, _dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String> {
+    self.check_options(&options)?;
+    let work_dir = std::env::temp_dir().join(format!("orirocks-qemu-{}-{}", process::id(), WORK_DIR_COUNTER.fetch_add(1, Ordering::SeqCst)));
+    fs::create_dir_all(&work_dir).map_err(|v| format!("could not create work directory: {}", v))?;
+    // constructed before the disk is populated so that it is cleaned up on failure
+    let env = QemuEnvironment {
+      disk: work_dir.join("disk.img"),
+      work_dir
+    };
+    if base.is_empty() {
+      let size = options.get_opt::<u64>("disk_size")?.unwrap_or(DEFAULT_DISK_SIZE);
+      ctx.info(&format!("creating blank disk of {} bytes", size));
+      File::create(&env.disk)
+        .and_then(|v| v.set_len(size))
+        .map_err(|v| format!("could not create disk: {}", v))?;
+    } else {
+      ctx.info(&format!("copying base image `{}`", base));
+      copy_with_progress(ctx, Path::new(&base), &env.disk)?;
+    }
+    Ok(Box::new(env))
   }
 }
 
 #[derive(Default, Debug)]
 pub struct QemuEnvironment {
-
+  work_dir: PathBuf,
+  disk: PathBuf
 }
 
 impl Environment for QemuEnvironment {
-  fn action(&mut self, name: &str, options: HashMap<String, Value>) -> Result<(), String> {
-    todo!()
+  fn action(&mut self, ctx: &Context, name: &str, _options: HashMap<String, Value>) -> Result<(), String> {
+    ctx.check_cancelled()?;
+    Err(format!("unsupported action `{}`", name))
+  }
+
+  fn finish(self: Box<Self>, ctx: &Context, out_path: &str) -> Result<(), String> {
+    ctx.check_cancelled()?;
+    if fs::rename(&self.disk, out_path).is_ok() {
+      return Ok(());
+    }
+    ctx.info(&format!("saving disk to `{}`", out_path));
+    copy_with_progress(ctx, &self.disk, Path::new(out_path))
   }
+}
+
+impl Drop for QemuEnvironment {
+  fn drop(&mut self) {
+    if !self.work_dir.as_os_str().is_empty() {
+      let _ = fs::remove_dir_all(&self.work_dir);
+    }
+  }
+}
 
-  fn finish(self, out_path: &str) -> Result<(), String> {
-    todo!()
+/// Copies a file while reporting progress. If the operation is cancelled, the partial copy is removed.
+fn copy_with_progress(ctx: &Context, from: &Path, to: &Path) -> Result<(), String> {
+  let result = (|| {
+    let mut src = File::open(from).map_err(|v| format!("could not open `{}`: {}", from.display(), v))?;
+    let total = src.metadata().map(|v| v.len()).ok();
+    let mut dest = File::create(to).map_err(|v| format!("could not create `{}`: {}", to.display(), v))?;
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    let mut done = 0u64;
+    loop {
+      ctx.check_cancelled()?;
+      let n = src.read(&mut buf).map_err(|v| format!("could not read `{}`: {}", from.display(), v))?;
+      if n == 0 {
+        break;
+      }
+      dest.write_all(&buf[..n]).map_err(|v| format!("could not write `{}`: {}", to.display(), v))?;
+      done += n as u64;
+      ctx.progress(Progress::Bytes { done, total });
+    }
+    Ok(())
+  })();
+  if result.is_err() {
+    let _ = fs::remove_file(to);
   }
+  result
 }
\ No newline at end of file
@@ -0,0 +1,330 @@
+//! A deployment provider that uploads artifacts to S3 or any S3-compatible object store, such as MinIO.
+//!
+//! ```yaml
+//! !deploy
+//!   name: catalog
+//!   provider: s3
+//!   artifacts: [app]
+//!   endpoint: http://minio.internal:9000
+//!   bucket: images
+//!   key: "{artifact}/{short_digest}/{file}"
+//!   access_key: ${vars.s3_access_key}
+//!   secret_key: ${vars.s3_secret_key}
+//!   metadata:
+//!     release: ${vars.release}
+//! ```
+//!
+//! `key` is a template like the `layout` of the `directory` provider, `{artifact}/{digest}/{file}` by default, with the
+//! placeholders `{deployment}`, `{artifact}`, `{digest}`, `{short_digest}` and `{file}`. The digest is the hex SHA-256
+//! of the artifact, or for an artifact that is a directory, of the `sha256sum` listing of its files.
+//!
+//! Every object is uploaded with `Content-MD5` and a signed SHA-256 of its body, so the store rejects corrupted uploads.
+//! Files larger than `part_size`, 64 MiB by default and at least 5 MiB, are uploaded in parts with a multipart upload,
+//! which is aborted if a part fails.
+//! Objects carry the metadata `orirocks-artifact`, `orirocks-deployment`, `orirocks-digest` (the digest of the artifact,
+//! such as `sha256:9f86d0...`, or `tree-sha256:4e07b4...` for a directory),
+//! `orirocks-sha256` (the digest of the object itself) and `orirocks-version` (the version of orirocks that uploaded it),
+//! along with the entries of `metadata`. Objects whose metadata already matches are not uploaded again.
+//!
+//! Credentials default to the environment variables `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+//! Buckets are addressed in the path, as `endpoint/bucket/key`, unless `path_style` is false.
+
+pub mod sigv4;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+use orirocks_api_v3::{Context, DeploymentProvider, OptionsExt, Progress, Value, ValueType};
+use orirocks_api_v3::digest::{list_files, read_chunk, sha256_file, sha256_hex, tree_digest, FILE_DIGEST};
+use time::OffsetDateTime;
+use crate::sigv4::{amz_date, canonical_query, uri_encode, Credentials};
+
+const DEFAULT_KEY: &str = "{artifact}/{digest}/{file}";
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_PART_SIZE: usize = 64 * 1024 * 1024;
+/// S3 rejects smaller parts, except for the last part of an upload
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+/// S3 does not allow more parts in a multipart upload
+const MAX_PARTS: u64 = 10000;
+const SHORT_DIGEST_LEN: usize = 12;
+const META_PREFIX: &str = "x-amz-meta-";
+
+#[derive(Debug, Clone)]
+pub struct S3DeploymentProvider {
+  /// The smallest `part_size` that is accepted
+  pub min_part_size: usize
+}
+
+impl Default for S3DeploymentProvider {
+  fn default() -> Self {
+    S3DeploymentProvider { min_part_size: MIN_PART_SIZE }
+  }
+}
+
+impl DeploymentProvider for S3DeploymentProvider {
+  fn name(&self) -> &str {
+    "s3"
+  }
+
+  fn options_schema(&self) -> Option<ValueType> {
+    let optional = |v| ValueType::Optional { inner: Box::new(v) };
+    Some(ValueType::Record {
+      fields: BTreeMap::from([
+        ("endpoint".into(), ValueType::String),
+        ("bucket".into(), ValueType::String),
+        ("region".into(), optional(ValueType::String)),
+        ("key".into(), optional(ValueType::String)),
+        ("access_key".into(), optional(ValueType::String)),
+        ("secret_key".into(), optional(ValueType::String)),
+        ("path_style".into(), optional(ValueType::Bool)),
+        ("part_size".into(), optional(ValueType::Integer)),
+        ("metadata".into(), optional(ValueType::Dict { inner: Box::new(ValueType::String) }))
+      ])
+    })
+  }
+
+  fn deploy(&self, ctx: &Context, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<(), String> {
+    let key = options.get_opt::<String>("key")?.unwrap_or_else(|| DEFAULT_KEY.into());
+    check_template(&key).map_err(|v| format!("`key`: {}", v))?;
+    let part_size = match options.get_opt::<i64>("part_size")? {
+      Some(size) if size < self.min_part_size as i64 => return Err(format!("`part_size`: must be at least {} bytes", self.min_part_size)),
+      Some(size) => size as usize,
+      None => DEFAULT_PART_SIZE
+    };
+    let metadata = options.get_opt::<BTreeMap<String, String>>("metadata")?.unwrap_or_default();
+    if let Some(name) = metadata.keys().find(|v| v.is_empty() || !v.bytes().all(|v| v.is_ascii_alphanumeric() || v == b'-')) {
+      return Err(format!("`metadata`: `{}` is not a valid metadata name", name));
+    }
+    let credential = |option: &str, var: &str| -> Result<String, String> {
+      match options.get_opt::<String>(option)? {
+        Some(v) => Ok(v),
+        None => std::env::var(var).map_err(|_| format!("`{}` is not set, and neither is `{}`", option, var))
+      }
+    };
+    let client = Client::new(
+      options.get_str("endpoint")?,
+      options.get_str("bucket")?,
+      options.get_opt::<String>("region")?.as_deref().unwrap_or(DEFAULT_REGION),
+      options.get_opt::<bool>("path_style")?.unwrap_or(true),
+      Credentials { access_key: credential("access_key", "AWS_ACCESS_KEY_ID")?, secret_key: credential("secret_key", "AWS_SECRET_ACCESS_KEY")? }
+    );
+    let mut artifacts = dependencies.iter()
+      .filter_map(|(k, v)| Some((k.strip_prefix("artifact:")?, Path::new(v))))
+      .collect::<Vec<_>>();
+    artifacts.sort();
+    for (artifact, path) in artifacts {
+      ctx.check_cancelled()?;
+      let read_error = |v: io::Error| format!("could not read artifact `{}`: {}", artifact, v);
+      if path.is_dir() && !key.contains("{file}") {
+        return Err(format!("`key`: artifact `{}` is a directory, so the key must contain `{{file}}`", artifact));
+      }
+      let files = match path.is_dir() {
+        true => list_files(path).map_err(read_error)?,
+        false => BTreeMap::from([(artifact.to_string(), path.to_path_buf())])
+      };
+      let sums = files.iter()
+        .map(|(name, path)| Ok((name.clone(), sha256_file(path)?.0)))
+        .collect::<io::Result<BTreeMap<_, _>>>()
+        .map_err(read_error)?;
+      let digest = match path.is_dir() {
+        true => tree_digest(&sums),
+        false => format!("{}:{}", FILE_DIGEST, sums[artifact])
+      };
+      let hex = digest.split_once(':').unwrap().1;
+      for (name, path) in &files {
+        ctx.check_cancelled()?;
+        let object_key = render(&key, &ctx.tag().artifact, artifact, hex, name);
+        let mut object_metadata = metadata.clone();
+        object_metadata.insert("orirocks-artifact".into(), artifact.into());
+        object_metadata.insert("orirocks-deployment".into(), ctx.tag().artifact.clone());
+        object_metadata.insert("orirocks-digest".into(), digest.clone());
+        object_metadata.insert("orirocks-sha256".into(), sums[name].clone());
+        object_metadata.insert("orirocks-version".into(), env!("CARGO_PKG_VERSION").into());
+        client.upload(ctx, &object_key, path, part_size, &object_metadata)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Checks that a key template only uses known placeholders
+fn check_template(template: &str) -> Result<(), String> {
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    let end = rest[start..].find('}').ok_or_else(|| format!("unclosed placeholder in `{}`", template))? + start;
+    match &rest[start + 1..end] {
+      "deployment" | "artifact" | "digest" | "short_digest" | "file" => {}
+      name => return Err(format!("unknown placeholder `{{{}}}`", name))
+    }
+    rest = &rest[end + 1..];
+  }
+  Ok(())
+}
+
+fn render(template: &str, deployment: &str, artifact: &str, digest: &str, file: &str) -> String {
+  template
+    .replace("{deployment}", deployment)
+    .replace("{artifact}", artifact)
+    .replace("{short_digest}", &digest[..SHORT_DIGEST_LEN])
+    .replace("{digest}", digest)
+    .replace("{file}", file)
+}
+
+/// Extracts the text of the first element named `tag` from an XML response
+fn xml_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+  let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+  let end = xml[start..].find(&format!("</{}>", tag))? + start;
+  Some(&xml[start..end])
+}
+
+struct Client {
+  agent: ureq::Agent,
+  /// Scheme of the endpoint, such as `https://`
+  scheme: String,
+  /// Host and port of the endpoint
+  host: String,
+  bucket: String,
+  region: String,
+  path_style: bool,
+  credentials: Credentials
+}
+
+impl Client {
+  fn new(endpoint: &str, bucket: &str, region: &str, path_style: bool, credentials: Credentials) -> Client {
+    let (scheme, host) = match endpoint.split_once("://") {
+      Some((scheme, host)) => (format!("{}://", scheme), host),
+      None => ("https://".to_string(), endpoint)
+    };
+    Client {
+      agent: ureq::Agent::new(),
+      scheme,
+      host: host.trim_end_matches('/').to_string(),
+      bucket: bucket.to_string(),
+      region: region.to_string(),
+      path_style,
+      credentials
+    }
+  }
+
+  /// Sends a signed request for an object. `headers` are signed along with the required ones.
+  fn send(&self, method: &str, key: &str, query: &[(&str, &str)], headers: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response, String> {
+    let (host, uri) = match self.path_style {
+      true => (self.host.clone(), format!("/{}/{}", uri_encode(&self.bucket, true), uri_encode(key, false))),
+      false => (format!("{}.{}", self.bucket, self.host), format!("/{}", uri_encode(key, false)))
+    };
+    let query = canonical_query(query);
+    let time = OffsetDateTime::now_utc();
+    let payload_hash = sha256_hex(body);
+    let mut signed = headers.iter().map(|(k, v)| (k.to_ascii_lowercase(), v.to_string())).collect::<BTreeMap<_, _>>();
+    signed.insert("host".into(), host.clone());
+    signed.insert("x-amz-content-sha256".into(), payload_hash.clone());
+    signed.insert("x-amz-date".into(), amz_date(&time));
+    let request = sigv4::Request { method, uri: &uri, query: &query, headers: &signed, payload_hash: &payload_hash };
+    let authorization = sigv4::authorization(&self.credentials, &self.region, &request, &time);
+
+    let url = match query.is_empty() {
+      true => format!("{}{}{}", self.scheme, host, uri),
+      false => format!("{}{}{}?{}", self.scheme, host, uri, query)
+    };
+    let mut request = self.agent.request(method, &url).set("Authorization", &authorization);
+    for (k, v) in &signed {
+      request = request.set(k, v);
+    }
+    match request.send_bytes(body) {
+      Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+      Err(err) => Err(format!("{} `{}` failed: {}", method, url, err))
+    }
+  }
+
+  fn expect(response: ureq::Response, what: &str) -> Result<ureq::Response, String> {
+    if (200..300).contains(&response.status()) {
+      return Ok(response);
+    }
+    let status = response.status();
+    let body = response.into_string().unwrap_or_default();
+    let message = xml_element(&body, "Message").or_else(|| xml_element(&body, "Code")).unwrap_or(body.trim());
+    Err(format!("could not {}: the store responded with status {}: {}", what, status, message))
+  }
+
+  /// Headers that carry the checksums and metadata of an object
+  fn object_headers(metadata: &BTreeMap<String, String>) -> Vec<(String, String)> {
+    let mut headers = vec![("content-type".to_string(), "application/octet-stream".to_string())];
+    headers.extend(metadata.iter().map(|(k, v)| (format!("{}{}", META_PREFIX, k.to_ascii_lowercase()), v.clone())));
+    headers
+  }
+
+  fn upload(&self, ctx: &Context, key: &str, path: &Path, part_size: usize, metadata: &BTreeMap<String, String>) -> Result<(), String> {
+    let what = format!("upload `{}`", key);
+    let response = self.send("HEAD", key, &[], &[], &[])?;
+    let headers = Self::object_headers(metadata);
+    if response.status() == 200 && headers.iter().filter(|(k, _)| k.starts_with(META_PREFIX)).all(|(k, v)| response.header(k) == Some(v.as_str())) {
+      ctx.info(&format!("`{}` is up to date", key));
+      return Ok(());
+    }
+    let mut file = File::open(path).map_err(|v| format!("could not open `{}`: {}", path.display(), v))?;
+    let size = file.metadata().map_err(|v| format!("could not read `{}`: {}", path.display(), v))?.len();
+    let headers = headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>();
+    ctx.info(&format!("uploading `{}`", key));
+    if size <= part_size as u64 {
+      let mut body = vec![];
+      file.read_to_end(&mut body).map_err(|v| format!("could not read `{}`: {}", path.display(), v))?;
+      let content_md5 = BASE64.encode(Md5::digest(&body));
+      let mut headers = headers.clone();
+      headers.push(("content-md5", &content_md5));
+      Self::expect(self.send("PUT", key, &[], &headers, &body)?, &what)?;
+      ctx.progress(Progress::Bytes { done: size, total: Some(size) });
+      return Ok(());
+    }
+    if size.div_ceil(part_size as u64) > MAX_PARTS {
+      return Err(format!("could not {}: `part_size` must be at least {} bytes for a file of {} bytes", what, size.div_ceil(MAX_PARTS), size));
+    }
+    let response = Self::expect(self.send("POST", key, &[("uploads", "")], &headers, &[])?, &what)?;
+    let body = response.into_string().map_err(|v| format!("could not {}: {}", what, v))?;
+    let upload_id = xml_element(&body, "UploadId")
+      .ok_or_else(|| format!("could not {}: the store did not return an upload ID", what))?
+      .to_string();
+    let result = self.upload_parts(ctx, key, &upload_id, &mut file, size, part_size);
+    if result.is_err() {
+      let _ = self.send("DELETE", key, &[("uploadId", &upload_id)], &[], &[]);
+    }
+    result
+  }
+
+  /// Uploads the parts of a multipart upload and completes it
+  fn upload_parts(&self, ctx: &Context, key: &str, upload_id: &str, file: &mut File, size: u64, part_size: usize) -> Result<(), String> {
+    let what = format!("upload `{}`", key);
+    let mut buf = vec![0u8; part_size];
+    let mut etags = vec![];
+    let mut done = 0u64;
+    loop {
+      ctx.check_cancelled()?;
+      let n = read_chunk(file, &mut buf).map_err(|v| format!("could not {}: {}", what, v))?;
+      if n == 0 {
+        break;
+      }
+      let part_number = (etags.len() + 1).to_string();
+      let content_md5 = BASE64.encode(Md5::digest(&buf[..n]));
+      let query = [("partNumber", part_number.as_str()), ("uploadId", upload_id)];
+      let response = Self::expect(self.send("PUT", key, &query, &[("content-md5", &content_md5)], &buf[..n])?, &what)?;
+      let etag = response.header("ETag").ok_or_else(|| format!("could not {}: the store did not return an ETag for part {}", what, part_number))?;
+      etags.push(etag.to_string());
+      done += n as u64;
+      ctx.progress(Progress::Bytes { done, total: Some(size) });
+    }
+    let parts = etags.iter().enumerate()
+      .map(|(i, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag))
+      .collect::<String>();
+    let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+    let response = Self::expect(self.send("POST", key, &[("uploadId", upload_id)], &[("content-type", "application/xml")], body.as_bytes())?, &what)?;
+    // completing can fail after the store has started to respond, in which case the error is in the body
+    let body = response.into_string().map_err(|v| format!("could not {}: {}", what, v))?;
+    match xml_element(&body, "Message").filter(|_| body.contains("<Error>")) {
+      Some(message) => Err(format!("could not {}: {}", what, message)),
+      None => Ok(())
+    }
+  }
+}
\ No newline at end of file
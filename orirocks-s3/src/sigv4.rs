@@ -0,0 +1,67 @@
+//! AWS Signature Version 4, which S3-compatible stores use to authenticate requests.
+
+use std::collections::BTreeMap;
+use orirocks_api_v3::digest::{hex, sha256_hex};
+use ring::hmac;
+use time::OffsetDateTime;
+
+#[derive(Clone, Debug)]
+pub struct Credentials {
+  pub access_key: String,
+  pub secret_key: String
+}
+
+/// The parts of a request that are signed
+pub struct Request<'a> {
+  pub method: &'a str,
+  /// Path of the request, already encoded with `uri_encode`
+  pub uri: &'a str,
+  /// Query string in canonical form, see `canonical_query`
+  pub query: &'a str,
+  /// Headers to sign by lowercase name, including `host`, `x-amz-date` and `x-amz-content-sha256`
+  pub headers: &'a BTreeMap<String, String>,
+  /// Hex SHA-256 digest of the body
+  pub payload_hash: &'a str
+}
+
+/// Percent-encodes everything but unreserved characters, and `/` unless `encode_slash` is set
+pub fn uri_encode(s: &str, encode_slash: bool) -> String {
+  s.bytes()
+    .map(|v| match v {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (v as char).to_string(),
+      b'/' if !encode_slash => "/".to_string(),
+      v => format!("%{:02X}", v)
+    })
+    .collect()
+}
+
+/// Encodes query parameters, sorted by name
+pub fn canonical_query(params: &[(&str, &str)]) -> String {
+  let mut params = params.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect::<Vec<_>>();
+  params.sort();
+  params.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&")
+}
+
+/// The value of `x-amz-date` for a time, such as `20130524T000000Z`
+pub fn amz_date(time: &OffsetDateTime) -> String {
+  format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", time.year(), u8::from(time.month()), time.day(), time.hour(), time.minute(), time.second())
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+  hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes()).as_ref().to_vec()
+}
+
+/// Computes the `Authorization` header of a request made at `time`, which must be the time in `x-amz-date`
+pub fn authorization(credentials: &Credentials, region: &str, request: &Request, time: &OffsetDateTime) -> String {
+  let date = &amz_date(time)[..8];
+  let scope = format!("{}/{}/s3/aws4_request", date, region);
+  let signed_headers = request.headers.keys().cloned().collect::<Vec<_>>().join(";");
+  let canonical_headers = request.headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect::<String>();
+  let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+    request.method, request.uri, request.query, canonical_headers, signed_headers, request.payload_hash);
+  let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date(time), scope, sha256_hex(canonical_request.as_bytes()));
+  let key = [date, region, "s3", "aws4_request"].iter()
+    .fold(format!("AWS4{}", credentials.secret_key).into_bytes(), |key, v| hmac_sha256(&key, v));
+  format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+    credentials.access_key, scope, signed_headers, hex(&hmac_sha256(&key, &string_to_sign)))
+}
\ No newline at end of file
@@ -1,119 +1,444 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::Read;
-use std::iter;
-use std::path::Path;
-use log::info;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use crate::model::{BuildDoc, Document, FunctionDoc, Import, Step};
-use crate::util::{ORError, ORResult, YamlLocation, validate_identifier, Located, SHA256Hasher, sha256_trunc};
+use orirocks_api_v3::{CancellationToken, Context, Environment, Reporter};
+use orirocks_api_v3::digest::sha256_hex;
+use crate::diagnostics::Diagnostics;
+use crate::expand::{check_recursion, expand_steps, InlinedStep};
+use crate::ident::ImportRef;
+use crate::interpolate::references;
+use crate::matrix::{expand_matrix, matrix_scope, resolve_matrix_references};
+use crate::model::{BuildDoc, DeployDoc, Document, FunctionDoc, Import, Library, Parameter, ParameterSpec, Parameters, Step};
+use crate::params::{check_control, check_references, validate_call_in_function, validate_parameter_spec};
+use crate::plan::{plan, plan_artifact, Plan, PlannedArtifact};
+use crate::plugins::PluginHive;
+use crate::remote::RemoteCache;
+use crate::resources::{collect_resources, source_path, walk_typed_values};
+use crate::store::{remove_output, ArtifactStore};
+use crate::vars::{interpolate_options, var_scope, VARS_PREFIX};
+use crate::util::{ORError, ORResult, YamlLocation, validate_identifier, Located, Suggestion, sha256_file, sha256_trunc};
 
 #[derive(Default, Clone, Debug, Eq, PartialEq)]
 pub struct Project {
-  imports: Vec<Located<Import>>,
-  functions: HashMap<String, Located<FunctionDoc>>,
-  builds: HashMap<String, Located<BuildDoc>>
+  /// Directory that `src:` resources are relative to. Empty means the current directory.
+  pub root: PathBuf,
+  pub imports: Vec<Located<Import>>,
+  /// Functions of the project, and of libraries as `library::function`
+  pub functions: HashMap<String, Located<FunctionDoc>>,
+  pub libraries: HashMap<String, Located<Library>>,
+  /// Digests of the contents of the libraries, set once they are loaded
+  pub library_digests: HashMap<String, String>,
+  /// Artifacts by name. Builds with a matrix are stored as one artifact per variant.
+  pub builds: HashMap<String, Located<BuildDoc>>,
+  pub deploys: HashMap<String, Located<DeployDoc>>,
+  /// Names of the variants of each build matrix
+  pub matrices: HashMap<String, Vec<String>>,
+  /// Declared project variables
+  pub vars: HashMap<String, Located<Parameter>>,
+  /// Values of project variables. Starts out with the defaults, see `vars::resolve_vars` for overrides.
+  pub var_values: Parameters
 }
 
-pub fn parse_project(files: Vec<(String, Box<dyn Read>)>) -> ORResult<Project> {
+impl Project {
+  /// Suggests an artifact or build matrix for a name that was not found
+  pub fn suggest_artifact(&self, name: &str) -> Suggestion {
+    let builds = self.builds.values().filter(|v| v.variant.is_none()).map(|v| v.name.as_str());
+    Suggestion::closest(name, builds.chain(self.matrices.keys().map(String::as_str)))
+  }
+
+  pub fn suggest_function(&self, name: &str) -> Suggestion {
+    Suggestion::closest(name, self.functions.keys().map(String::as_str))
+  }
+
+  pub fn suggest_import(&self, name: &str) -> Suggestion {
+    Suggestion::closest(name, self.imports.iter().map(|v| v.require.name()))
+  }
+
+  pub fn suggest_deployment(&self, name: &str) -> Suggestion {
+    Suggestion::closest(name, self.deploys.keys().map(String::as_str))
+  }
+
+  /// Returns the import that provides an environment or deployment provider such as `qemu/vm`.
+  /// Names without a plugin prefix are provided by built-in plugins.
+  pub fn plugin_import(&self, name: &ImportRef, loc: &YamlLocation) -> ORResult<Option<&Import>> {
+    let plugin = match name.prefix() {
+      Some(plugin) => plugin,
+      None => return Ok(None)
+    };
+    self.imports.iter()
+      .find(|v| v.require.name() == plugin)
+      .map(|v| Some(&**v))
+      .ok_or_else(|| ORError::ImportNotFound(loc.clone(), plugin.to_string(), self.suggest_import(plugin)))
+  }
+}
+
+/// Parses project files, recording every error in `diagnostics`. Documents with errors are left out of the project.
+pub fn collect_project(files: Vec<(String, Box<dyn Read>)>, diagnostics: &mut Diagnostics) -> Project {
   let mut project = Project::default();
   for (filename, file) in files {
     for (i, document) in serde_yaml::Deserializer::from_reader(file).enumerate() {
       let location = YamlLocation::new(filename.clone(), i, vec![]);
-      let value = Document::deserialize(document)
-        .map_err(|v| ORError::YamlError(location.clone(), v))?;
+      let value = match Document::deserialize(document) {
+        Ok(value) => value,
+        Err(err) => {
+          diagnostics.error(ORError::YamlError(location, err));
+          continue;
+        }
+      };
       match value {
         Document::Import(import_doc) => {
           project.imports.extend(import_doc.into_iter().map(|v| Located::new(location.clone(), v)));
         },
         Document::Function(function_doc) => {
-          if project.functions.contains_key(&function_doc.name) {
-            return Err(ORError::DuplicateSymbol(location.clone(), "function".into(), function_doc.name));
+          if project.functions.contains_key(function_doc.name.as_str()) {
+            diagnostics.error(ORError::DuplicateSymbol(location.clone(), "function".into(), function_doc.name.to_string()));
+            continue;
           }
-          project.functions.insert(function_doc.name.clone(), Located::new(location.clone(), function_doc));
+          project.functions.insert(function_doc.name.to_string(), Located::new(location.clone(), function_doc));
         }
         Document::Build(build_doc) => {
-          if project.builds.contains_key(&build_doc.name) {
-            return Err(ORError::DuplicateSymbol(location.clone(), "artifact".into(), build_doc.name));
+          if project.builds.contains_key(build_doc.name.as_str()) || project.matrices.contains_key(build_doc.name.as_str()) {
+            diagnostics.error(ORError::DuplicateSymbol(location.clone(), "artifact".into(), build_doc.name.to_string()));
+            continue;
+          }
+          if build_doc.matrix.is_some() {
+            let name = build_doc.name.to_string();
+            let variants = match diagnostics.check(expand_matrix(build_doc, &location)) {
+              Some(variants) => variants,
+              None => continue
+            };
+            project.matrices.insert(name, variants.iter().map(|v| v.name.to_string()).collect());
+            for variant in variants {
+              // errors in a variant name it, since all variants share the same document
+              let mut location = location.clone();
+              location.push(variant.name.to_string());
+              project.builds.insert(variant.name.to_string(), Located::new(location, variant));
+            }
+          } else {
+            project.builds.insert(build_doc.name.to_string(), Located::new(location.clone(), build_doc));
+          }
+        }
+        Document::Library(library_doc) => {
+          for (i, library) in library_doc.into_iter().enumerate() {
+            let mut location = location.clone();
+            location.push_key(library.name.to_string(), &i.to_string());
+            if project.libraries.contains_key(library.name.as_str()) {
+              diagnostics.error(ORError::DuplicateSymbol(location, "library".into(), library.name.to_string()));
+              continue;
+            }
+            project.libraries.insert(library.name.to_string(), Located::new(location, library));
+          }
+        }
+        Document::Deploy(deploy_doc) => {
+          if project.deploys.contains_key(deploy_doc.name.as_str()) {
+            diagnostics.error(ORError::DuplicateSymbol(location.clone(), "deployment".into(), deploy_doc.name.to_string()));
+            continue;
+          }
+          project.deploys.insert(deploy_doc.name.to_string(), Located::new(location.clone(), deploy_doc));
+        }
+        Document::Vars(vars_doc) => {
+          for (name, var) in vars_doc {
+            if project.vars.contains_key(&name) {
+              diagnostics.error(ORError::DuplicateSymbol(location.clone(), "variable".into(), name));
+              continue;
+            }
+            if let Some(default) = &var.default {
+              project.var_values.insert(name.clone(), default.clone());
+            }
+            project.vars.insert(name, Located::new(location.clone(), var));
           }
-          project.builds.insert(build_doc.name.clone(), Located::new(location.clone(), build_doc));
         }
       }
     }
   }
-  Ok(project)
+  resolve_matrix_references(&mut project, diagnostics);
+  project
 }
 
-pub fn validate_project(project: &Project) -> ORResult<()> {
-  fn validate_step(step: &Step, loc: &mut YamlLocation) -> ORResult<()> {
-    validate_identifier(match step {
-      Step::EnvironmentStep(step) => &step.action,
-      Step::InvokeFunctionStep(step) => &step.invoke_fn,
+/// Validates a project, recording every error and warning in `diagnostics`
+pub fn check_project(project: &Project, diagnostics: &mut Diagnostics) {
+  // `scope` lists the parameters and variables that the step may reference
+  fn validate_step(project: &Project, step: &Step, scope: &ParameterSpec, loc: &mut YamlLocation) -> ORResult<()> {
+    let control = match step {
+      Step::EnvironmentStep(step) => &step.control,
+      Step::InvokeFunctionStep(step) => &step.control,
       Step::Null => Err(ORError::GenericInvalid(loc.clone()))?
-    }, &loc)?;
+    };
+    let scope = check_control(scope, control, loc)?;
+    match step {
+      Step::EnvironmentStep(step) => {
+        for (k, v) in &step.parameters {
+          check_references(&scope, k, v, loc)?;
+        }
+      }
+      Step::InvokeFunctionStep(step) => {
+        let function = project.functions.get(step.invoke_fn.as_str())
+          .ok_or_else(|| ORError::FunctionNotFound(loc.clone(), step.invoke_fn.to_string(), project.suggest_function(&step.invoke_fn)))?;
+        validate_call_in_function(&scope, &function.parameter_spec, &step.parameters, loc)?;
+      }
+      Step::Null => {}
+    }
+    Ok(())
+  }
+
+  fn validate_var(project: &Project, name: &str, var: &Located<Parameter>) -> ORResult<()> {
+    let loc = Located::location(var);
+    validate_identifier(name, loc)?;
+    if let Some(default) = &var.default {
+      var.type_.check(default)
+        .map_err(|v| ORError::TypeMismatch(loc.clone(), v.at_key(name)))?;
+    }
+    if !project.var_values.contains_key(name) && !var.type_.is_optional() {
+      return Err(ORError::MissingVariable(loc.clone(), name.to_string()));
+    }
+    Ok(())
+  }
+
+  fn validate_artifact_name(build: &BuildDoc, loc: &YamlLocation) -> ORResult<()> {
+    match &build.variant {
+      Some(variant) => {
+        validate_identifier(&variant.matrix, loc)?;
+        for axis in variant.values.keys() {
+          validate_identifier(axis, loc)?;
+        }
+        Ok(())
+      }
+      // selectors are only allowed when referring to artifacts
+      None => validate_identifier(&build.name, loc)
+    }
+  }
+
+  fn validate_artifact_reference(project: &Project, name: &str, loc: &YamlLocation) -> ORResult<()> {
+    if !project.builds.contains_key(name) {
+      return Err(ORError::ArtifactNotFound(loc.clone(), name.to_string(), project.suggest_artifact(name)));
+    }
     Ok(())
   }
 
-  for import in &project.imports {
-    validate_identifier(&import.require, Located::location(&import))?;
-    //TODO maybe validate semver
+  let vars = var_scope(project);
+  for (name, var) in &project.vars {
+    diagnostics.check(validate_var(project, name, var));
   }
-  for (_, function) in &project.functions {
-    let mut loc = Located::location(&function).clone();
-    validate_identifier(&function.name, &loc)?;
+  //TODO maybe validate import versions as semver
+  for function in project.functions.values() {
+    let mut loc = Located::location(function).clone();
+    diagnostics.check(validate_parameter_spec(&function.parameter_spec, &loc));
+    let mut scope = function.parameter_spec.clone();
+    scope.extend(vars.clone());
     for (i, step) in function.steps.iter().enumerate() {
-      loc.push(format!("step #{}", i));
-      validate_step(&step, &mut loc)?;
+      loc.push_item(format!("step #{}", i), "steps", i);
+      diagnostics.check(validate_step(project, step, &scope, &mut loc));
       loc.pop();
     }
   }
-  for (_, build) in &project.builds {
-    let mut loc = Located::location(&build).clone();
-    validate_identifier(&build.name, &loc)?;
-    for env in &build.envs {
-      loc.push(env.name.clone());
-      let (plugin, env_name) = env.name.split_once("/").ok_or_else(|| ORError::InvalidEnvironmentName(loc.clone()))?;
-      validate_identifier(plugin, &loc)?;
-      validate_identifier(env_name, &loc)?;
+  let acyclic = diagnostics.check(check_recursion(project)).is_some();
+  for build in project.builds.values() {
+    let mut loc = Located::location(build).clone();
+    let mut scope = vars.clone();
+    diagnostics.check(validate_artifact_name(build, &loc));
+    scope.extend(matrix_scope(build));
+    if let Some(from) = &build.from {
+      loc.push_key("from".into(), "from");
+      diagnostics.check(validate_artifact_reference(project, from, &loc));
+      loc.pop();
+    }
+    for (i, dep) in build.depends.iter().flatten().enumerate() {
+      loc.push_item(format!("depends #{}", i), "depends", i);
+      diagnostics.check(validate_artifact_reference(project, dep, &loc));
+      loc.pop();
+    }
+    for (i, env) in build.envs.iter().enumerate() {
+      loc.push_item(env.name.to_string(), "envs", i);
+      let errors = diagnostics.error_count();
+      diagnostics.check(project.plugin_import(&env.name, &loc));
+      for (k, v) in &env.parameters {
+        diagnostics.check(check_references(&scope, k, v, &loc));
+      }
       for (i, step) in env.steps.iter().enumerate() {
-        loc.push(format!("step #{}", i));
-        validate_step(&step, &mut loc)?;
+        loc.push_item(format!("step #{}", i), "steps", i);
+        diagnostics.check(validate_step(project, step, &scope, &mut loc));
         loc.pop();
       }
+      // expansion would report the same problems again
+      if acyclic && diagnostics.error_count() == errors {
+        diagnostics.check(expand_steps(project, build, &env.steps, &loc));
+      }
       loc.pop();
     }
   }
-  Ok(())
+  for deploy in project.deploys.values() {
+    let mut loc = Located::location(deploy).clone();
+    loc.push_key("provider".into(), "provider");
+    diagnostics.check(project.plugin_import(&deploy.provider, &loc));
+    loc.pop();
+    for (i, artifact) in deploy.artifacts.iter().enumerate() {
+      loc.push_item(format!("artifacts #{}", i), "artifacts", i);
+      diagnostics.check(validate_artifact_reference(project, artifact, &loc));
+      loc.pop();
+    }
+    for (k, v) in &deploy.options {
+      diagnostics.check(check_references(&vars, k, v, &loc));
+    }
+  }
+  check_unused(project, diagnostics);
+}
+
+/// Warns about functions that are never called and variables that are never referenced
+fn check_unused(project: &Project, diagnostics: &mut Diagnostics) {
+  let mut steps = vec![];
+  let mut values = vec![];
+  for build in project.builds.values() {
+    for env in &build.envs {
+      values.extend(env.parameters.values());
+      steps.extend(&env.steps);
+    }
+  }
+  for deploy in project.deploys.values() {
+    values.extend(deploy.options.values());
+  }
+  for function in project.functions.values() {
+    steps.extend(&function.steps);
+  }
+  let mut called = HashSet::new();
+  for step in steps {
+    let (control, parameters) = match step {
+      Step::EnvironmentStep(step) => (&step.control, &step.parameters),
+      Step::InvokeFunctionStep(step) => {
+        called.insert(step.invoke_fn.as_str());
+        (&step.control, &step.parameters)
+      }
+      Step::Null => continue
+    };
+    values.extend(control.when.iter().chain(&control.for_each).chain(parameters.values()));
+  }
+  let referenced = values.into_iter()
+    .flat_map(|v| references(v).unwrap_or_default())
+    .filter_map(|v| v.name.strip_prefix(VARS_PREFIX).map(String::from))
+    .collect::<HashSet<_>>();
+  // libraries are shared between projects, which each use some of their functions
+  for (name, function) in project.functions.iter().filter(|v| !v.0.contains("::")) {
+    if !called.contains(name.as_str()) {
+      diagnostics.warning(ORError::UnusedFunction(Located::location(function).clone(), name.clone()));
+    }
+  }
+  for (name, var) in &project.vars {
+    if !referenced.contains(name) {
+      diagnostics.warning(ORError::UnusedVariable(Located::location(var).clone(), name.clone()));
+    }
+  }
 }
 
 pub struct BuildOptions {
   /// Instructs orirocks to build and deploy all artifacts regardless of dirty status.
   pub rebuild: bool,
   /// Specifies the directory to store the build cache and intermediate artifacts
-  pub build_dir: String
+  pub build_dir: String,
+  /// Set when the build should stop, either on Ctrl-C or after a timeout
+  pub cancel: CancellationToken,
+  /// Shares outputs with other machines, see `remote`
+  pub remote_cache: Option<RemoteCache>
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
 pub struct BuildCache {
   import_hashes: HashMap<String, u64>,
   fn_hashes: HashMap<String, u64>,
-  build_hashes: HashMap<String, u64>
+  build_hashes: HashMap<String, u64>,
+  #[serde(default)]
+  library_hashes: HashMap<String, u64>
+}
+
+impl BuildCache {
+  const FILE_NAME: &'static str = "cache.yaml";
+
+  /// Reads the build cache from the build directory, if it exists
+  pub fn load(build_dir: &str) -> ORResult<Option<BuildCache>> {
+    let path = Path::new(build_dir).join(Self::FILE_NAME);
+    let file = match File::open(&path) {
+      Ok(file) => file,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(ORError::IoError(err))
+    };
+    serde_yaml::from_reader(file)
+      .map(Some)
+      .map_err(|v| ORError::YamlError(YamlLocation::new(path.to_string_lossy().into_owned(), 0, vec![]), v))
+  }
+
+  pub fn save(&self, build_dir: &str) -> ORResult<()> {
+    fs::create_dir_all(build_dir).map_err(ORError::IoError)?;
+    let path = Path::new(build_dir).join(Self::FILE_NAME);
+    // written next to the file and renamed over it, so that an interrupted save leaves the previous version
+    let temp = Path::new(build_dir).join(format!(".{}.tmp", Self::FILE_NAME));
+    let file = File::create(&temp).map_err(ORError::IoError)?;
+    serde_yaml::to_writer(file, self)
+      .map_err(|v| ORError::YamlError(YamlLocation::new(path.to_string_lossy().into_owned(), 0, vec![]), v))?;
+    fs::rename(&temp, &path).map_err(ORError::IoError)
+  }
+
+  /// Names of the artifacts that have been built
+  pub fn artifacts(&self) -> impl Iterator<Item = &String> {
+    self.build_hashes.keys()
+  }
+
+  /// Marks an artifact as dirty, such as when its output was removed
+  pub fn forget(&mut self, name: &str) {
+    self.build_hashes.remove(name);
+  }
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct OrderedDependencyGraph {
-  deploys: Vec<String>,
-  artifacts: Vec<(String, Vec<String>)>
+  /// Dirty artifacts in build order, along with their direct dependencies
+  pub artifacts: Vec<(String, Vec<String>)>
+}
+
+/// Checks plugin options and function parameters against the schemas declared for them, and environment options
+/// against the checks of their providers
+pub fn check_plugin_options(project: &Project, plugins: &PluginHive) -> ORResult<()> {
+  for build in project.builds.values() {
+    walk_typed_values(project, build, plugins, &mut |ty, value, loc| {
+      ty.check(value).map_err(|v| ORError::TypeMismatch(loc.clone(), v))
+    })?;
+    let mut loc = Located::location(build).clone();
+    for (i, env) in build.envs.iter().enumerate() {
+      loc.push_item(env.name.to_string(), "envs", i);
+      if let Some(provider) = plugins.environment(&env.name) {
+        let options = interpolate_options(project, build, &env.parameters, &loc)?;
+        provider.check_options(&options.into_iter().collect()).map_err(|v| ORError::PluginError(loc.clone(), v))?;
+      }
+      loc.pop();
+    }
+  }
+  Ok(())
+}
+
+/// Hashes the contents of every project file that an artifact refers to
+fn hash_resources(project: &Project, name: &str, plugins: &PluginHive) -> ORResult<BTreeMap<String, u64>> {
+  let artifact = &project.builds[name];
+  let mut hashes = BTreeMap::new();
+  for resource in collect_resources(project, artifact, plugins)? {
+    let loc = Located::location(artifact);
+    if let Some(path) = source_path(project, &resource, loc)? {
+      let hash = sha256_file(&path)
+        .map_err(|v| ORError::ResourceError(loc.clone(), resource.clone(), v))?;
+      hashes.insert(resource, hash);
+    }
+  }
+  Ok(hashes)
 }
 
 /// Reads and updates the build cache and returns an ordered dependency graph
-pub fn update_cache(project: &Project, build_cache: &mut BuildCache) -> ORResult<OrderedDependencyGraph> {
+pub fn update_cache(project: &Project, plugins: &PluginHive, build_cache: &mut BuildCache) -> ORResult<OrderedDependencyGraph> {
   #[derive(Default)]
   struct IsCleanCache {
     import_clean: HashMap<String, bool>,
     fn_clean: HashMap<String, bool>,
-    build_clean: HashMap<String, bool>
+    build_clean: HashMap<String, bool>,
+    library_clean: HashMap<String, bool>
   }
   let mut is_clean_cache = IsCleanCache::default();
   fn is_hash_clean(is_clean_cache: &mut HashMap<String, bool>, hash_cache: &mut HashMap<String, u64>, s: &str, obj: &impl Hash) -> bool {
@@ -130,102 +455,272 @@ pub fn update_cache(project: &Project, build_cache: &mut BuildCache) -> ORResult
     }
   }
 
-  // does not check dependencies but checks function and import blocks used
-  fn check_artifact_itself_clean(name: &str, project: &Project, build_cache: &mut BuildCache, icc: &mut IsCleanCache) -> bool {
+  // does not check dependencies but checks function and import blocks and project files used
+  // every hash is visited (no short-circuiting) so that all of them are recorded in the cache
+  fn check_artifact_itself_clean(name: &str, project: &Project, plugins: &PluginHive, build_cache: &mut BuildCache, icc: &mut IsCleanCache) -> ORResult<bool> {
     let artifact = &project.builds[name];
-    let artifact_is_clean =
-      is_hash_clean(
-        &mut icc.build_clean,
-        &mut build_cache.build_hashes,
-        name,
-        &**artifact
-      ) &&
-        artifact.envs.iter().all(|v| {
-          let import_name = v.name.split_once("/").unwrap().0;
-          is_hash_clean(
-            &mut icc.import_clean,
-            &mut build_cache.import_hashes,
-            &v.name,
-            &**project.imports.iter().find(|v| v.require == import_name).unwrap()
-          ) && v.steps.iter().all(|v| match v {
-            Step::EnvironmentStep(v) => true,
-            Step::InvokeFunctionStep(v) => is_hash_clean(
-              &mut icc.fn_clean,
-              &mut build_cache.fn_hashes,
-              &v.invoke_fn,
-              &**project.functions.get(&v.invoke_fn).unwrap()
-            ),
-            Step::Null => panic!("Project contains null step")
-          })
-        });
-    artifact_is_clean
-  }
-
-  fn is_clean_dfs<'a>(arti: &'a String, project: &'a Project, clean_artifacts: &mut HashSet<&'a String>, dirty_artifacts: &mut HashSet<&'a String>, check_artifact_itself_clean: &mut dyn FnMut(&str) -> bool) -> ORResult<bool> {
-    if check_artifact_itself_clean(arti) {
+    let resource_hashes = hash_resources(project, name, plugins)?;
+    // options and steps as they will run, so that changing a variable dirties exactly the artifacts using it
+    let planned = plan_artifact(project, name, &[])?;
+    let expanded = planned.envs.iter()
+      .map(|env| (&env.options, env.steps.iter().map(|v| &v.step).collect::<Vec<_>>()))
+      .collect::<Vec<_>>();
+    let functions = planned.envs.iter()
+      .flat_map(|env| env.steps.iter().flat_map(|v| v.call_stack.iter()))
+      .collect::<BTreeSet<_>>();
+    let mut artifact_is_clean = is_hash_clean(
+      &mut icc.build_clean,
+      &mut build_cache.build_hashes,
+      name,
+      &(&**artifact, resource_hashes, expanded)
+    );
+    // keyed by artifact, so that building some artifacts does not hide changes from the others
+    for env in &planned.envs {
+      if let Some(import) = project.plugin_import(&env.name, &env.loc)? {
+        artifact_is_clean &= is_hash_clean(
+          &mut icc.import_clean,
+          &mut build_cache.import_hashes,
+          &format!("{}/{}", name, env.name),
+          import
+        );
+      }
+    }
+    for function in functions {
+      artifact_is_clean &= is_hash_clean(
+        &mut icc.fn_clean,
+        &mut build_cache.fn_hashes,
+        &format!("{}/{}", name, function),
+        &*project.functions[function]
+      );
+      if let Some((library, _)) = function.split_once("::") {
+        artifact_is_clean &= is_hash_clean(
+          &mut icc.library_clean,
+          &mut build_cache.library_hashes,
+          &format!("{}/{}", name, library),
+          &(&*project.libraries[library], project.library_digests.get(library))
+        );
+      }
+    }
+    Ok(artifact_is_clean)
+  }
+
+  struct DfsState<'a> {
+    clean_artifacts: HashSet<&'a str>,
+    dirty_artifacts: HashSet<&'a str>,
+    /// Artifacts on the current dfs path, used to detect cycles
+    visiting: Vec<&'a str>,
+    /// Artifacts in post-order, which is a valid build order
+    order: Vec<&'a str>
+  }
+
+  fn is_clean_dfs<'a>(arti: &'a str, project: &'a Project, state: &mut DfsState<'a>, check_artifact_itself_clean: &mut dyn FnMut(&str) -> ORResult<bool>) -> ORResult<bool> {
+    if state.clean_artifacts.contains(arti) {
+      return Ok(true);
+    } else if state.dirty_artifacts.contains(arti) {
       return Ok(false);
+    } else if state.visiting.contains(&arti) {
+      let cycle = state.visiting.iter()
+        .skip_while(|v| **v != arti)
+        .chain([&arti])
+        .copied()
+        .collect::<Vec<_>>();
+      return Err(ORError::CircularDependency(cycle.join(" -> ")));
     }
-    let mut stack = Vec::new();
-    stack.push(arti);
-    let mut visited = HashSet::new();
-    visited.insert(arti);
-    let mut is_clean = true;
-    while !stack.is_empty() {
-      let arti = stack.pop().unwrap();
-      if dirty_artifacts.contains(arti) {
-        is_clean = false;
-        break;
-      } else if clean_artifacts.contains(arti) {
-        continue;
-      } else {
-        let is_itself_clean = check_artifact_itself_clean(arti);
-        if !is_itself_clean {
-          is_clean = false;
-          break;
-        }
-        let build_doc = &project.builds[arti];
-        for dep in build_doc.depends
-          .iter()
-          .flatten()
-          .chain(build_doc.from.iter())
-        {
-          stack.push(dep);
-          visited.insert(dep);
-        }
+    state.visiting.push(arti);
+    let mut is_clean = check_artifact_itself_clean(arti)?;
+    let build_doc = &project.builds[arti];
+    for dep in build_doc.depends
+      .iter()
+      .flatten()
+      .chain(build_doc.from.iter())
+    {
+      if !project.builds.contains_key(dep.as_str()) {
+        return Err(ORError::ArtifactNotFound(Located::location(build_doc).clone(), dep.to_string(), project.suggest_artifact(dep)));
       }
+      is_clean &= is_clean_dfs(dep, project, state, check_artifact_itself_clean)?;
     }
+    state.visiting.pop();
+    state.order.push(arti);
     if is_clean {
-      clean_artifacts.insert(arti);
+      state.clean_artifacts.insert(arti);
     } else {
-      dirty_artifacts.insert(arti);
+      state.dirty_artifacts.insert(arti);
     }
     Ok(is_clean)
+  }
+
+  let mut state = DfsState {
+    clean_artifacts: HashSet::new(),
+    dirty_artifacts: HashSet::new(),
+    visiting: Vec::new(),
+    order: Vec::new()
   };
-  // dfs
-  let mut clean_artifacts = HashSet::new();
-  let mut dirty_artifacts = HashSet::new();
-  for (name, arti) in &project.builds {
+  // sorted so that the build order is deterministic
+  let mut names = project.builds.keys().collect::<Vec<_>>();
+  names.sort();
+  for name in names {
     is_clean_dfs(
-      &name, project, &mut clean_artifacts, &mut dirty_artifacts,
-      &mut |name: &str| check_artifact_itself_clean(name, project, build_cache, &mut is_clean_cache))?;
-  };
-  // add dependencies
-  let mut dirty_artifacts_with_deps = HashMap::new();
-  for name in dirty_artifacts {
-    let build = &project.builds[name];
-    dirty_artifacts_with_deps.insert(
-      name,
-      build.depends.iter()
+      name, project, &mut state,
+      &mut |name: &str| check_artifact_itself_clean(name, project, plugins, build_cache, &mut is_clean_cache))?;
+  }
+  let artifacts = state.order.iter()
+    .filter(|v| state.dirty_artifacts.contains(*v))
+    .map(|name| {
+      let build = &project.builds[*name];
+      let deps = build.depends.iter()
         .flatten()
         .chain(build.from.iter())
-        .filter(|v| clean_artifacts.contains(v))
-        .collect::<Vec<_>>());
+        .map(|v| v.to_string())
+        .collect();
+      (name.to_string(), deps)
+    })
+    .collect();
+  Ok(OrderedDependencyGraph { artifacts })
+}
+
+/// Returns the path of the latest output of an artifact, see `ArtifactStore`
+pub fn artifact_path(opts: &BuildOptions, name: &str) -> String {
+  ArtifactStore::new(&opts.build_dir).path(name).to_string_lossy().into_owned()
+}
+
+/// Converts a plugin error, reporting it as a cancellation if that is why the plugin failed
+pub fn plugin_error(loc: &YamlLocation, cancel: &CancellationToken, err: String) -> ORError {
+  if cancel.is_cancelled() {
+    ORError::Cancelled
+  } else {
+    ORError::PluginError(loc.clone(), err)
   }
-  todo!()
 }
 
-/// Primary build function
-pub fn build(project: &Project, buildcache: Option<BuildCache>, opts: BuildOptions) {
-  info!("starting build");
+fn run_steps(steps: &[InlinedStep], env: &mut dyn Environment, ctx: &Context) -> ORResult<()> {
+  for (i, inlined) in steps.iter().enumerate() {
+    if ctx.is_cancelled() {
+      return Err(ORError::Cancelled);
+    }
+    let step = &inlined.step;
+    let step_ctx = ctx.for_step(format!("step #{} ({})", i, step.action));
+    env.action(&step_ctx, &step.action, step.parameters.clone().into_iter().collect())
+      .map_err(|v| plugin_error(&inlined.loc, ctx.cancellation(), v))?;
+  }
+  Ok(())
+}
+
+/// Identifies the inputs of an artifact for the remote cache: its definition, the environments and steps as they
+/// will run, the plugins and project files they use, and the outputs of its dependencies.
+/// Locations are left out, so that the key does not change when the project files are rearranged.
+pub fn cache_key(project: &Project, planned: &PlannedArtifact, store: &ArtifactStore, plugins: &PluginHive) -> ORResult<String> {
+  let name = planned.name.as_str();
+  let resource_hashes = hash_resources(project, name, plugins)?;
+  let envs = planned.envs.iter()
+    .map(|env| Ok((&env.name, &env.options, env.steps.iter().map(|v| &v.step).collect::<Vec<_>>(), project.plugin_import(&env.name, &env.loc)?)))
+    .collect::<ORResult<Vec<_>>>()?;
+  let deps = planned.deps.iter()
+    .map(|v| Ok((v, store.digest(v)?)))
+    .collect::<io::Result<Vec<_>>>()
+    .map_err(ORError::IoError)?;
+  // hashed as JSON, whose objects have sorted keys, since `Hash` is not stable across Rust versions and platforms
+  let inputs = serde_json::to_value((env!("CARGO_PKG_VERSION"), &*project.builds[name], resource_hashes, envs, deps))
+    .map_err(|v| ORError::IoError(io::Error::other(v)))?;
+  Ok(sha256_hex(inputs.to_string().as_bytes()))
+}
+
+fn build_artifact(project: &Project, planned: &PlannedArtifact, opts: &BuildOptions, plugins: &PluginHive, reporter: &dyn Reporter) -> ORResult<()> {
+  let name = planned.name.as_str();
+  info!("building `{}`", name);
+  let artifact = &project.builds[name];
+  let ctx = Context::new(name.to_string(), reporter, opts.cancel.clone());
+  let work_dir = Path::new(&opts.build_dir).join("work").join(name);
+  fs::create_dir_all(&work_dir).map_err(ORError::IoError)?;
+  // the output is staged in the work directory until the build succeeds
+  let output = work_dir.join("output");
+  remove_output(&output).map_err(ORError::IoError)?;
+  let store = ArtifactStore::new(&opts.build_dir);
+  store.touch(&planned.deps).map_err(ORError::IoError)?;
+  let key = opts.remote_cache.as_ref()
+    .map(|_| cache_key(project, planned, &store, plugins))
+    .transpose()?;
+  // a rebuild builds everything locally, but still uploads
+  if let (Some(cache), Some(key)) = (opts.remote_cache.as_ref().filter(|_| !opts.rebuild), &key) {
+    match cache.fetch(key, &output) {
+      Ok(Some(digest)) => {
+        store.insert_digest(name, &output, &digest).map_err(ORError::IoError)?;
+        info!("downloaded `{}` from the remote cache as `{}`", name, digest);
+        return Ok(());
+      }
+      Ok(None) => debug!("`{}` is not in the remote cache", name),
+      Err(err) => warn!("could not download `{}` from the remote cache, building it: {}", name, err)
+    }
+  }
+  let mut dependencies = planned.deps.iter()
+    .map(|v| (format!("artifact:{}", v), artifact_path(opts, v)))
+    .collect::<HashMap<_, _>>();
+  for resource in collect_resources(project, artifact, plugins)? {
+    if let Some(path) = source_path(project, &resource, Located::location(artifact))? {
+      dependencies.insert(resource, path.to_string_lossy().into_owned());
+    }
+  }
+  let mut base = artifact.from.as_ref()
+    .map(|v| artifact_path(opts, v))
+    .unwrap_or_default();
+  for (i, env) in planned.envs.iter().enumerate() {
+    if opts.cancel.is_cancelled() {
+      return Err(ORError::Cancelled);
+    }
+    let provider = plugins.environment(&env.name)
+      .ok_or_else(|| ORError::EnvironmentNotFound(env.loc.clone(), env.name.to_string()))?;
+    let mut environment = provider.create(&ctx, base, dependencies.clone(), env.options.clone().into_iter().collect())
+      .map_err(|v| plugin_error(&env.loc, &opts.cancel, v))?;
+    run_steps(&env.steps, environment.as_mut(), &ctx)?;
+    let out = if i + 1 == planned.envs.len() {
+      output.to_string_lossy().into_owned()
+    } else {
+      work_dir.join(format!("env-{}", i)).to_string_lossy().into_owned()
+    };
+    environment.finish(&ctx, &out)
+      .map_err(|v| plugin_error(&env.loc, &opts.cancel, v))?;
+    base = out;
+  }
+  if fs::symlink_metadata(&output).is_err() {
+    let loc = planned.envs.last().map(|v| v.loc.clone()).unwrap_or_else(|| Located::location(artifact).clone());
+    return Err(ORError::MissingOutput(loc, name.to_string()));
+  }
+  let digest = store.insert(name, &output).map_err(ORError::IoError)?;
+  debug!("stored `{}` as `{}`", name, digest);
+  if let (Some(cache), Some(key)) = (opts.remote_cache.as_ref().filter(|v| v.upload), &key) {
+    match cache.store(key, &digest, &store.object_path(&digest), &work_dir) {
+      Ok(()) => info!("uploaded `{}` to the remote cache", name),
+      Err(err) => warn!("could not upload `{}` to the remote cache: {}", name, err)
+    }
+  }
+  Ok(())
+}
+
+/// Works out which artifacts are dirty and which steps building them runs.
+/// Returns the plan and the updated build cache, which should only be saved once the plan has been carried out.
+pub fn plan_build(project: &Project, build_cache: Option<BuildCache>, rebuild: bool, plugins: &PluginHive) -> ORResult<(Plan, BuildCache)> {
+  let mut build_cache = build_cache.unwrap_or_default();
+  if rebuild {
+    // only the artifacts being built are forgotten, so that building a subset keeps the rest of the cache
+    for name in project.builds.keys() {
+      build_cache.forget(name);
+    }
+  }
+  check_plugin_options(project, plugins)?;
+  let graph = update_cache(project, plugins, &mut build_cache)?;
+  Ok((plan(project, &graph)?, build_cache))
+}
 
+/// Primary build function.
+/// Returns the updated build cache, which should only be saved if the build succeeded.
+pub fn build(project: &Project, build_cache: Option<BuildCache>, opts: &BuildOptions, plugins: &PluginHive, reporter: &dyn Reporter) -> ORResult<BuildCache> {
+  info!("starting build");
+  let (plan, build_cache) = plan_build(project, build_cache, opts.rebuild, plugins)?;
+  if plan.artifacts.is_empty() {
+    info!("all artifacts are up to date");
+  }
+  for artifact in &plan.artifacts {
+    build_artifact(project, artifact, opts, plugins, reporter)?;
+  }
+  info!("build finished");
+  Ok(build_cache)
 }
\ No newline at end of file
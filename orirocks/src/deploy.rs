@@ -0,0 +1,275 @@
+//! Deployments publish built artifacts with a deployment provider, see `DeployDoc`.
+//! They only run with `orirocks deploy`, which first builds the artifacts they need.
+//! What was deployed is recorded in a ledger, so that deployments whose artifacts and options did not change are skipped.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use log::info;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use orirocks_api_v3::{Context, Reporter, Value};
+use crate::build::{artifact_path, build, plugin_error, BuildCache, BuildOptions, Project};
+use crate::interpolate::interpolate;
+use crate::model::{DeployDoc, Parameters};
+use crate::plugins::PluginHive;
+use crate::resources::source_path;
+use crate::store::ArtifactStore;
+use crate::util::{sha256_file, sha256_trunc, Located, ORError, ORResult, YamlLocation};
+use crate::vars::var_params;
+
+/// The last run of every deployment, stored in the build directory
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
+pub struct DeploymentLedger {
+  deployments: BTreeMap<String, LedgerEntry>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct LedgerEntry {
+  /// Digests of the deployed artifacts, by name
+  pub artifacts: BTreeMap<String, String>,
+  /// Hash of the provider, its options after variables were substituted, and the project files they refer to
+  pub options_hash: u64,
+  /// When the deployment ran, in RFC 3339 format
+  pub time: String,
+  pub result: DeployResult
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployResult {
+  Succeeded,
+  Failed { error: String }
+}
+
+/// A difference between a deployment and what it last deployed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Drift {
+  NeverDeployed,
+  LastFailed(String),
+  ArtifactChanged(String),
+  ArtifactNotBuilt(String),
+  OptionsChanged
+}
+
+impl Display for Drift {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Drift::NeverDeployed => write!(f, "never deployed"),
+      Drift::LastFailed(err) => write!(f, "last deployment failed: {}", err),
+      Drift::ArtifactChanged(name) => write!(f, "artifact `{}` changed", name),
+      Drift::ArtifactNotBuilt(name) => write!(f, "artifact `{}` has not been built", name),
+      Drift::OptionsChanged => write!(f, "options changed")
+    }
+  }
+}
+
+impl DeploymentLedger {
+  const FILE_NAME: &'static str = "deployments.yaml";
+
+  /// Reads the ledger from the build directory. It is empty if nothing has been deployed yet.
+  pub fn load(build_dir: &str) -> ORResult<DeploymentLedger> {
+    let path = Path::new(build_dir).join(Self::FILE_NAME);
+    let file = match File::open(&path) {
+      Ok(file) => file,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(DeploymentLedger::default()),
+      Err(err) => return Err(ORError::IoError(err))
+    };
+    serde_yaml::from_reader(file)
+      .map_err(|v| ORError::YamlError(YamlLocation::new(path.to_string_lossy().into_owned(), 0, vec![]), v))
+  }
+
+  pub fn save(&self, build_dir: &str) -> ORResult<()> {
+    fs::create_dir_all(build_dir).map_err(ORError::IoError)?;
+    let path = Path::new(build_dir).join(Self::FILE_NAME);
+    // written next to the file and renamed over it, so that an interrupted save leaves the previous version
+    let temp = Path::new(build_dir).join(format!(".{}.tmp", Self::FILE_NAME));
+    let file = File::create(&temp).map_err(ORError::IoError)?;
+    serde_yaml::to_writer(file, self)
+      .map_err(|v| ORError::YamlError(YamlLocation::new(path.to_string_lossy().into_owned(), 0, vec![]), v))?;
+    fs::rename(&temp, &path).map_err(ORError::IoError)
+  }
+
+  pub fn get(&self, deployment: &str) -> Option<&LedgerEntry> {
+    self.deployments.get(deployment)
+  }
+
+  /// Compares what a deployment would deploy now with what it last deployed. Empty if nothing changed.
+  /// Artifacts that have not been built have no digest.
+  fn drift(&self, deployment: &str, artifacts: &BTreeMap<String, Option<String>>, options_hash: u64) -> Vec<Drift> {
+    let entry = match self.deployments.get(deployment) {
+      Some(entry) => entry,
+      None => return vec![Drift::NeverDeployed]
+    };
+    let mut drift = vec![];
+    if let DeployResult::Failed { error } = &entry.result {
+      drift.push(Drift::LastFailed(error.clone()));
+    }
+    for (name, digest) in artifacts {
+      match digest {
+        None => drift.push(Drift::ArtifactNotBuilt(name.clone())),
+        Some(digest) if entry.artifacts.get(name) != Some(digest) => drift.push(Drift::ArtifactChanged(name.clone())),
+        Some(_) => {}
+      }
+    }
+    if entry.options_hash != options_hash {
+      drift.push(Drift::OptionsChanged);
+    }
+    drift
+  }
+}
+
+/// What a deployment would deploy if it ran now
+struct DeployState {
+  options: Parameters,
+  options_hash: u64,
+  /// Digests of the artifacts, or `None` for artifacts that have not been built
+  artifacts: BTreeMap<String, Option<String>>
+}
+
+impl DeployState {
+  /// `files` are the project files that the options refer to, as returned by `check_deploy_options`
+  fn new(project: &Project, deploy: &Located<DeployDoc>, files: &HashMap<String, String>, opts: &BuildOptions) -> ORResult<Self> {
+    let loc = Located::location(deploy);
+    let options = deploy_options(project, deploy, loc)?;
+    let file_hashes = files.iter()
+      .map(|(resource, path)| {
+        let hash = sha256_file(Path::new(path)).map_err(|v| ORError::ResourceError(loc.clone(), resource.clone(), v))?;
+        Ok((resource, hash))
+      })
+      .collect::<ORResult<BTreeMap<_, _>>>()?;
+    let options_hash = sha256_trunc(&(&deploy.provider, &options, file_hashes));
+    let store = ArtifactStore::new(&opts.build_dir);
+    let artifacts = deploy.artifacts.iter()
+      .map(|v| Ok((v.to_string(), store.digest(v).map_err(ORError::IoError)?)))
+      .collect::<ORResult<_>>()?;
+    Ok(DeployState { options, options_hash, artifacts })
+  }
+}
+
+/// Compares every deployment in `deploys` with what it last deployed
+pub fn deployment_status(project: &Project, deploys: &[String], opts: &BuildOptions, plugins: &PluginHive, ledger: &DeploymentLedger) -> ORResult<Vec<(String, Vec<Drift>)>> {
+  let resources = check_deploy_options(project, deploys, plugins)?;
+  deploys.iter()
+    .map(|name| {
+      let state = DeployState::new(project, &project.deploys[name], &resources[name], opts)?;
+      Ok((name.clone(), ledger.drift(name, &state.artifacts, state.options_hash)))
+    })
+    .collect()
+}
+
+/// Returns the deployments to run: those named in `only`, or every deployment if it is empty. Sorted by name.
+pub fn select_deployments(project: &Project, only: &[String]) -> ORResult<Vec<String>> {
+  let mut names = if only.is_empty() {
+    project.deploys.keys().cloned().collect::<Vec<_>>()
+  } else {
+    only.to_vec()
+  };
+  for name in &names {
+    if !project.deploys.contains_key(name) {
+      return Err(ORError::UnknownDeployment(name.clone(), project.suggest_deployment(name)));
+    }
+  }
+  names.sort();
+  names.dedup();
+  Ok(names)
+}
+
+/// Returns the artifacts that the deployments publish, along with every artifact these are built from
+pub fn required_artifacts(project: &Project, deploys: &[String]) -> BTreeSet<String> {
+  let mut required = BTreeSet::new();
+  let mut queue = deploys.iter()
+    .flat_map(|v| project.deploys[v].artifacts.iter().map(|v| v.to_string()))
+    .collect::<Vec<_>>();
+  while let Some(name) = queue.pop() {
+    if let Some(build) = project.builds.get(&name).filter(|_| !required.contains(&name)) {
+      queue.extend(build.from.iter().chain(build.depends.iter().flatten()).map(|v| v.to_string()));
+      required.insert(name);
+    }
+  }
+  required
+}
+
+/// Substitutes project variables in the options of a deployment
+fn deploy_options(project: &Project, deploy: &DeployDoc, loc: &YamlLocation) -> ORResult<Parameters> {
+  let vars = var_params(project);
+  deploy.options.iter()
+    .map(|(k, v)| interpolate(v, &vars)
+      .map(|v| (k.clone(), v))
+      .map_err(|v| ORError::InterpolationError(loc.clone(), v.at_key(k))))
+    .collect()
+}
+
+/// Checks that a plugin provides every deployment, and that their options match the schemas of the providers.
+/// Returns the project files that the options refer to, by deployment.
+pub fn check_deploy_options(project: &Project, deploys: &[String], plugins: &PluginHive) -> ORResult<HashMap<String, HashMap<String, String>>> {
+  let mut resources = HashMap::new();
+  for name in deploys {
+    let deploy = &project.deploys[name];
+    let loc = Located::location(deploy);
+    let provider = plugins.deployment(&deploy.provider)
+      .ok_or_else(|| ORError::DeploymentNotFound(loc.clone(), deploy.provider.to_string()))?;
+    let mut files = HashMap::new();
+    if let Some(schema) = provider.options_schema() {
+      let options = Value::Dict(deploy_options(project, deploy, loc)?);
+      schema.check(&options).map_err(|v| ORError::TypeMismatch(loc.clone(), v))?;
+      for resource in schema.resources(&options) {
+        if let Some(path) = source_path(project, resource, loc)? {
+          files.insert(resource.to_string(), path.to_string_lossy().into_owned());
+        }
+      }
+    }
+    resources.insert(name.clone(), files);
+  }
+  Ok(resources)
+}
+
+/// Runs the deployments `deploys` in order, recording them in `ledger`. Their artifacts must have been built.
+/// Deployments whose artifacts and options did not change since they last succeeded are skipped, unless `force` is set.
+pub fn run_deployments(project: &Project, deploys: &[String], opts: &BuildOptions, force: bool, plugins: &PluginHive, reporter: &dyn Reporter, ledger: &mut DeploymentLedger) -> ORResult<()> {
+  let mut resources = check_deploy_options(project, deploys, plugins)?;
+  for name in deploys {
+    if opts.cancel.is_cancelled() {
+      return Err(ORError::Cancelled);
+    }
+    let deploy = &project.deploys[name];
+    let loc = Located::location(deploy);
+    let provider = plugins.deployment(&deploy.provider).unwrap();
+    let mut dependencies = resources.remove(name).unwrap_or_default();
+    let state = DeployState::new(project, deploy, &dependencies, opts)?;
+    if !force && ledger.drift(name, &state.artifacts, state.options_hash).is_empty() {
+      info!("`{}` is up to date", name);
+      continue;
+    }
+    ArtifactStore::new(&opts.build_dir).touch(deploy.artifacts.iter().map(|v| &**v)).map_err(ORError::IoError)?;
+    dependencies.extend(deploy.artifacts.iter().map(|v| (format!("artifact:{}", v), artifact_path(opts, v))));
+    info!("deploying `{}`", name);
+    let ctx = Context::new(name.to_string(), reporter, opts.cancel.clone());
+    let result = provider.deploy(&ctx, dependencies, state.options.into_iter().collect());
+    ledger.deployments.insert(name.clone(), LedgerEntry {
+      artifacts: state.artifacts.into_iter().filter_map(|(k, v)| Some((k, v?))).collect(),
+      options_hash: state.options_hash,
+      time: OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
+      result: match &result {
+        Ok(()) => DeployResult::Succeeded,
+        Err(error) => DeployResult::Failed { error: error.clone() }
+      }
+    });
+    result.map_err(|v| plugin_error(loc, &opts.cancel, v))?;
+  }
+  Ok(())
+}
+
+/// Builds the artifacts that the deployments need if they are dirty, leaving other artifacts alone.
+/// Returns the updated build cache, which should be saved before running the deployments.
+pub fn build_required(project: &Project, deploys: &[String], build_cache: Option<BuildCache>, opts: &BuildOptions, plugins: &PluginHive, reporter: &dyn Reporter) -> ORResult<BuildCache> {
+  // mistakes in deployments are reported before spending time on building
+  check_deploy_options(project, deploys, plugins)?;
+  let required = required_artifacts(project, deploys);
+  let mut subset = project.clone();
+  subset.builds.retain(|k, _| required.contains(k));
+  build(&subset, build_cache, opts, plugins, reporter)
+}
\ No newline at end of file
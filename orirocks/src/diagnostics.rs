@@ -0,0 +1,123 @@
+use std::fmt::{Display, Formatter};
+use serde::Serialize;
+use crate::source::SourceMap;
+use crate::util::{ORError, ORResult};
+
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str(match self {
+      Severity::Error => "error",
+      Severity::Warning => "warning"
+    })
+  }
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub error: ORError
+}
+
+/// A diagnostic as it is written in JSON
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+  severity: Severity,
+  message: String,
+  file: Option<&'a str>,
+  document: Option<usize>,
+  path: Option<String>,
+  line: Option<usize>,
+  column: Option<usize>
+}
+
+/// Collects errors and warnings, so that all problems in a project can be reported at once
+#[derive(Default, Debug)]
+pub struct Diagnostics {
+  pub(crate) items: Vec<Diagnostic>
+}
+
+impl Diagnostics {
+  pub fn error(&mut self, error: ORError) {
+    self.items.push(Diagnostic { severity: Severity::Error, error });
+  }
+
+  pub fn warning(&mut self, error: ORError) {
+    self.items.push(Diagnostic { severity: Severity::Warning, error });
+  }
+
+  /// Records the error of `result` and returns its value otherwise
+  pub fn check<T>(&mut self, result: ORResult<T>) -> Option<T> {
+    result.map_err(|v| self.error(v)).ok()
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.error_count() > 0
+  }
+
+  pub fn error_count(&self) -> usize {
+    self.items.iter().filter(|v| v.severity == Severity::Error).count()
+  }
+
+  pub fn items(&self) -> &[Diagnostic] {
+    &self.items
+  }
+
+  /// Sorts diagnostics by file, line and column. Diagnostics without a position come last.
+  pub fn sort(&mut self, sources: &SourceMap) {
+    self.items.sort_by_cached_key(|v| {
+      let span = sources.locate(&v.error);
+      let file = span.map(|v| v.0.to_string()).or_else(|| v.error.location().map(|v| v.file.clone()));
+      (file.is_none(), file, span.map(|v| (v.1.line, v.1.column)), v.error.location().map(|v| v.document_id))
+    });
+  }
+
+  /// Formats every diagnostic with its source snippet, followed by a summary
+  pub fn render(&self, sources: &SourceMap, color: bool) -> String {
+    let mut out = self.items.iter()
+      .map(|v| sources.render(&v.error, v.severity, color))
+      .collect::<Vec<_>>()
+      .join("\n");
+    let count = |severity| self.items.iter().filter(|v| v.severity == severity).count();
+    let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+    if !self.items.is_empty() {
+      out += &format!("\n{}, {}\n", plural(count(Severity::Error), "error"), plural(count(Severity::Warning), "warning"));
+    }
+    out
+  }
+
+  /// Formats the diagnostics as a JSON array, for editors and CI
+  pub fn to_json(&self, sources: &SourceMap) -> String {
+    let items = self.items.iter()
+      .map(|v| {
+        let loc = v.error.location();
+        let span = sources.locate(&v.error);
+        JsonDiagnostic {
+          severity: v.severity,
+          message: message(&v.error),
+          file: loc.map(|v| v.file.as_str()),
+          document: loc.map(|v| v.document_id),
+          path: loc.map(|v| v.path()),
+          line: span.map(|v| v.1.line),
+          column: span.map(|v| v.1.column)
+        }
+      })
+      .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&items).unwrap()
+  }
+}
+
+/// The message of an error without the location it starts with
+fn message(error: &ORError) -> String {
+  let message = error.to_string();
+  match error.location() {
+    Some(loc) => message.strip_prefix(&format!("in `{}`: ", loc)).map(String::from).unwrap_or(message),
+    None => message
+  }
+}
\ No newline at end of file
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use orirocks_api_v3::{Value, ValueError};
+use crate::build::Project;
+use crate::interpolate::{interpolate, interpolate_step};
+use crate::matrix::matrix_params;
+use crate::model::{BuildDoc, EnvironmentStep, FunctionDoc, Parameters, Step, StepControl};
+use crate::params::resolve_parameters;
+use crate::util::{Located, ORError, ORResult, YamlLocation};
+use crate::vars::var_params;
+
+/// Name of the loop variable of a `for_each` step that does not set `as`
+pub const DEFAULT_LOOP_VARIABLE: &str = "item";
+
+/// An environment step after function calls have been inlined
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InlinedStep {
+  pub step: EnvironmentStep,
+  /// Location of the step, including every function call and loop iteration that led to it
+  pub loc: YamlLocation,
+  /// Functions the step was inlined from, outermost first
+  pub call_stack: Vec<String>
+}
+
+/// Called for every function call during expansion, with the resolved parameters of the call
+pub type CallVisitor<'a> = dyn FnMut(&FunctionDoc, &Parameters, &YamlLocation) -> ORResult<()> + 'a;
+
+/// Substitutes project variables, evaluates `when` conditions, unrolls `for_each` loops
+/// and inlines every function call in `steps`, including calls made by functions, into a flat list of environment steps.
+/// Calls to missing functions and recursive calls are reported as errors.
+/// The steps of `artifact` may also reference its matrix values, function steps may not.
+pub fn expand_steps(project: &Project, artifact: &BuildDoc, steps: &[Step], loc: &YamlLocation) -> ORResult<Vec<InlinedStep>> {
+  expand_steps_with(project, artifact, steps, loc, &mut |_, _, _| Ok(()))
+}
+
+/// Like `expand_steps`, but also reports every function call to `on_call`
+pub fn expand_steps_with(project: &Project, artifact: &BuildDoc, steps: &[Step], loc: &YamlLocation, on_call: &mut CallVisitor) -> ORResult<Vec<InlinedStep>> {
+  let vars = var_params(project);
+  let mut scope = vars.clone();
+  scope.extend(matrix_params(artifact));
+  let mut expander = Expander {
+    project,
+    vars,
+    stack: vec![],
+    out: vec![],
+    on_call
+  };
+  expander.expand(steps, &scope, &mut loc.clone())?;
+  Ok(expander.out)
+}
+
+/// Reports the first cycle of function calls in the project, including cycles between functions that are never called.
+/// Calls are followed whatever their `when` conditions, and calls to missing functions are ignored.
+pub fn check_recursion(project: &Project) -> ORResult<()> {
+  fn visit(project: &Project, name: &str, stack: &mut Vec<String>, acyclic: &mut HashSet<String>) -> ORResult<()> {
+    let Some(function) = project.functions.get(name).filter(|_| !acyclic.contains(name)) else {
+      return Ok(());
+    };
+    stack.push(name.to_string());
+    for (i, step) in function.steps.iter().enumerate() {
+      let Step::InvokeFunctionStep(call) = step else {
+        continue;
+      };
+      if let Some(start) = stack.iter().position(|v| *v == *call.invoke_fn) {
+        let mut loc = Located::location(function).clone();
+        loc.push_item(format!("step #{}", i), "steps", i);
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(call.invoke_fn.to_string());
+        return Err(ORError::RecursiveFunction(loc, cycle.join(" -> ")));
+      }
+      visit(project, &call.invoke_fn, stack, acyclic)?;
+    }
+    stack.pop();
+    acyclic.insert(name.to_string());
+    Ok(())
+  }
+
+  let mut names = project.functions.keys().collect::<Vec<_>>();
+  names.sort();
+  let mut acyclic = HashSet::new();
+  for name in names {
+    visit(project, name, &mut vec![], &mut acyclic)?;
+  }
+  Ok(())
+}
+
+/// Evaluates a `when` condition after interpolation.
+/// It is either a bool, or a comparison of two strings such as `aarch64 == x86_64` or `3.17 != 3.18`.
+/// Null, such as an optional parameter that was not passed, counts as false.
+pub fn evaluate_condition(value: &Value) -> Result<bool, ValueError> {
+  match value {
+    Value::Bool(v) => Ok(*v),
+    Value::Null => Ok(false),
+    Value::String(s) => {
+      let (lhs, rhs, equal) = match (s.split_once("=="), s.split_once("!=")) {
+        (Some((lhs, rhs)), _) => (lhs, rhs, true),
+        (None, Some((lhs, rhs))) => (lhs, rhs, false),
+        (None, None) => return Err(ValueError::new(format!("expected bool or comparison like `a == b`, found `{}`", s)))
+      };
+      Ok((lhs.trim() == rhs.trim()) == equal)
+    }
+    v => Err(ValueError::type_mismatch("bool or comparison", v))
+  }
+}
+
+/// Returns the elements that a `for_each` step iterates over, after interpolation
+fn loop_items(for_each: Value) -> Result<Vec<Value>, ValueError> {
+  match for_each {
+    Value::Array(items) => Ok(items),
+    Value::Null => Ok(vec![]),
+    v => Err(ValueError::type_mismatch("array", &v))
+  }
+}
+
+struct Expander<'a, 'b> {
+  project: &'a Project,
+  /// Project variables, under the names they are referenced by
+  vars: Parameters,
+  stack: Vec<String>,
+  out: Vec<InlinedStep>,
+  on_call: &'b mut CallVisitor<'b>
+}
+
+impl Expander<'_, '_> {
+  /// `scope` holds the values that the steps may reference
+  fn expand(&mut self, steps: &[Step], scope: &Parameters, loc: &mut YamlLocation) -> ORResult<()> {
+    for (i, step) in steps.iter().enumerate() {
+      loc.push_item(format!("step #{}", i), "steps", i);
+      let control = match step {
+        Step::EnvironmentStep(step) => &step.control,
+        Step::InvokeFunctionStep(step) => &step.control,
+        Step::Null => return Err(ORError::GenericInvalid(loc.clone()))
+      };
+      if let Some(when) = &control.when {
+        let holds = interpolate(when, scope)
+          .map_err(|v| ORError::InterpolationError(loc.clone(), v.at_key("when")))
+          .and_then(|v| evaluate_condition(&v).map_err(|v| ORError::TypeMismatch(loc.clone(), v.at_key("when"))))?;
+        if !holds {
+          loc.pop();
+          continue;
+        }
+      }
+      let items = match &control.for_each {
+        Some(for_each) => interpolate(for_each, scope)
+          .map_err(|v| ORError::InterpolationError(loc.clone(), v.at_key("for_each")))
+          .and_then(|v| loop_items(v).map_err(|v| ORError::TypeMismatch(loc.clone(), v.at_key("for_each"))))
+          .map(Some)?,
+        None => None
+      };
+      match items {
+        None => self.expand_step(step, scope, loc)?,
+        Some(items) => {
+          let name = control.as_.as_deref().unwrap_or(DEFAULT_LOOP_VARIABLE);
+          for (j, item) in items.into_iter().enumerate() {
+            let mut scope = scope.clone();
+            scope.insert(name.to_string(), item);
+            loc.push(format!("item #{}", j));
+            self.expand_step(step, &scope, loc)?;
+            loc.pop();
+          }
+        }
+      }
+      loc.pop();
+    }
+    Ok(())
+  }
+
+  fn expand_step(&mut self, step: &Step, scope: &Parameters, loc: &mut YamlLocation) -> ORResult<()> {
+    let step = interpolate_step(step, scope)
+      .map_err(|v| ORError::InterpolationError(loc.clone(), v))?;
+    match step {
+      Step::EnvironmentStep(step) => self.out.push(InlinedStep {
+        step: EnvironmentStep { control: StepControl::default(), ..step },
+        loc: loc.clone(),
+        call_stack: self.stack.clone()
+      }),
+      Step::InvokeFunctionStep(call) => {
+        if let Some(start) = self.stack.iter().position(|v| *v == *call.invoke_fn) {
+          let mut cycle = self.stack[start..].to_vec();
+          cycle.push(call.invoke_fn.to_string());
+          return Err(ORError::RecursiveFunction(loc.clone(), cycle.join(" -> ")));
+        }
+        let project = self.project;
+        let function = project.functions.get(call.invoke_fn.as_str())
+          .ok_or_else(|| ORError::FunctionNotFound(loc.clone(), call.invoke_fn.to_string(), project.suggest_function(&call.invoke_fn)))?;
+        let mut params = resolve_parameters(&function.parameter_spec, &call.parameters, loc)?;
+        (self.on_call)(function, &params, loc)?;
+        // optional parameters that were not passed are substituted as null
+        for name in function.parameter_spec.keys() {
+          params.entry(name.clone()).or_insert(Value::Null);
+        }
+        params.extend(self.vars.clone());
+        self.stack.push(call.invoke_fn.to_string());
+        loc.push_document(format!("function {}", call.invoke_fn), Located::location(function));
+        self.expand(&function.steps, &params, loc)?;
+        loc.pop();
+        self.stack.pop();
+      }
+      Step::Null => return Err(ORError::GenericInvalid(loc.clone()))
+    }
+    Ok(())
+  }
+}
\ No newline at end of file
@@ -0,0 +1,191 @@
+//! Garbage collection of the artifact store. Outputs are kept if they are among the latest outputs of an artifact
+//! that the project defines or the build cache knows, and the least recently used ones are evicted to stay
+//! within a size budget.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::time::UNIX_EPOCH;
+use crate::store::{disk_size, remove_output, ArtifactStore};
+use crate::util::{ORError, ORResult};
+
+pub struct GcOptions {
+  /// Outputs to keep for every artifact, including the latest one
+  pub keep_last: usize,
+  /// Evicts the least recently used outputs until the store is no larger than this many bytes
+  pub max_size: Option<u64>,
+  /// Works out what would be removed without removing anything
+  pub dry_run: bool
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Reason {
+  /// No artifact of the project or the build cache has the output
+  Unreferenced,
+  /// Older than the outputs kept for its artifacts
+  Superseded,
+  /// Removed to stay within the size budget
+  Evicted
+}
+
+/// An output that was removed, or that would be removed on a dry run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Garbage {
+  pub digest: String,
+  pub size: u64,
+  /// Artifacts that had the output
+  pub names: Vec<String>,
+  pub reason: Reason
+}
+
+impl Display for Garbage {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let names = self.names.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>().join(", ");
+    let reason = match (self.reason, names.is_empty()) {
+      (Reason::Unreferenced, true) => "not referenced".to_string(),
+      (Reason::Unreferenced, false) => format!("output of {}, which the project no longer has", names),
+      (Reason::Superseded, _) => format!("older output of {}", names),
+      (Reason::Evicted, _) => format!("least recently used output of {}", names)
+    };
+    write!(f, "{} ({}): {}", self.digest, format_size(self.size), reason)
+  }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GcReport {
+  pub removed: Vec<Garbage>,
+  /// Size of the outputs left in the store
+  pub kept_size: u64,
+  /// Artifacts whose latest output was evicted. They must be rebuilt before they can be deployed.
+  pub evicted_artifacts: Vec<String>
+}
+
+impl GcReport {
+  pub fn freed(&self) -> u64 {
+    self.removed.iter().map(|v| v.size).sum()
+  }
+}
+
+/// Removes the outputs in the store of `build_dir` that are not among the latest `keep_last` outputs of an artifact
+/// in `live`, then evicts the least recently used outputs until the store fits in `max_size`.
+pub fn collect_garbage(build_dir: &str, live: &BTreeSet<String>, opts: &GcOptions) -> ORResult<GcReport> {
+  let store = ArtifactStore::new(build_dir);
+  let mut index = store.index().map_err(ORError::IoError)?;
+  let mut latest = BTreeMap::new();
+  for name in store.names().map_err(ORError::IoError)? {
+    if let Some(digest) = store.digest(&name).map_err(ORError::IoError)? {
+      latest.insert(name, digest);
+    }
+  }
+  // artifacts that had each output, to explain why it is removed
+  let mut owners = BTreeMap::<String, BTreeSet<String>>::new();
+  for (name, digests) in &index.history {
+    for digest in digests {
+      owners.entry(digest.clone()).or_default().insert(name.clone());
+    }
+  }
+  for (name, digest) in &latest {
+    owners.entry(digest.clone()).or_default().insert(name.clone());
+  }
+  let mut kept = BTreeMap::<String, BTreeSet<String>>::new();
+  for name in live {
+    let history = index.history.get(name).map(Vec::as_slice).unwrap_or_default();
+    let mut digests = latest.get(name).into_iter().chain(history.iter().rev()).collect::<Vec<_>>();
+    digests.dedup();
+    for digest in digests.into_iter().take(opts.keep_last.max(1)) {
+      kept.entry(digest.clone()).or_default().insert(name.clone());
+    }
+  }
+
+  let mut report = GcReport::default();
+  let mut sizes = BTreeMap::new();
+  for digest in store.objects().map_err(ORError::IoError)? {
+    let size = disk_size(&store.object_path(&digest)).map_err(ORError::IoError)?;
+    if kept.contains_key(&digest) {
+      sizes.insert(digest, size);
+      continue;
+    }
+    let names = owners.remove(&digest).unwrap_or_default();
+    let reason = match names.iter().any(|v| live.contains(v)) {
+      true => Reason::Superseded,
+      false => Reason::Unreferenced
+    };
+    report.removed.push(Garbage { digest, size, names: names.into_iter().collect(), reason });
+  }
+  report.kept_size = sizes.values().sum();
+  if let Some(max_size) = opts.max_size {
+    let mut by_use = sizes.keys()
+      .map(|v| Ok((last_used(&store, &index.last_used, v)?, v.clone())))
+      .collect::<ORResult<Vec<_>>>()?;
+    by_use.sort();
+    for (_, digest) in by_use {
+      if report.kept_size <= max_size {
+        break;
+      }
+      let size = sizes.remove(&digest).unwrap();
+      report.kept_size -= size;
+      let names = kept.remove(&digest).unwrap_or_default();
+      report.evicted_artifacts.extend(names.iter().filter(|v| latest.get(*v) == Some(&digest)).cloned());
+      report.removed.push(Garbage { digest, size, names: names.into_iter().collect(), reason: Reason::Evicted });
+    }
+  }
+  if opts.dry_run {
+    return Ok(report);
+  }
+
+  // links go first, so that no link points at a removed output
+  for (name, digest) in &latest {
+    if !live.contains(name) || !sizes.contains_key(digest) {
+      store.unlink(name).map_err(ORError::IoError)?;
+    }
+  }
+  for garbage in &report.removed {
+    remove_output(&store.object_path(&garbage.digest)).map_err(ORError::IoError)?;
+  }
+  index.history.retain(|k, _| live.contains(k));
+  for digests in index.history.values_mut() {
+    digests.retain(|v| sizes.contains_key(v));
+  }
+  index.history.retain(|_, v| !v.is_empty());
+  index.last_used.retain(|k, _| sizes.contains_key(k));
+  store.save_index(&index).map_err(ORError::IoError)?;
+  Ok(report)
+}
+
+/// When an output was last used, or else when it was stored
+fn last_used(store: &ArtifactStore, recorded: &BTreeMap<String, u64>, digest: &str) -> ORResult<u64> {
+  if let Some(time) = recorded.get(digest) {
+    return Ok(*time);
+  }
+  let modified = fs::symlink_metadata(store.object_path(digest))
+    .and_then(|v| v.modified())
+    .map_err(ORError::IoError)?;
+  Ok(modified.duration_since(UNIX_EPOCH).map(|v| v.as_secs()).unwrap_or_default())
+}
+
+/// Parses a size such as `500M` or `10GiB`. Units are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+  let s = s.trim();
+  let split = s.find(|v: char| !v.is_ascii_digit() && v != '.').unwrap_or(s.len());
+  let (number, unit) = s.split_at(split);
+  let number = number.parse::<f64>().map_err(|_| format!("invalid size `{}`", s))?;
+  let shift = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+    "" => 0,
+    "K" => 10,
+    "M" => 20,
+    "G" => 30,
+    "T" => 40,
+    _ => return Err(format!("unknown unit in `{}`, expected K, M, G or T", s))
+  };
+  Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// Formats a size with the largest unit that keeps it at least 1, such as `1.5 GiB`
+pub fn format_size(size: u64) -> String {
+  let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let exp = (0..units.len()).rev().find(|v| size >> (10 * v) > 0).unwrap_or(0);
+  match exp {
+    0 => format!("{} B", size),
+    _ => format!("{:.1} {}", size as f64 / (1u64 << (10 * exp)) as f64, units[exp])
+  }
+}
\ No newline at end of file
@@ -0,0 +1,199 @@
+//! Names used in project files. They are checked while documents are deserialized, following this grammar:
+//!
+//! ```text
+//! ident         = [a-zA-Z0-9_]+
+//! segment       = [a-zA-Z0-9_-]+
+//! import_ref    = [segment "/"] segment
+//! function_ref  = [ident "::"] ident
+//! artifact_name = ident ["[" selector ("," selector)* "]"]
+//! selector      = ident "=" value
+//! value         = [a-zA-Z0-9_.-]+
+//! ```
+//!
+//! Identifiers name functions, actions, parameters, variables, loop variables and matrix axes.
+//! They cannot contain `-`, so that they can be referenced as `${name}`.
+//!
+//! Import references name plugins. An import requires `namespace/plugin` or just `plugin`,
+//! and an environment of a build is `plugin/environment`, or just `environment` for built-in plugins.
+//!
+//! Function references call functions, either of the project or of a library, such as `docker::install`.
+//!
+//! Artifact names name builds. `from` and `depends` may select variants of a build matrix, such as `base[arch=aarch64]`.
+//! They are also file names in the build directory, so values cannot contain `/`.
+
+use std::borrow::Borrow;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use serde::{Deserialize, Serialize};
+
+fn is_ident_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_segment_char(c: char) -> bool {
+  is_ident_char(c) || c == '-'
+}
+
+pub fn is_ident(s: &str) -> bool {
+  !s.is_empty() && s.chars().all(is_ident_char)
+}
+
+fn is_segment(s: &str) -> bool {
+  !s.is_empty() && s.chars().all(is_segment_char)
+}
+
+/// Matrix values are part of artifact names, which name files in the build directory
+pub fn is_selector_value(s: &str) -> bool {
+  !s.is_empty() && s.chars().all(|c| is_segment_char(c) || c == '.')
+}
+
+fn is_import_ref(s: &str) -> bool {
+  match s.split_once('/') {
+    Some((prefix, name)) => is_segment(prefix) && is_segment(name),
+    None => is_segment(s)
+  }
+}
+
+fn is_function_ref(s: &str) -> bool {
+  match s.split_once("::") {
+    Some((library, name)) => is_ident(library) && is_ident(name),
+    None => is_ident(s)
+  }
+}
+
+fn is_artifact_name(s: &str) -> bool {
+  let (name, selectors) = match s.split_once('[') {
+    Some((name, selectors)) => match selectors.strip_suffix(']') {
+      Some(selectors) => (name, Some(selectors)),
+      None => return false
+    },
+    None => (s, None)
+  };
+  is_ident(name) && selectors.is_none_or(|v| v.split(',').all(|selector| {
+    matches!(selector.split_once('='), Some((axis, value)) if is_ident(axis) && is_selector_value(value))
+  }))
+}
+
+macro_rules! name_type {
+  ($(#[$attr:meta])* $name:ident, $check:ident, $what:literal, $expected:literal) => {
+    $(#[$attr])*
+    #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+    #[serde(try_from = "String", into = "String")]
+    pub struct $name(String);
+
+    impl $name {
+      pub fn as_str(&self) -> &str {
+        &self.0
+      }
+    }
+
+    impl TryFrom<String> for $name {
+      type Error = String;
+
+      fn try_from(s: String) -> Result<Self, Self::Error> {
+        if $check(&s) {
+          Ok($name(s))
+        } else {
+          Err(format!("invalid {} `{}`, expected {}", $what, s, $expected))
+        }
+      }
+    }
+
+    impl TryFrom<&str> for $name {
+      type Error = String;
+
+      fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::try_from(s.to_string())
+      }
+    }
+
+    impl From<$name> for String {
+      fn from(v: $name) -> Self {
+        v.0
+      }
+    }
+
+    impl Deref for $name {
+      type Target = str;
+
+      fn deref(&self) -> &str {
+        &self.0
+      }
+    }
+
+    impl Borrow<str> for $name {
+      fn borrow(&self) -> &str {
+        &self.0
+      }
+    }
+
+    impl Display for $name {
+      fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+      }
+    }
+
+    impl PartialEq<str> for $name {
+      fn eq(&self, other: &str) -> bool {
+        self.0 == other
+      }
+    }
+
+    impl PartialEq<&str> for $name {
+      fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+      }
+    }
+  };
+}
+
+name_type!(
+  /// Name of a function, action, parameter, variable, loop variable or matrix axis
+  Ident, is_ident, "identifier", "letters, digits and `_`"
+);
+
+name_type!(
+  /// Reference to a plugin, such as `example/plugin` in an import or `qemu/vm` for an environment
+  ImportRef, is_import_ref, "import reference", "`name` or `prefix/name` made of letters, digits, `_` and `-`"
+);
+
+name_type!(
+  /// Function to call, such as `setup` in the project or `docker::install` in the library `docker`
+  FunctionRef, is_function_ref, "function reference", "`name` or `library::name` made of letters, digits and `_`"
+);
+
+name_type!(
+  /// Name of an artifact, optionally selecting variants of a build matrix, such as `base[arch=aarch64]`
+  ArtifactName, is_artifact_name, "artifact name", "an identifier, optionally followed by selectors like `[axis=value]` with values made of letters, digits, `_`, `-` and `.`"
+);
+
+impl ImportRef {
+  /// The part before `/`, if there is one
+  pub fn prefix(&self) -> Option<&str> {
+    self.0.split_once('/').map(|v| v.0)
+  }
+
+  /// The part after `/`, or the whole reference if there is no `/`
+  pub fn name(&self) -> &str {
+    self.0.split_once('/').map_or(&self.0, |v| v.1)
+  }
+}
+
+impl FunctionRef {
+  /// Refers to `name` in `library`
+  pub fn qualified(library: &str, name: &str) -> Self {
+    FunctionRef(format!("{}::{}", library, name))
+  }
+
+  /// The library the function is in, if it is not a project function
+  pub fn library(&self) -> Option<&str> {
+    self.0.split_once("::").map(|v| v.0)
+  }
+}
+
+impl ArtifactName {
+  /// Names an artifact without checking it, for names that orirocks generates, such as matrix variants
+  pub fn new_unchecked(name: String) -> Self {
+    ArtifactName(name)
+  }
+}
\ No newline at end of file
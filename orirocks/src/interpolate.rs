@@ -0,0 +1,157 @@
+use orirocks_api_v3::{Value, ValueError};
+use crate::model::{EnvironmentStep, InvokeFunctionStep, Parameters, Step};
+
+/// A piece of a string that may contain parameter references
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Segment {
+  Literal(String),
+  /// A reference in the form `${name}` or `${vars.name}`
+  Reference(String)
+}
+
+/// Splits a string into literals and `${name}` references. `$${` is an escaped literal `${`.
+pub fn parse_template(s: &str) -> Result<Vec<Segment>, ValueError> {
+  let mut segments = vec![];
+  let mut literal = String::new();
+  let mut rest = s;
+  while let Some(i) = rest.find('$') {
+    literal.push_str(&rest[..i]);
+    rest = &rest[i..];
+    if rest.starts_with("$${") {
+      literal.push_str("${");
+      rest = &rest[3..];
+    } else if let Some(reference) = rest.strip_prefix("${") {
+      let end = reference.find('}')
+        .ok_or_else(|| ValueError::new(format!("unterminated reference in `{}`", s)))?;
+      let name = &reference[..end];
+      if name.is_empty() || !name.chars().all(|v| v.is_ascii_alphanumeric() || v == '_' || v == '.') {
+        return Err(ValueError::new(format!("invalid reference `${{{}}}`", name)));
+      }
+      if !literal.is_empty() {
+        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+      }
+      segments.push(Segment::Reference(name.to_string()));
+      rest = &reference[end + 1..];
+    } else {
+      literal.push('$');
+      rest = &rest[1..];
+    }
+  }
+  literal.push_str(rest);
+  if !literal.is_empty() {
+    segments.push(Segment::Literal(literal));
+  }
+  Ok(segments)
+}
+
+/// A parameter reference found in a value
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reference {
+  pub name: String,
+  /// Path to the value containing the reference, in the same format as `ValueError::path`
+  pub path: String,
+  /// True if the reference makes up the whole value, in which case any type can be substituted
+  pub whole: bool
+}
+
+/// Lists every parameter reference inside `value`
+pub fn references(value: &Value) -> Result<Vec<Reference>, ValueError> {
+  fn collect(value: &Value, path: String, out: &mut Vec<Reference>) -> Result<(), ValueError> {
+    match value {
+      Value::String(s) => {
+        let segments = parse_template(s).map_err(|v| ValueError { path: path.clone(), ..v })?;
+        let whole = segments.len() == 1;
+        for segment in segments {
+          if let Segment::Reference(name) = segment {
+            out.push(Reference { name, path: path.clone(), whole });
+          }
+        }
+      }
+      Value::Array(values) => {
+        for (i, v) in values.iter().enumerate() {
+          collect(v, format!("{}[{}]", path, i), out)?;
+        }
+      }
+      Value::Dict(values) => {
+        for (k, v) in values {
+          collect(v, if path.is_empty() { k.clone() } else { format!("{}.{}", path, k) }, out)?;
+        }
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+  let mut out = vec![];
+  collect(value, String::new(), &mut out)?;
+  Ok(out)
+}
+
+/// Formats a parameter so that it can be embedded in a string
+fn to_template_string(name: &str, value: &Value) -> Result<String, ValueError> {
+  match value {
+    Value::String(v) => Ok(v.clone()),
+    Value::Integer(v) => Ok(v.to_string()),
+    Value::Float(v) => Ok(v.inner.to_string()),
+    Value::Bool(v) => Ok(v.to_string()),
+    v => Err(ValueError::new(format!("parameter `{}` is {} and cannot be embedded in a string", name, v.type_name())))
+  }
+}
+
+/// Substitutes parameter references in `value`.
+/// A string that consists of a single reference is replaced by the parameter itself, keeping its type.
+/// Otherwise parameters are formatted into the string, which requires them to be scalars.
+pub fn interpolate(value: &Value, params: &Parameters) -> Result<Value, ValueError> {
+  match value {
+    Value::String(s) => {
+      let segments = parse_template(s)?;
+      let lookup = |name: &str| params.get(name)
+        .ok_or_else(|| ValueError::new(format!("unknown parameter `{}`", name)));
+      match segments.as_slice() {
+        [Segment::Reference(name)] => lookup(name).cloned(),
+        segments => {
+          let mut out = String::new();
+          for segment in segments {
+            match segment {
+              Segment::Literal(v) => out.push_str(v),
+              Segment::Reference(name) => out.push_str(&to_template_string(name, lookup(name)?)?)
+            }
+          }
+          Ok(Value::String(out))
+        }
+      }
+    }
+    Value::Array(values) => values.iter()
+      .enumerate()
+      .map(|(i, v)| interpolate(v, params).map_err(|v| v.at_index(i)))
+      .collect::<Result<_, _>>()
+      .map(Value::Array),
+    Value::Dict(values) => values.iter()
+      .map(|(k, v)| interpolate(v, params).map(|v| (k.clone(), v)).map_err(|v| v.at_key(k)))
+      .collect::<Result<_, _>>()
+      .map(Value::Dict),
+    v => Ok(v.clone())
+  }
+}
+
+fn interpolate_parameters(parameters: &Parameters, params: &Parameters) -> Result<Parameters, ValueError> {
+  parameters.iter()
+    .map(|(k, v)| interpolate(v, params).map(|v| (k.clone(), v)).map_err(|v| v.at_key(k)))
+    .collect()
+}
+
+/// Substitutes parameter references in the options of a step. `when` and `for_each` are left for the expansion pass.
+pub fn interpolate_step(step: &Step, params: &Parameters) -> Result<Step, ValueError> {
+  Ok(match step {
+    Step::EnvironmentStep(step) => Step::EnvironmentStep(EnvironmentStep {
+      action: step.action.clone(),
+      control: step.control.clone(),
+      parameters: interpolate_parameters(&step.parameters, params)?
+    }),
+    Step::InvokeFunctionStep(step) => Step::InvokeFunctionStep(InvokeFunctionStep {
+      invoke_fn: step.invoke_fn.clone(),
+      control: step.control.clone(),
+      parameters: interpolate_parameters(&step.parameters, params)?
+    }),
+    Step::Null => Step::Null
+  })
+}
\ No newline at end of file
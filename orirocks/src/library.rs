@@ -0,0 +1,179 @@
+//! Function libraries, which share functions between projects. A library is a directory, or a `.tar` or `.tar.gz`
+//! archive of one, holding a `library.yaml` that declares its version and YAML files of `!function` documents:
+//!
+//! ```yaml
+//! !library
+//! - name: docker
+//!   path: vendor/docker-functions.tar.gz
+//!   version: 1.2.0
+//! ```
+//!
+//! Every library is pinned by `version`, by `hash`, or both. The hash is the SHA-256 digest of the library files,
+//! see `digest`. Functions of a library are called as `docker::install`.
+//! Calls without a library inside a library refer to functions of the same library.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use crate::build::Project;
+use crate::diagnostics::Diagnostics;
+use crate::ident::FunctionRef;
+use crate::model::{Document, FunctionDoc, Library, Step};
+use crate::source::SourceMap;
+use crate::util::{format_digest, Located, ORError, ORResult, YamlLocation};
+
+/// Name of the file that declares the version of a library
+pub const LIBRARY_MANIFEST: &str = "library.yaml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LibraryManifest {
+  version: String
+}
+
+/// Contents of the YAML files of a library, by path within the library
+pub type LibraryFiles = BTreeMap<String, String>;
+
+fn is_yaml(path: &str) -> bool {
+  path.ends_with(".yaml")
+}
+
+fn read_directory(dir: &Path, prefix: &str, files: &mut LibraryFiles) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if name.starts_with('.') {
+      continue;
+    }
+    let path = format!("{}{}", prefix, name);
+    if entry.file_type()?.is_dir() {
+      read_directory(&entry.path(), &format!("{}/", path), files)?;
+    } else if is_yaml(&path) {
+      files.insert(path, fs::read_to_string(entry.path())?);
+    }
+  }
+  Ok(())
+}
+
+fn read_archive(path: &Path) -> io::Result<LibraryFiles> {
+  let file = File::open(path)?;
+  let name = path.to_string_lossy();
+  let reader: Box<dyn Read> = if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+    Box::new(GzDecoder::new(file))
+  } else {
+    Box::new(file)
+  };
+  let mut files = LibraryFiles::new();
+  for entry in tar::Archive::new(reader).entries()? {
+    let mut entry = entry?;
+    let path = entry.path()?.to_string_lossy().trim_start_matches("./").to_string();
+    // hidden files and directories are left out, as they are when reading a directory
+    let hidden = path.split('/').any(|v| v.starts_with('.'));
+    if entry.header().entry_type().is_file() && is_yaml(&path) && !hidden {
+      let mut text = String::new();
+      entry.read_to_string(&mut text)?;
+      files.insert(path, text);
+    }
+  }
+  // archives usually hold the library in a directory of its own
+  let top = files.keys()
+    .filter_map(|v| v.strip_suffix(LIBRARY_MANIFEST)?.strip_suffix('/'))
+    .find(|v| !v.contains('/'))
+    .map(|v| format!("{}/", v));
+  match top {
+    Some(top) if !files.contains_key(LIBRARY_MANIFEST) => Ok(files.into_iter()
+      .filter_map(|(k, v)| Some((k.strip_prefix(&top)?.to_string(), v)))
+      .collect()),
+    _ => Ok(files)
+  }
+}
+
+/// Reads the YAML files of the library at `path`, which is either a directory or an archive
+pub fn read_library(path: &Path) -> io::Result<LibraryFiles> {
+  if path.is_dir() {
+    let mut files = LibraryFiles::new();
+    read_directory(path, "", &mut files)?;
+    Ok(files)
+  } else {
+    read_archive(path)
+  }
+}
+
+/// Digest of the files of a library, such as `sha256:9f86d0...`. It does not depend on how the library is packaged.
+pub fn digest(files: &LibraryFiles) -> String {
+  let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+  for (path, text) in files {
+    ctx.update(path.as_bytes());
+    ctx.update(&[0]);
+    ctx.update(&(text.len() as u64).to_le_bytes());
+    ctx.update(text.as_bytes());
+  }
+  format_digest(ctx.finish())
+}
+
+/// Loads the functions of every library of the project, adding them to `Project.functions` as `library::function`.
+/// Library files are added to `sources`, so that errors in them can show the offending line.
+pub fn load_libraries(project: &mut Project, sources: &mut SourceMap, diagnostics: &mut Diagnostics) {
+  let mut libraries = project.libraries.values().cloned().collect::<Vec<_>>();
+  libraries.sort_by(|a, b| a.name.cmp(&b.name));
+  for library in libraries {
+    let result = load_library(project, &library, sources, diagnostics);
+    diagnostics.check(result);
+  }
+}
+
+fn load_library(project: &mut Project, library: &Located<Library>, sources: &mut SourceMap, diagnostics: &mut Diagnostics) -> ORResult<()> {
+  let loc = Located::location(library);
+  let error = |message: String| ORError::InvalidLibrary(loc.clone(), library.name.to_string(), message);
+  if library.version.is_none() && library.hash.is_none() {
+    return Err(error("must be pinned by `version` or `hash`".into()));
+  }
+  let files = read_library(&project.root.join(&library.path))
+    .map_err(|v| error(format!("could not read `{}`: {}", library.path, v)))?;
+  let digest = digest(&files);
+  if let Some(hash) = library.hash.as_ref().filter(|v| **v != digest) {
+    return Err(error(format!("hash is `{}`, but `{}` is required", digest, hash)));
+  }
+  let file_name = |name: &str| format!("{}/{}", library.path.trim_end_matches('/'), name);
+  let manifest = files.get(LIBRARY_MANIFEST)
+    .ok_or_else(|| error(format!("`{}` not found", file_name(LIBRARY_MANIFEST))))?;
+  let manifest: LibraryManifest = serde_yaml::from_str(manifest)
+    .map_err(|v| ORError::YamlError(YamlLocation::new(file_name(LIBRARY_MANIFEST), 0, vec![]), v))?;
+  if let Some(version) = library.version.as_ref().filter(|v| **v != manifest.version) {
+    return Err(error(format!("version is `{}`, but `{}` is required", manifest.version, version)));
+  }
+
+  let mut functions: Vec<Located<FunctionDoc>> = vec![];
+  for (name, text) in files.iter().filter(|v| v.0 != LIBRARY_MANIFEST) {
+    let file = file_name(name);
+    sources.add(file.clone(), text.clone());
+    for (i, document) in serde_yaml::Deserializer::from_str(text).enumerate() {
+      let location = YamlLocation::new(file.clone(), i, vec![]);
+      match Document::deserialize(document) {
+        Ok(Document::Function(function)) => functions.push(Located::new(location, function)),
+        Ok(_) => diagnostics.error(ORError::InvalidLibrary(location, library.name.to_string(), "only functions can be declared in a library".into())),
+        Err(err) => diagnostics.error(ORError::YamlError(location, err))
+      }
+    }
+  }
+  for mut function in functions {
+    for step in &mut function.steps {
+      if let Step::InvokeFunctionStep(call) = step {
+        if call.invoke_fn.library().is_none() {
+          call.invoke_fn = FunctionRef::qualified(&library.name, &call.invoke_fn);
+        }
+      }
+    }
+    let name = FunctionRef::qualified(&library.name, &function.name).to_string();
+    if project.functions.contains_key(&name) {
+      diagnostics.error(ORError::DuplicateSymbol(Located::location(&function).clone(), "function".into(), name));
+      continue;
+    }
+    project.functions.insert(name, function);
+  }
+  project.library_digests.insert(library.name.to_string(), digest);
+  Ok(())
+}
\ No newline at end of file
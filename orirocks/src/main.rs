@@ -2,11 +2,460 @@ mod util;
 mod model;
 mod build;
 mod plugins;
+mod report;
+mod params;
+mod resources;
+mod interpolate;
+mod expand;
+mod vars;
+mod plan;
+mod matrix;
+mod source;
+mod diagnostics;
+mod ident;
+mod manifest;
+mod library;
+mod deploy;
+mod gc;
+mod remote;
+mod store;
 
 #[cfg(test)]
 mod tests;
 
+use std::{env, fs, mem};
+use std::collections::BTreeSet;
+use std::io::{self, Cursor, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use log::{debug, info, warn, LevelFilter};
+use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
+use orirocks_api_v3::CancellationToken;
+use crate::build::{build, check_plugin_options, check_project, collect_project, plan_build, BuildCache, BuildOptions, Project};
+use crate::deploy::{build_required, deployment_status, run_deployments, select_deployments, DeploymentLedger};
+use crate::diagnostics::Diagnostics;
+use crate::gc::{collect_garbage, format_size, parse_size, GcOptions};
+use crate::library::load_libraries;
+use crate::manifest::{RemoteCacheConfig, Workspace};
+use crate::plugins::PluginHive;
+use crate::remote::RemoteCache;
+use crate::report::LogReporter;
+use crate::source::SourceMap;
+use crate::util::{ORError, ORResult};
+use crate::vars::{env_overrides, load_vars_file, resolve_vars, VarOverride};
+
+#[derive(Parser)]
+#[command(version, about = "Builds machine images offline")]
+struct Cli {
+  /// Increases logging verbosity, can be repeated
+  #[arg(short, long, action = ArgAction::Count, global = true)]
+  verbose: u8,
+  #[command(subcommand)]
+  command: Command
+}
+
+/// Arguments for commands that load a project and work out what to build
+#[derive(Args)]
+struct ProjectArgs {
+  /// Project files to read, instead of the files included by `orirocks.yaml`
+  files: Vec<String>,
+  /// Path of the project manifest, found in the current directory or its parents by default
+  #[arg(long, value_name = "FILE", conflicts_with = "files")]
+  manifest_path: Option<PathBuf>,
+  /// Directory to store the build cache and artifacts in, `build` in the project root by default
+  #[arg(long)]
+  build_dir: Option<String>,
+  /// Builds all artifacts regardless of dirty status
+  #[arg(long)]
+  rebuild: bool,
+  /// URL of a remote cache to download outputs from instead of building them, overriding `ORIROCKS_REMOTE_CACHE`
+  /// and the manifest
+  #[arg(long, value_name = "URL")]
+  remote_cache: Option<String>,
+  /// Uploads outputs that were built locally to the remote cache, as does `ORIROCKS_REMOTE_CACHE_UPLOAD=1`
+  #[arg(long)]
+  remote_cache_upload: bool,
+  /// Sets a project variable, overriding the vars file and `ORIROCKS_VAR_*` environment variables
+  #[arg(long = "var", value_name = "KEY=VALUE", value_parser = VarOverride::from_arg)]
+  vars: Vec<VarOverride>,
+  /// YAML file mapping project variables to values
+  #[arg(long, value_name = "FILE")]
+  vars_file: Option<String>
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Builds all dirty artifacts in the project
+  Build {
+    #[command(flatten)]
+    project: ProjectArgs,
+    /// Aborts the build after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>
+  },
+  /// Builds the artifacts that deployments need if they are dirty, then runs the deployments
+  Deploy {
+    #[command(flatten)]
+    project: ProjectArgs,
+    /// Runs only this deployment, can be repeated. Runs every deployment by default.
+    #[arg(long, value_name = "NAME")]
+    only: Vec<String>,
+    /// Deploys even if the artifacts and options did not change since the last deployment
+    #[arg(long)]
+    force: bool,
+    /// Lists deployments whose artifacts or options changed since they were deployed, without building or deploying.
+    /// Exits with an error status if any did.
+    #[arg(long, conflicts_with = "force")]
+    status: bool,
+    /// Aborts building and deploying after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>
+  },
+  /// Shows which artifacts would be built and every step that would run, without building anything
+  Plan {
+    #[command(flatten)]
+    project: ProjectArgs
+  },
+  /// Reports every error and warning in the project without building anything
+  Check {
+    #[command(flatten)]
+    project: ProjectArgs,
+    /// Output format of the diagnostics
+    #[arg(long, value_enum, default_value_t = Format::Human)]
+    format: Format
+  },
+  /// Removes outputs from the build directory that the project and the build cache no longer need
+  Gc {
+    #[command(flatten)]
+    project: ProjectArgs,
+    /// Outputs to keep for every artifact, including the latest one
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    keep_last: u32,
+    /// Evicts the least recently used outputs until the stored outputs take no more than this, such as `20G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Prints what would be removed without removing anything
+    #[arg(long)]
+    dry_run: bool
+  }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Format {
+  /// Source snippets on stderr
+  Human,
+  /// A JSON array on stdout, for editors and CI
+  Json
+}
+
+/// Project sources and the problems found in them
+#[derive(Default)]
+struct Session {
+  sources: SourceMap,
+  diagnostics: Diagnostics,
+  /// Set once an error has been reported
+  failed: bool
+}
+
+impl Session {
+  /// Prints the diagnostics collected so far to stderr, sorted by location
+  fn report(&mut self) {
+    let mut diagnostics = mem::take(&mut self.diagnostics);
+    self.failed |= diagnostics.has_errors();
+    diagnostics.sort(&self.sources);
+    eprint!("{}", diagnostics.render(&self.sources, io::stderr().is_terminal()));
+  }
+}
+
+/// Where the files of a project are and where its outputs go, from the command line and the manifest
+struct ProjectLayout {
+  root: PathBuf,
+  /// Project files, relative to `root`
+  files: Vec<String>,
+  build_dir: String,
+  remote_cache: Option<RemoteCacheConfig>
+}
+
+impl ProjectLayout {
+  /// Uses the files given on the command line, or else the files included by the manifest
+  fn new(args: &ProjectArgs, sources: &mut SourceMap) -> ORResult<Self> {
+    let workspace = match (&args.manifest_path, args.files.is_empty()) {
+      (Some(path), _) => Some(Workspace::load(path, sources)?),
+      (None, true) => Some(Workspace::discover(&env::current_dir().map_err(ORError::IoError)?, sources)?),
+      (None, false) => None
+    };
+    let layout = match workspace {
+      Some(workspace) => {
+        debug!("using manifest `{}`", workspace.manifest_path.display());
+        // files in the build directory, wherever it is, are outputs and not project files
+        let build_dir = args.build_dir.clone().map(PathBuf::from).unwrap_or_else(|| workspace.build_dir());
+        ProjectLayout {
+          files: workspace.files(&build_dir)?,
+          build_dir: build_dir.to_string_lossy().into_owned(),
+          remote_cache: workspace.manifest.remote_cache.clone(),
+          root: workspace.root
+        }
+      }
+      None => ProjectLayout {
+        root: PathBuf::new(),
+        files: args.files.clone(),
+        build_dir: args.build_dir.clone().unwrap_or_else(|| "build".to_string()),
+        remote_cache: None
+      }
+    };
+    // the command line overrides the environment, which overrides the manifest
+    let url = args.remote_cache.clone().or_else(|| env::var("ORIROCKS_REMOTE_CACHE").ok().filter(|v| !v.is_empty()));
+    let upload = args.remote_cache_upload || env::var("ORIROCKS_REMOTE_CACHE_UPLOAD").is_ok_and(|v| v == "1" || v == "true");
+    Ok(ProjectLayout {
+      remote_cache: url
+        .map(|url| RemoteCacheConfig { url, upload: false })
+        .or(layout.remote_cache)
+        .map(|v| RemoteCacheConfig { upload: v.upload || upload, ..v }),
+      ..layout
+    })
+  }
+}
+
+/// Reads the project files, keeping their contents in `sources` so that errors can show the offending lines
+fn read_project_files(root: &Path, files: Vec<String>, sources: &mut SourceMap) -> ORResult<Vec<(String, Box<dyn Read>)>> {
+  files.into_iter()
+    .map(|v| {
+      let text = fs::read_to_string(root.join(&v)).map_err(ORError::IoError)?;
+      sources.add(v.clone(), text.clone());
+      Ok((v, Box::new(Cursor::new(text)) as Box<dyn Read>))
+    })
+    .collect()
+}
+
+/// Creates a cancellation token that is set on Ctrl-C. A second Ctrl-C exits immediately.
+fn cancellation_token(timeout: Option<u64>) -> CancellationToken {
+  let token = timeout
+    .map(|v| CancellationToken::with_timeout(Duration::from_secs(v)))
+    .unwrap_or_default();
+  let handler_token = token.clone();
+  let result = ctrlc::set_handler(move || {
+    if handler_token.is_cancelled() {
+      process::exit(130);
+    }
+    warn!("cancelling build, press Ctrl-C again to exit immediately");
+    handler_token.cancel();
+  });
+  if let Err(err) = result {
+    warn!("could not install Ctrl-C handler: {}", err);
+  }
+  token
+}
+
+/// Collects variable overrides in order of increasing precedence: vars file, environment, command line
+fn var_overrides(vars_file: Option<String>, vars: Vec<VarOverride>) -> ORResult<Vec<VarOverride>> {
+  let mut overrides = match vars_file {
+    Some(path) => load_vars_file(&path)?,
+    None => vec![]
+  };
+  overrides.extend(env_overrides(env::vars()));
+  overrides.extend(vars);
+  Ok(overrides)
+}
+
+/// Reads, resolves and validates the project, collecting every problem in the session.
+/// Returns `None` if there are errors.
+fn load_project(args: ProjectArgs, layout: &ProjectLayout, session: &mut Session) -> ORResult<Option<Project>> {
+  let overrides = var_overrides(args.vars_file, args.vars)?;
+  let files = read_project_files(&layout.root, layout.files.clone(), &mut session.sources)?;
+  let mut project = collect_project(files, &mut session.diagnostics);
+  project.root = layout.root.clone();
+  load_libraries(&mut project, &mut session.sources, &mut session.diagnostics);
+  if session.diagnostics.has_errors() {
+    return Ok(None);
+  }
+  resolve_vars(&mut project, overrides)?;
+  check_project(&project, &mut session.diagnostics);
+  Ok(Some(project).filter(|_| !session.diagnostics.has_errors()))
+}
+
+fn load_plugins() -> PluginHive {
+  let plugins = PluginHive::new();
+  debug!("loaded {} environment providers and {} deployment providers", plugins.environments().len(), plugins.deployments().len());
+  plugins
+}
+
+/// Connects to the remote cache of the layout, authenticating with `ORIROCKS_REMOTE_CACHE_TOKEN` if it is set
+fn remote_cache(layout: &ProjectLayout) -> Option<RemoteCache> {
+  let config = layout.remote_cache.as_ref()?;
+  debug!("using remote cache `{}`", config.url);
+  Some(RemoteCache::new(&config.url, config.upload, env::var("ORIROCKS_REMOTE_CACHE_TOKEN").ok()))
+}
+
+fn run_build(args: ProjectArgs, timeout: Option<u64>, session: &mut Session) -> ORResult<()> {
+  let layout = ProjectLayout::new(&args, &mut session.sources)?;
+  let opts = BuildOptions {
+    rebuild: args.rebuild,
+    build_dir: layout.build_dir.clone(),
+    cancel: cancellation_token(timeout),
+    remote_cache: remote_cache(&layout)
+  };
+  let project = load_project(args, &layout, session)?;
+  session.report();
+  let project = match project {
+    Some(project) => project,
+    None => return Ok(())
+  };
+  let plugins = load_plugins();
+  let build_cache = BuildCache::load(&opts.build_dir)?;
+  let build_cache = build(&project, build_cache, &opts, &plugins, &LogReporter::new())?;
+  build_cache.save(&opts.build_dir)
+}
+
+/// Options of `orirocks deploy`
+struct DeployArgs {
+  only: Vec<String>,
+  force: bool,
+  status: bool,
+  timeout: Option<u64>
+}
+
+fn run_deploy(args: ProjectArgs, deploy: DeployArgs, session: &mut Session) -> ORResult<()> {
+  let layout = ProjectLayout::new(&args, &mut session.sources)?;
+  let opts = BuildOptions {
+    rebuild: args.rebuild,
+    build_dir: layout.build_dir.clone(),
+    cancel: cancellation_token(deploy.timeout),
+    remote_cache: remote_cache(&layout)
+  };
+  let project = load_project(args, &layout, session)?;
+  session.report();
+  let project = match project {
+    Some(project) => project,
+    None => return Ok(())
+  };
+  let deploys = select_deployments(&project, &deploy.only)?;
+  if deploys.is_empty() {
+    info!("the project has no deployments");
+    return Ok(());
+  }
+  let mut ledger = DeploymentLedger::load(&opts.build_dir)?;
+  let plugins = load_plugins();
+  if deploy.status {
+    for (name, drift) in deployment_status(&project, &deploys, &opts, &plugins, &ledger)? {
+      match (drift.is_empty(), ledger.get(&name)) {
+        (true, Some(entry)) => println!("{}: up to date, deployed {}", name, entry.time),
+        _ => println!("{}: {}", name, drift.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "))
+      }
+      session.failed |= !drift.is_empty();
+    }
+    return Ok(());
+  }
+  let reporter = LogReporter::new();
+  let build_cache = BuildCache::load(&opts.build_dir)?;
+  let build_cache = build_required(&project, &deploys, build_cache, &opts, &plugins, &reporter)?;
+  build_cache.save(&opts.build_dir)?;
+  // rebuilt artifacts are deployed again, as `BuildOptions.rebuild` promises
+  let force = deploy.force || opts.rebuild;
+  let result = run_deployments(&project, &deploys, &opts, force, &plugins, &reporter, &mut ledger);
+  ledger.save(&opts.build_dir)?;
+  result
+}
+
+fn run_plan(args: ProjectArgs, session: &mut Session) -> ORResult<()> {
+  let layout = ProjectLayout::new(&args, &mut session.sources)?;
+  let rebuild = args.rebuild;
+  let project = load_project(args, &layout, session)?;
+  session.report();
+  let project = match project {
+    Some(project) => project,
+    None => return Ok(())
+  };
+  let build_cache = BuildCache::load(&layout.build_dir)?;
+  let (plan, _) = plan_build(&project, build_cache, rebuild, &load_plugins())?;
+  print!("{}", plan);
+  Ok(())
+}
+
+fn run_check(args: ProjectArgs, format: Format, session: &mut Session) -> ORResult<()> {
+  let loaded = ProjectLayout::new(&args, &mut session.sources)
+    .and_then(|layout| load_project(args, &layout, session))
+    .map(|project| {
+      // plugin options are only checked once the rest of the project is valid
+      if let Some(project) = project {
+        session.diagnostics.check(check_plugin_options(&project, &load_plugins()));
+      }
+    });
+  if format == Format::Json {
+    // tools reading the output expect JSON even when the project could not be read
+    if let Err(err) = loaded {
+      session.diagnostics.error(err);
+    }
+    session.diagnostics.sort(&session.sources);
+    println!("{}", session.diagnostics.to_json(&session.sources));
+    session.failed = session.diagnostics.has_errors();
+    session.diagnostics = Diagnostics::default();
+    return Ok(());
+  }
+  loaded?;
+  if session.diagnostics.items().is_empty() {
+    info!("no problems found");
+  }
+  Ok(())
+}
+
+fn run_gc(args: ProjectArgs, opts: GcOptions, session: &mut Session) -> ORResult<()> {
+  let layout = ProjectLayout::new(&args, &mut session.sources)?;
+  let project = load_project(args, &layout, session)?;
+  session.report();
+  // an unreadable project would make every output look unreferenced
+  let project = match project {
+    Some(project) => project,
+    None => return Ok(())
+  };
+  let mut build_cache = BuildCache::load(&layout.build_dir)?;
+  let live = project.builds.keys()
+    .chain(build_cache.iter().flat_map(|v| v.artifacts()))
+    .cloned()
+    .collect::<BTreeSet<_>>();
+  let report = collect_garbage(&layout.build_dir, &live, &opts)?;
+  let verb = if opts.dry_run { "would remove" } else { "removed" };
+  for garbage in &report.removed {
+    println!("{} {}", verb, garbage);
+  }
+  // evicted artifacts are dirty again, so that the next build replaces their outputs
+  if !opts.dry_run && !report.evicted_artifacts.is_empty() {
+    if let Some(build_cache) = &mut build_cache {
+      for name in &report.evicted_artifacts {
+        build_cache.forget(name);
+      }
+      build_cache.save(&layout.build_dir)?;
+    }
+  }
+  for name in &report.evicted_artifacts {
+    warn!("the latest output of `{}` {} evicted, the next build rebuilds it", name, if opts.dry_run { "would be" } else { "was" });
+  }
+  info!("{} {}, keeping {}", if opts.dry_run { "would free" } else { "freed" }, format_size(report.freed()), format_size(report.kept_size));
+  Ok(())
+}
 
 fn main() {
-    println!("Hello, world!");
+  let cli = Cli::parse();
+  let level = match cli.verbose {
+    0 => LevelFilter::Info,
+    1 => LevelFilter::Debug,
+    _ => LevelFilter::Trace
+  };
+  TermLogger::init(level, Config::default(), TerminalMode::Stderr, ColorChoice::Auto).unwrap();
+  let mut session = Session::default();
+  let result = match cli.command {
+    Command::Build { project, timeout } => run_build(project, timeout, &mut session),
+    Command::Deploy { project, only, force, status, timeout } => run_deploy(project, DeployArgs { only, force, status, timeout }, &mut session),
+    Command::Plan { project } => run_plan(project, &mut session),
+    Command::Check { project, format } => run_check(project, format, &mut session),
+    Command::Gc { project, keep_last, max_size, dry_run } => run_gc(project, GcOptions { keep_last: keep_last as usize, max_size, dry_run }, &mut session)
+  };
+  if let Err(err) = result {
+    session.diagnostics.error(err);
+  }
+  session.report();
+  if session.failed {
+    process::exit(1);
+  }
 }
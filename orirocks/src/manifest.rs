@@ -0,0 +1,135 @@
+//! The project manifest, `orirocks.yaml`, marks the root of a project and says which files make it up.
+//! The CLI looks for it in the current directory and its parents, the way cargo finds `Cargo.toml`.
+//!
+//! ```yaml
+//! include: ["images/**/*.yaml", "functions.yaml"]
+//! exclude: ["images/old/*"]
+//! build_dir: out
+//! remote_cache:
+//!   url: https://cache.example.com/orirocks
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use glob::{MatchOptions, Pattern};
+use log::warn;
+use serde::Deserialize;
+use crate::source::SourceMap;
+use crate::util::{ORError, ORResult, YamlLocation};
+
+/// Name of the file that marks the root of a project
+pub const MANIFEST_FILE: &str = "orirocks.yaml";
+
+/// Settings of a project. Paths other than `root` are relative to the project root.
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+  /// Root of the project, relative to the manifest. Defaults to the directory of the manifest.
+  #[serde(default)]
+  pub root: Option<PathBuf>,
+  /// Globs matching the project files. Files in hidden directories are only matched if named explicitly.
+  #[serde(default = "default_include")]
+  pub include: Vec<String>,
+  /// Globs matching files to leave out, even if they are included
+  #[serde(default)]
+  pub exclude: Vec<String>,
+  /// Directory to store the build cache and artifacts in. Files in it are never included.
+  #[serde(default = "default_build_dir")]
+  pub build_dir: PathBuf,
+  /// Server to share build outputs with, see `remote`
+  #[serde(default)]
+  pub remote_cache: Option<RemoteCacheConfig>
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteCacheConfig {
+  pub url: String,
+  /// Uploads outputs that were built locally, usually only set on CI
+  #[serde(default)]
+  pub upload: bool
+}
+
+fn default_include() -> Vec<String> {
+  vec!["**/*.yaml".to_string()]
+}
+
+fn default_build_dir() -> PathBuf {
+  PathBuf::from("build")
+}
+
+/// A project found through its manifest
+#[derive(Clone, Debug)]
+pub struct Workspace {
+  pub manifest_path: PathBuf,
+  /// Directory the paths of the manifest are relative to
+  pub root: PathBuf,
+  pub manifest: Manifest
+}
+
+impl Workspace {
+  /// Reads the manifest at `path`, keeping its contents in `sources` so that errors can show the offending line
+  pub fn load(path: &Path, sources: &mut SourceMap) -> ORResult<Self> {
+    let text = fs::read_to_string(path).map_err(ORError::IoError)?;
+    let file = path.display().to_string();
+    sources.add(file.clone(), text.clone());
+    let manifest: Manifest = serde_yaml::from_str(&text)
+      .map_err(|v| ORError::YamlError(YamlLocation::new(file.clone(), 0, vec![]), v))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let root = match &manifest.root {
+      Some(root) => dir.join(root),
+      None => dir.to_path_buf()
+    };
+    Ok(Workspace { manifest_path: path.to_path_buf(), root, manifest })
+  }
+
+  /// Finds the manifest in `dir` or the closest of its parents
+  pub fn discover(dir: &Path, sources: &mut SourceMap) -> ORResult<Self> {
+    let path = dir.ancestors()
+      .map(|v| v.join(MANIFEST_FILE))
+      .find(|v| v.is_file())
+      .ok_or_else(|| ORError::ManifestNotFound(dir.display().to_string()))?;
+    Self::load(&path, sources)
+  }
+
+  /// Lists the project files relative to the root, in the order of the include globs and then by name.
+  /// Files in `build_dir`, which may differ from the build directory of the manifest, are left out.
+  pub fn files(&self, build_dir: &Path) -> ORResult<Vec<String>> {
+    let options = MatchOptions { require_literal_leading_dot: true, ..Default::default() };
+    let invalid = |pattern: &str, err: glob::PatternError| {
+      ORError::ManifestError(self.manifest_path.display().to_string(), format!("invalid glob `{}`: {}", pattern, err))
+    };
+    let exclude = self.manifest.exclude.iter()
+      .map(|v| Pattern::new(v).map_err(|err| invalid(v, err)))
+      .collect::<ORResult<Vec<_>>>()?;
+    let manifest = fs::canonicalize(&self.manifest_path).ok();
+    let build_dir = fs::canonicalize(build_dir).ok();
+    let mut seen = HashSet::new();
+    let mut files = vec![];
+    for include in &self.manifest.include {
+      let pattern = Path::new(&Pattern::escape(&self.root.to_string_lossy())).join(include);
+      let paths = glob::glob_with(&pattern.to_string_lossy(), options).map_err(|err| invalid(include, err))?;
+      for path in paths {
+        let path = path.map_err(|v| ORError::IoError(v.into()))?;
+        let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+        let real = fs::canonicalize(&path).ok();
+        let skip = !path.is_file()
+          || real == manifest
+          || real.as_ref().zip(build_dir.as_ref()).is_some_and(|(path, dir)| path.starts_with(dir))
+          || exclude.iter().any(|v| v.matches_path_with(relative, options));
+        if !skip && seen.insert(relative.to_path_buf()) {
+          files.push(relative.to_string_lossy().into_owned());
+        }
+      }
+    }
+    if files.is_empty() {
+      warn!("no project files match the include globs of `{}`", self.manifest_path.display());
+    }
+    Ok(files)
+  }
+
+  pub fn build_dir(&self) -> PathBuf {
+    self.root.join(&self.manifest.build_dir)
+  }
+}
\ No newline at end of file
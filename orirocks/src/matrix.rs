@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+use orirocks_api_v3::{Value, ValueError};
+use crate::build::Project;
+use crate::diagnostics::Diagnostics;
+use crate::ident::{is_selector_value, ArtifactName};
+use crate::model::{BuildDoc, Parameter, ParameterSpec, Parameters, Variant};
+use crate::params::infer_type;
+use crate::util::{Located, ORError, ORResult, YamlLocation};
+
+/// Matrix values are referenced as `${matrix.axis}`
+pub const MATRIX_PREFIX: &str = "matrix.";
+
+/// Formats a matrix value for use in an artifact name
+fn format_axis_value(value: &Value) -> String {
+  match value {
+    Value::String(v) => v.clone(),
+    Value::Integer(v) => v.to_string(),
+    Value::Bool(v) => v.to_string(),
+    v => v.type_name().to_string()
+  }
+}
+
+/// Returns the name of a variant, such as `my_image[arch=aarch64,ver=3.17]`. Axes are sorted by name.
+pub fn variant_name(matrix: &str, values: &Parameters) -> String {
+  let values = values.iter()
+    .map(|(k, v)| format!("{}={}", k, format_axis_value(v)))
+    .collect::<Vec<_>>();
+  format!("{}[{}]", matrix, values.join(","))
+}
+
+/// Expands a build with a `matrix` into one build per combination of values.
+/// Values must be strings, integers or bools made of letters, digits, `_`, `-` and `.`, so that they can be part of
+/// artifact names. Floats are rejected, since `3.10` would be named `3.1`; they can be quoted as strings instead.
+/// Values that are named alike, such as `1` and `"1"`, are rejected, since their variants would share a name.
+pub fn expand_matrix(build: BuildDoc, loc: &YamlLocation) -> ORResult<Vec<BuildDoc>> {
+  let matrix = match &build.matrix {
+    Some(matrix) => matrix,
+    None => return Ok(vec![build])
+  };
+  let invalid = |err: ValueError, axis: &str| ORError::InvalidMatrix(loc.clone(), err.at_key(axis));
+  let mut combinations = vec![Parameters::new()];
+  for (axis, values) in matrix {
+    if values.is_empty() {
+      return Err(invalid(ValueError::new("axis has no values"), axis));
+    }
+    if let Some((i, v)) = values.iter().enumerate().find(|(_, v)| !matches!(v, Value::String(_) | Value::Integer(_) | Value::Bool(_))) {
+      return Err(invalid(ValueError::type_mismatch("string, integer or bool", v).at_index(i), axis));
+    }
+    let mut names = HashSet::new();
+    for (i, v) in values.iter().map(format_axis_value).enumerate() {
+      if !is_selector_value(&v) {
+        let err = ValueError::new(format!("invalid value `{}`, expected letters, digits, `_`, `-` and `.`", v));
+        return Err(invalid(err.at_index(i), axis));
+      }
+      if !names.insert(v.clone()) {
+        let err = ValueError::new(format!("value `{}` appears twice, so two variants would have the same name", v));
+        return Err(invalid(err.at_index(i), axis));
+      }
+    }
+    combinations = combinations.into_iter()
+      .flat_map(|combination| values.iter().map(move |v| {
+        let mut combination = combination.clone();
+        combination.insert(axis.clone(), v.clone());
+        combination
+      }))
+      .collect();
+  }
+  Ok(combinations.into_iter()
+    .map(|values| BuildDoc {
+      name: ArtifactName::new_unchecked(variant_name(&build.name, &values)),
+      matrix: None,
+      variant: Some(Variant { matrix: build.name.to_string(), values }),
+      ..build.clone()
+    })
+    .collect())
+}
+
+/// Values of the matrix axes of an artifact, under the names they are referenced by
+pub fn matrix_params(build: &BuildDoc) -> Parameters {
+  build.variant.iter()
+    .flat_map(|v| v.values.iter())
+    .map(|(k, v)| (format!("{}{}", MATRIX_PREFIX, k), v.clone()))
+    .collect()
+}
+
+/// Types of the matrix axes of an artifact, under the names they are referenced by
+pub fn matrix_scope(build: &BuildDoc) -> ParameterSpec {
+  matrix_params(build).into_iter()
+    .map(|(k, v)| (k, Parameter { type_: infer_type(&v), default: None }))
+    .collect()
+}
+
+/// Splits a reference such as `my_image[arch=aarch64]` into the matrix name and the selected values
+fn parse_reference(reference: &str) -> (&str, Vec<(&str, &str)>) {
+  let selectors = reference.strip_suffix(']')
+    .and_then(|v| v.split_once('['));
+  match selectors {
+    Some((name, selectors)) => (name, selectors.split(',')
+      .filter(|v| !v.trim().is_empty())
+      .map(|v| v.split_once('=').map(|(k, v)| (k.trim(), v.trim())).unwrap_or((v.trim(), "")))
+      .collect()),
+    None => (reference, vec![])
+  }
+}
+
+/// Returns the variants that a `from` or `depends` reference selects.
+/// References to plain artifacts are returned as they are.
+/// Axes that are not selected explicitly but that the referencing artifact shares take its values,
+/// so that `from: base` in `my_image[arch=aarch64]` refers to `base[arch=aarch64]`.
+fn select_variants(project: &Project, reference: &str, referrer: Option<&Variant>, loc: &YamlLocation) -> ORResult<Vec<String>> {
+  if project.builds.contains_key(reference) {
+    return Ok(vec![reference.to_string()]);
+  }
+  let (name, selectors) = parse_reference(reference);
+  let variants = match project.matrices.get(name) {
+    Some(variants) => variants,
+    None => return Ok(vec![reference.to_string()])
+  };
+  let mut selected = vec![];
+  for variant_name in variants {
+    let variant = project.builds[variant_name].variant.as_ref().unwrap();
+    for (axis, _) in &selectors {
+      if !variant.values.contains_key(*axis) {
+        return Err(ORError::MatrixReference(loc.clone(), format!("matrix `{}` has no axis `{}`", name, axis)));
+      }
+    }
+    let explicit = selectors.iter().all(|(axis, value)| format_axis_value(&variant.values[*axis]) == *value);
+    let shared = variant.values.iter()
+      .filter(|(axis, _)| !selectors.iter().any(|v| v.0 == axis.as_str()))
+      .all(|(axis, value)| referrer.and_then(|v| v.values.get(axis)).map(|v| v == value).unwrap_or(true));
+    if explicit && shared {
+      selected.push(variant_name.clone());
+    }
+  }
+  if selected.is_empty() {
+    return Err(ORError::MatrixReference(loc.clone(), format!("no variant of `{}` matches `{}`", name, reference)));
+  }
+  Ok(selected)
+}
+
+/// Replaces references to matrices in `from`, `depends` and the artifacts of deployments with the names of the selected variants.
+/// `from` must select exactly one variant, the others may select several.
+/// Artifacts with invalid references are reported and left unchanged.
+pub fn resolve_matrix_references(project: &mut Project, diagnostics: &mut Diagnostics) {
+  fn resolve(project: &Project, build: &Located<BuildDoc>) -> ORResult<(Option<String>, Option<Vec<String>>)> {
+    let loc = Located::location(build);
+    let from = match &build.from {
+      Some(from) => {
+        let mut selected = select_variants(project, from, build.variant.as_ref(), loc)?;
+        if selected.len() > 1 {
+          return Err(ORError::MatrixReference(loc.clone(), format!("`from: {}` selects {} variants, select one like `{}`", from, selected.len(), selected[0])));
+        }
+        selected.pop()
+      }
+      None => None
+    };
+    let depends = match &build.depends {
+      Some(depends) => Some(depends.iter()
+        .map(|v| select_variants(project, v, build.variant.as_ref(), loc))
+        .collect::<ORResult<Vec<_>>>()?
+        .concat()),
+      None => None
+    };
+    Ok((from, depends))
+  }
+
+  let mut resolved = vec![];
+  for (name, build) in &project.builds {
+    if let Some((from, depends)) = diagnostics.check(resolve(project, build)) {
+      resolved.push((name.clone(), from, depends));
+    }
+  }
+  for (name, from, depends) in resolved {
+    let build = project.builds.get_mut(&name).unwrap();
+    build.from = from.map(ArtifactName::new_unchecked);
+    build.depends = depends.map(|v| v.into_iter().map(ArtifactName::new_unchecked).collect());
+  }
+
+  let mut resolved = vec![];
+  for (name, deploy) in &project.deploys {
+    let artifacts = deploy.artifacts.iter()
+      .map(|v| select_variants(project, v, None, Located::location(deploy)))
+      .collect::<ORResult<Vec<_>>>();
+    if let Some(artifacts) = diagnostics.check(artifacts) {
+      resolved.push((name.clone(), artifacts.concat()));
+    }
+  }
+  for (name, artifacts) in resolved {
+    project.deploys.get_mut(&name).unwrap().artifacts = artifacts.into_iter().map(ArtifactName::new_unchecked).collect();
+  }
+}
\ No newline at end of file
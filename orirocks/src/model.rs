@@ -1,6 +1,7 @@
-use std::collections::{BTreeMap, HashMap};
-use serde::{Deserialize, Serialize};
-use orirocks_api_v3::{CmpFloat, Value, ValueType};
+use std::collections::BTreeMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use orirocks_api_v3::{Value, ValueType};
+use crate::ident::{ArtifactName, FunctionRef, Ident, ImportRef};
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Document {
@@ -9,41 +10,97 @@ pub enum Document {
   #[serde(rename = "function")]
   Function(FunctionDoc),
   #[serde(rename = "build")]
-  Build(BuildDoc)
+  Build(BuildDoc),
+  #[serde(rename = "vars")]
+  Vars(VarsDoc),
+  #[serde(rename = "library")]
+  Library(LibraryDoc),
+  #[serde(rename = "deploy")]
+  Deploy(DeployDoc)
 }
 
 pub type ImportDoc = Vec<Import>;
 
+/// Declares function libraries, see `library::load_libraries`
+pub type LibraryDoc = Vec<Library>;
+
+/// Declares project variables, which builds and functions reference as `${vars.name}`
+pub type VarsDoc = ParameterSpec;
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct FunctionDoc {
-  pub name: String,
+  pub name: Ident,
   pub parameter_spec: ParameterSpec,
   pub steps: Vec<Step>
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct BuildDoc {
-  pub name: String,
-  pub from: Option<String>,
-  pub depends: Option<Vec<String>>,
-  pub envs: Vec<Environment>
+  pub name: ArtifactName,
+  pub from: Option<ArtifactName>,
+  pub depends: Option<Vec<ArtifactName>>,
+  /// Builds one artifact per combination of values, see `matrix::expand_matrix`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub matrix: Option<Matrix>,
+  pub envs: Vec<Environment>,
+  /// Set on artifacts expanded from a matrix
+  #[serde(skip)]
+  pub variant: Option<Variant>
+}
+
+/// Publishes artifacts with a deployment provider. Deployments only run with `orirocks deploy`, never while building.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct DeployDoc {
+  pub name: Ident,
+  /// Deployment provider, such as `registry/oci`
+  pub provider: ImportRef,
+  /// Artifacts to publish. A build matrix stands for all of its variants, or for those its selectors match.
+  pub artifacts: Vec<ArtifactName>,
+  /// Options of the provider, which may reference project variables
+  #[serde(flatten)]
+  pub options: Parameters
+}
+
+/// Axes of a build matrix, each with the values to build for
+pub type Matrix = BTreeMap<String, Vec<Value>>;
+
+/// One combination of values of a build matrix
+#[derive(Default, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Variant {
+  /// Name of the matrix, which `from` and `depends` use to refer to all of its artifacts
+  pub matrix: String,
+  pub values: Parameters
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Import {
-  pub require: String,
+  pub require: ImportRef,
   pub version: String
 }
 
+/// A directory or archive of functions shared between projects. Its functions are called as `name::function`.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Library {
+  pub name: Ident,
+  /// Directory, `.tar` or `.tar.gz` archive, relative to the project root
+  pub path: String,
+  /// Version that the `library.yaml` of the library must declare
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub version: Option<String>,
+  /// Digest of the library contents, such as `sha256:9f86d0...`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub hash: Option<String>
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Environment {
-  pub name: String,
+  pub name: ImportRef,
   #[serde(flatten)]
   pub parameters: Parameters,
   pub steps: Vec<Step>
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Serialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 #[serde(untagged)]
 pub enum Step {
   EnvironmentStep(EnvironmentStep),
@@ -52,20 +109,55 @@ pub enum Step {
   Null
 }
 
+/// Picks the kind of step by its keys rather than trying each in turn,
+/// so that errors such as an invalid action name are reported as they are.
+impl<'de> Deserialize<'de> for Step {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let value = serde_yaml::Value::deserialize(deserializer)?;
+    let result = if value.is_null() {
+      return Ok(Step::Null);
+    } else if value.get("action").is_none() && value.get("invoke_fn").is_some() {
+      InvokeFunctionStep::deserialize(value).map(Step::InvokeFunctionStep)
+    } else {
+      EnvironmentStep::deserialize(value).map(Step::EnvironmentStep)
+    };
+    result.map_err(serde::de::Error::custom)
+  }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct EnvironmentStep {
-  pub action: String,
+  pub action: Ident,
+  #[serde(flatten)]
+  pub control: StepControl,
   #[serde(flatten)]
   pub parameters: Parameters
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct InvokeFunctionStep {
-  pub invoke_fn: String,
+  pub invoke_fn: FunctionRef,
+  #[serde(flatten)]
+  pub control: StepControl,
   #[serde(flatten)]
   pub parameters: Parameters
 }
 
+/// Decides whether and how often a step runs. Evaluated at plan time, when function calls are expanded.
+/// Must be declared before the flattened parameters, so that its keys are taken out first.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct StepControl {
+  /// Runs the step only if this is true, or a comparison such as `${vars.arch} == aarch64` that holds
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub when: Option<Value>,
+  /// Runs the step once for every element of this array
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub for_each: Option<Value>,
+  /// Name that the current element is referenced by, `item` by default
+  #[serde(rename = "as", default, skip_serializing_if = "Option::is_none")]
+  pub as_: Option<Ident>
+}
+
 pub type ParameterSpec = BTreeMap<String, Parameter>;
 pub type Parameters = BTreeMap<String, Value>;
 
@@ -75,7 +167,3 @@ pub struct Parameter {
   pub type_: ValueType,
   pub default: Option<Value>
 }
-
-/// Represents an reference to an object imported from a plugin
-/// It is always in the form of [a-zA-Z0-9_-]/[a-zA-Z0-9_-]
-pub type ImportRef = String;
\ No newline at end of file
@@ -0,0 +1,177 @@
+use orirocks_api_v3::{Value, ValueError, ValueType};
+use crate::expand::{evaluate_condition, DEFAULT_LOOP_VARIABLE};
+use crate::interpolate::references;
+use crate::model::{Parameter, ParameterSpec, Parameters, StepControl};
+use crate::util::{validate_identifier, ORError, ORResult, YamlLocation};
+use crate::vars::VARS_PREFIX;
+
+/// Checks the parameters of a function call against the function's `parameter_spec`,
+/// and fills in defaults for parameters that were not passed.
+/// Parameters without a default are required, unless they are of type `optional`.
+pub fn resolve_parameters(spec: &ParameterSpec, params: &Parameters, loc: &YamlLocation) -> ORResult<Parameters> {
+  check_call(spec, params, None, loc)
+}
+
+/// Checks a function call made from inside another function, before parameters are substituted.
+/// Values that reference the caller's parameters are checked against the caller's `parameter_spec`.
+pub fn validate_call_in_function(caller: &ParameterSpec, spec: &ParameterSpec, params: &Parameters, loc: &YamlLocation) -> ORResult<()> {
+  check_call(spec, params, Some(caller), loc).map(|_| ())
+}
+
+fn check_call(spec: &ParameterSpec, params: &Parameters, caller: Option<&ParameterSpec>, loc: &YamlLocation) -> ORResult<Parameters> {
+  if let Some(unknown) = params.keys().find(|v| !spec.contains_key(*v)) {
+    return Err(ORError::UnknownParameter(loc.clone(), unknown.clone()));
+  }
+  let mut resolved = Parameters::new();
+  for (name, param) in spec {
+    let value = match params.get(name).or(param.default.as_ref()) {
+      Some(value) => value,
+      None if param.type_.is_optional() => continue,
+      None => return Err(ORError::MissingParameter(loc.clone(), name.clone()))
+    };
+    let templated = caller
+      .filter(|_| params.contains_key(name))
+      .filter(|_| references(value).map(|v| !v.is_empty()).unwrap_or(true));
+    if let Some(caller) = templated {
+      if let Some(ty) = check_references(caller, name, value, loc)? {
+        if !param.type_.accepts(&ty) {
+          let err = ValueError::new(format!("expected {}, found parameter of type {}", param.type_, ty));
+          return Err(ORError::TypeMismatch(loc.clone(), err.at_key(name)));
+        }
+      }
+    } else {
+      param.type_.check(value)
+        .map_err(|v| ORError::TypeMismatch(loc.clone(), v.at_key(name)))?;
+    }
+    resolved.insert(name.clone(), value.clone());
+  }
+  Ok(resolved)
+}
+
+/// Checks the references to the caller's parameters inside `value`, which is passed as the option `key`.
+/// Returns the type of the referenced parameter if `value` consists of a single reference.
+pub fn check_references(caller: &ParameterSpec, key: &str, value: &Value, loc: &YamlLocation) -> ORResult<Option<ValueType>> {
+  let references = references(value)
+    .map_err(|v| ORError::InterpolationError(loc.clone(), v.at_key(key)))?;
+  let mut whole_type = None;
+  for reference in references {
+    let err = |message: String| ORError::InterpolationError(loc.clone(), ValueError { path: reference.path.clone(), message }.at_key(key));
+    let param = caller.get(&reference.name).ok_or_else(|| err(match reference.name.strip_prefix(VARS_PREFIX) {
+      Some(name) => format!("unknown variable `{}`", name),
+      None => format!("unknown parameter `{}`", reference.name)
+    }))?;
+    if !reference.whole && !param.type_.is_scalar() {
+      return Err(err(format!("parameter `{}` is {} and cannot be embedded in a string", reference.name, param.type_)));
+    }
+    if reference.whole && reference.path.is_empty() {
+      whole_type = Some(param.type_.clone());
+    }
+  }
+  Ok(whole_type)
+}
+
+/// Checks the `when` and `for_each` of a step, before parameters are substituted.
+/// Returns the scope of the step, which includes the loop variable if the step is a loop.
+pub fn check_control(scope: &ParameterSpec, control: &StepControl, loc: &YamlLocation) -> ORResult<ParameterSpec> {
+  let mismatch = |key: &str, message: String| ORError::TypeMismatch(loc.clone(), ValueError::new(message).at_key(key));
+  if let Some(when) = &control.when {
+    let bool_type = ValueType::Optional { inner: Box::new(ValueType::Bool) };
+    match check_references(scope, "when", when, loc)? {
+      Some(ty) if !bool_type.accepts(&ty) => return Err(mismatch("when", format!("expected bool, found parameter of type {}", ty))),
+      Some(_) => {}
+      None if references(when).map(|v| v.is_empty()).unwrap_or(false) => {
+        evaluate_condition(when).map_err(|v| ORError::TypeMismatch(loc.clone(), v.at_key("when")))?;
+      }
+      None => {
+        let s = when.as_str().unwrap_or_default();
+        if !s.contains("==") && !s.contains("!=") {
+          return Err(mismatch("when", format!("expected bool or comparison like `a == b`, found `{}`", s)));
+        }
+      }
+    }
+  }
+  let for_each = match &control.for_each {
+    Some(v) => v,
+    None => return Ok(scope.clone())
+  };
+  let name = control.as_.as_deref().unwrap_or(DEFAULT_LOOP_VARIABLE);
+  let element_type = match (check_references(scope, "for_each", for_each, loc)?, for_each) {
+    (Some(ty), _) => element_type(&ty)
+      .ok_or_else(|| mismatch("for_each", format!("expected array, found parameter of type {}", ty)))?,
+    (None, Value::Array(items)) => {
+      let mut element_type = None;
+      for (i, item) in items.iter().enumerate() {
+        let ty = match references(item).ok().filter(|v| v.len() == 1 && v[0].whole && v[0].path.is_empty()) {
+          Some(refs) => scope[&refs[0].name].type_.clone(),
+          None => infer_type(item)
+        };
+        element_type = Some(match element_type {
+          None => ty,
+          Some(prev) => common_type(prev, ty)
+            .ok_or_else(|| mismatch("for_each", format!("element #{} has a different type than the elements before it", i)))?
+        });
+      }
+      element_type.unwrap_or_default()
+    }
+    (None, v) => return Err(ORError::TypeMismatch(loc.clone(), ValueError::type_mismatch("array", v).at_key("for_each")))
+  };
+  let mut scope = scope.clone();
+  scope.insert(name.to_string(), Parameter { type_: element_type, default: None });
+  Ok(scope)
+}
+
+/// Returns the type of the elements of an array type, which may be optional
+fn element_type(ty: &ValueType) -> Option<ValueType> {
+  match ty {
+    ValueType::Array { inner } => Some((**inner).clone()),
+    ValueType::Optional { inner } => element_type(inner),
+    _ => None
+  }
+}
+
+/// Works out the type of a value written in a project file, such as an element of a `for_each` array
+pub fn infer_type(value: &Value) -> ValueType {
+  match value {
+    Value::Null => ValueType::Optional { inner: Box::new(ValueType::String) },
+    Value::Bool(_) => ValueType::Bool,
+    Value::Integer(_) => ValueType::Integer,
+    Value::Float(_) => ValueType::Float,
+    Value::String(_) => ValueType::String,
+    Value::Array(items) => ValueType::Array {
+      inner: Box::new(items.iter().map(infer_type).reduce(|a, b| common_type(a, b).unwrap_or_default()).unwrap_or_default())
+    },
+    Value::Dict(values) => ValueType::Record {
+      fields: values.iter().map(|(k, v)| (k.clone(), infer_type(v))).collect()
+    }
+  }
+}
+
+/// Returns the more general of two types, or `None` if neither accepts the other
+fn common_type(a: ValueType, b: ValueType) -> Option<ValueType> {
+  if a.accepts(&b) {
+    Some(a)
+  } else if b.accepts(&a) {
+    Some(b)
+  } else {
+    None
+  }
+}
+
+/// Checks that the defaults in a `parameter_spec` conform to their declared types
+pub fn validate_parameter_spec(spec: &ParameterSpec, loc: &YamlLocation) -> ORResult<()> {
+  for (name, param) in spec {
+    validate_identifier(name, loc)?;
+    if let Some(default) = &param.default {
+      param.type_.check(default)
+        .map_err(|v| ORError::TypeMismatch(loc.clone(), v.at_key(name)))?;
+    }
+  }
+  Ok(())
+}
+
+/// Describes the parameters of a function as a record, so that they can be handled like plugin options
+pub fn spec_type(spec: &ParameterSpec) -> ValueType {
+  ValueType::Record {
+    fields: spec.iter().map(|(k, v)| (k.clone(), v.type_.clone())).collect()
+  }
+}
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::fmt::{Display, Formatter};
+use orirocks_api_v3::Value;
+use crate::build::{OrderedDependencyGraph, Project};
+use crate::expand::{expand_steps, InlinedStep};
+use crate::ident::ImportRef;
+use crate::model::Parameters;
+use crate::util::{Located, ORResult, YamlLocation};
+use crate::vars::interpolate_options;
+
+/// What a build will do: the dirty artifacts in build order, with every step that will run
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Plan {
+  pub artifacts: Vec<PlannedArtifact>
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlannedArtifact {
+  pub name: String,
+  /// Direct dependencies, including the artifact this one is built `from`
+  pub deps: Vec<String>,
+  pub envs: Vec<PlannedEnvironment>
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlannedEnvironment {
+  pub name: ImportRef,
+  pub loc: YamlLocation,
+  /// Options with project variables substituted
+  pub options: Parameters,
+  /// Steps after conditions, loops and function calls have been expanded
+  pub steps: Vec<InlinedStep>
+}
+
+/// Expands the environments of an artifact
+pub fn plan_artifact(project: &Project, name: &str, deps: &[String]) -> ORResult<PlannedArtifact> {
+  let artifact = &project.builds[name];
+  let mut envs = vec![];
+  for (i, env) in artifact.envs.iter().enumerate() {
+    let mut loc = Located::location(artifact).clone();
+    loc.push_item(env.name.to_string(), "envs", i);
+    envs.push(PlannedEnvironment {
+      name: env.name.clone(),
+      options: interpolate_options(project, artifact, &env.parameters, &loc)?,
+      steps: expand_steps(project, artifact, &env.steps, &loc)?,
+      loc
+    });
+  }
+  Ok(PlannedArtifact {
+    name: name.to_string(),
+    deps: deps.to_vec(),
+    envs
+  })
+}
+
+/// Expands every dirty artifact in a dependency graph
+pub fn plan(project: &Project, graph: &OrderedDependencyGraph) -> ORResult<Plan> {
+  let artifacts = graph.artifacts.iter()
+    .map(|(name, deps)| plan_artifact(project, name, deps))
+    .collect::<ORResult<_>>()?;
+  Ok(Plan { artifacts })
+}
+
+/// Formats a value on a single line, quoting strings that would otherwise be ambiguous
+fn format_value(value: &Value) -> String {
+  match value {
+    Value::Null => "null".into(),
+    Value::Bool(v) => v.to_string(),
+    Value::Integer(v) => v.to_string(),
+    Value::Float(v) => v.inner.to_string(),
+    Value::String(v) if v.is_empty() || v.contains(|c: char| c.is_whitespace() || ",=[]{}\"".contains(c)) => format!("{:?}", v),
+    Value::String(v) => v.clone(),
+    Value::Array(values) => format!("[{}]", values.iter().map(format_value).collect::<Vec<_>>().join(", ")),
+    Value::Dict(values) => format!("{{{}}}", values.iter().map(|(k, v)| format!("{}: {}", k, format_value(v))).collect::<Vec<_>>().join(", "))
+  }
+}
+
+fn format_parameters(parameters: &Parameters) -> String {
+  parameters.iter()
+    .map(|(k, v)| format!(" {}={}", k, format_value(v)))
+    .collect()
+}
+
+impl Display for Plan {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    if self.artifacts.is_empty() {
+      return writeln!(f, "all artifacts are up to date");
+    }
+    for artifact in &self.artifacts {
+      write!(f, "{}", artifact.name)?;
+      if !artifact.deps.is_empty() {
+        write!(f, " (after {})", artifact.deps.join(", "))?;
+      }
+      writeln!(f)?;
+      for env in &artifact.envs {
+        writeln!(f, "  {}{}", env.name, format_parameters(&env.options))?;
+        for (i, inlined) in env.steps.iter().enumerate() {
+          write!(f, "    #{} {}{}", i, inlined.step.action, format_parameters(&inlined.step.parameters))?;
+          if !inlined.call_stack.is_empty() {
+            write!(f, "  [{}]", inlined.call_stack.join(" > "))?;
+          }
+          writeln!(f)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
\ No newline at end of file
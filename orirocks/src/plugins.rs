@@ -1,13 +1,30 @@
 use std::collections::HashMap;
 use orirocks_api_v3::{DeploymentProvider, EnvironmentProvider};
+#[cfg(feature = "plugin-qemu")]
 use orirocks_qemu::QemuEnvironmentProvider;
+#[cfg(feature = "plugin-fs")]
+use orirocks_fs::DirectoryDeploymentProvider;
+#[cfg(feature = "plugin-oci")]
+use orirocks_oci::OciDeploymentProvider;
+#[cfg(feature = "plugin-s3")]
+use orirocks_s3::S3DeploymentProvider;
 
-fn collect_plugins() -> (Vec<Box<dyn EnvironmentProvider>>, Vec<Box<dyn DeploymentProvider>>) {
+pub type Providers = (Vec<Box<dyn EnvironmentProvider>>, Vec<Box<dyn DeploymentProvider>>);
+
+fn collect_plugins() -> Providers {
+  #[allow(unused_mut)]
   let mut env_providers: Vec<Box<dyn EnvironmentProvider>> = vec![];
+  #[allow(unused_mut)]
   let mut dep_providers: Vec<Box<dyn DeploymentProvider>> = vec![];
 
   #[cfg(feature = "plugin-qemu")]
-  env_providers.push(Box::new(QemuEnvironmentProvider::default()));
+  env_providers.push(Box::new(QemuEnvironmentProvider));
+  #[cfg(feature = "plugin-fs")]
+  dep_providers.push(Box::new(DirectoryDeploymentProvider));
+  #[cfg(feature = "plugin-oci")]
+  dep_providers.push(Box::new(OciDeploymentProvider));
+  #[cfg(feature = "plugin-s3")]
+  dep_providers.push(Box::new(S3DeploymentProvider::default()));
 
   (env_providers, dep_providers)
 }
@@ -19,7 +36,10 @@ pub struct PluginHive {
 
 impl PluginHive {
   pub fn new() -> Self {
-    let (collected_envs, collected_deps) = collect_plugins();
+    Self::from_providers(collect_plugins())
+  }
+
+  pub fn from_providers((collected_envs, collected_deps): Providers) -> Self {
     PluginHive {
       env: collected_envs.into_iter().map(|v| (v.name().to_string(), v)).collect(),
       dep: collected_deps.into_iter().map(|v| (v.name().to_string(), v)).collect(),
@@ -30,7 +50,19 @@ impl PluginHive {
     &self.env
   }
 
+  /// Finds the provider for an environment named `plugin/env` in a build
+  pub fn environment(&self, name: &str) -> Option<&dyn EnvironmentProvider> {
+    let env_name = name.split_once('/').map(|v| v.1).unwrap_or(name);
+    self.env.get(env_name).map(|v| &**v)
+  }
+
   pub fn deployments(&self) -> &HashMap<String, Box<dyn DeploymentProvider>> {
     &self.dep
   }
+
+  /// Finds the provider for a deployment provider named `plugin/provider`
+  pub fn deployment(&self, name: &str) -> Option<&dyn DeploymentProvider> {
+    let provider_name = name.split_once('/').map(|v| v.1).unwrap_or(name);
+    self.dep.get(provider_name).map(|v| &**v)
+  }
 }
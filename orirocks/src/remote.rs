@@ -0,0 +1,117 @@
+//! The remote cache shares build outputs between machines, such as CI runners and developer laptops.
+//! It is any HTTP server that serves files, and that accepts `PUT` for machines that upload:
+//!
+//! ```text
+//! {url}/keys/<key>         entry naming the output built from the inputs with this key
+//! {url}/sha256/<hex>       output that is a file, with a digest such as `sha256:9f86d0...`
+//! {url}/tree-sha256/<hex>  output that is a directory as a tar archive, with a digest such as `tree-sha256:4e07b4...`
+//! ```
+//!
+//! Keys identify everything that goes into building an artifact, see `build::cache_key`.
+//! Downloaded outputs are checked against the digest of their entry before they are stored.
+//! The cache is set by `remote_cache` in the manifest, `ORIROCKS_REMOTE_CACHE` or `--remote-cache`, each overriding
+//! the one before, and uploads are enabled by any of `upload`, `ORIROCKS_REMOTE_CACHE_UPLOAD=1` or `--remote-cache-upload`.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::store::remove_output;
+use orirocks_api_v3::digest::{artifact_digest, parse_digest};
+
+/// Entry stored under a key
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+struct CacheEntry {
+  digest: String,
+  /// Set if the output is a directory, which is stored as a tar archive
+  #[serde(default)]
+  directory: bool
+}
+
+pub struct RemoteCache {
+  url: String,
+  /// Uploads outputs that were built locally
+  pub upload: bool,
+  /// Sent as a bearer token with every request
+  token: Option<String>,
+  agent: ureq::Agent
+}
+
+impl RemoteCache {
+  pub fn new(url: &str, upload: bool, token: Option<String>) -> Self {
+    RemoteCache { url: url.trim_end_matches('/').to_string(), upload, token, agent: ureq::Agent::new() }
+  }
+
+  fn request(&self, method: &str, path: &str) -> ureq::Request {
+    let request = self.agent.request(method, &format!("{}/{}", self.url, path));
+    match &self.token {
+      Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+      None => request
+    }
+  }
+
+  /// Downloads the output built from the inputs with `key` to `output`, returning its digest. `None` on a miss.
+  pub fn fetch(&self, key: &str, output: &Path) -> io::Result<Option<String>> {
+    let entry = match self.request("GET", &format!("keys/{}", key)).call() {
+      Ok(response) => response.into_string()?,
+      Err(ureq::Error::Status(404, _)) => return Ok(None),
+      Err(err) => return Err(io::Error::other(err))
+    };
+    let entry: CacheEntry = serde_yaml::from_str(&entry).map_err(|v| io::Error::new(io::ErrorKind::InvalidData, v))?;
+    let (algorithm, hex) = parse_digest(&entry.digest)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid digest `{}`", entry.digest)))?;
+    let mut reader = self.request("GET", &format!("{}/{}", algorithm, hex)).call()
+      .map_err(io::Error::other)?
+      .into_reader();
+    let result = match entry.directory {
+      true => tar::Archive::new(reader).unpack(output),
+      false => File::create(output).and_then(|mut v| io::copy(&mut reader, &mut v)).map(|_| ())
+    };
+    let result = result.and_then(|_| artifact_digest(output)).and_then(|digest| match digest == entry.digest {
+      true => Ok(Some(digest)),
+      false => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected `{}`, downloaded `{}`", entry.digest, digest)))
+    });
+    if result.is_err() {
+      remove_output(output)?;
+    }
+    result
+  }
+
+  /// Uploads an output with `digest` as the output built from the inputs with `key`.
+  /// Directories are archived in `work_dir` first.
+  pub fn store(&self, key: &str, digest: &str, path: &Path, work_dir: &Path) -> io::Result<()> {
+    let object = digest.replacen(':', "/", 1);
+    let directory = path.is_dir();
+    let exists = match self.request("HEAD", &object).call() {
+      Ok(_) => true,
+      Err(ureq::Error::Status(404, _)) => false,
+      Err(err) => return Err(io::Error::other(err))
+    };
+    if !exists {
+      let archive = work_dir.join("output.tar");
+      let upload = match directory {
+        true => {
+          let mut builder = tar::Builder::new(File::create(&archive)?);
+          builder.append_dir_all(".", path)?;
+          builder.into_inner()?.sync_all()?;
+          archive.as_path()
+        }
+        false => path
+      };
+      let file = File::open(upload)?;
+      let len = file.metadata()?.len();
+      let result = self.request("PUT", &object)
+        .set("Content-Length", &len.to_string())
+        .send(file);
+      if directory {
+        fs::remove_file(&archive)?;
+      }
+      result.map_err(io::Error::other)?;
+    }
+    // the entry goes last, so that it never names an output that is not there
+    let entry = serde_yaml::to_string(&CacheEntry { digest: digest.to_string(), directory })
+      .map_err(|v| io::Error::new(io::ErrorKind::InvalidData, v))?;
+    self.request("PUT", &format!("keys/{}", key)).send_string(&entry).map_err(io::Error::other)?;
+    Ok(())
+  }
+}
\ No newline at end of file
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use log::{info, log, Level};
+use orirocks_api_v3::{LogLevel, Progress, Reporter, Tag};
+
+/// Minimum time between two progress lines for the same tag
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Forwards plugin logs and progress to the `log` crate
+#[derive(Default)]
+pub struct LogReporter {
+  last_progress: Mutex<HashMap<Tag, Instant>>
+}
+
+impl LogReporter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+fn format_tag(tag: &Tag) -> String {
+  match &tag.step {
+    Some(step) => format!("[{}: {}]", tag.artifact, step),
+    None => format!("[{}]", tag.artifact)
+  }
+}
+
+fn format_progress(progress: Progress) -> String {
+  match progress {
+    Progress::Bytes { done, total: Some(total) } if total > 0 =>
+      format!("{:.1}% ({} / {} bytes)", done as f64 * 100.0 / total as f64, done, total),
+    Progress::Bytes { done, .. } => format!("{} bytes", done),
+    Progress::Percent(percent) => format!("{:.1}%", percent)
+  }
+}
+
+fn is_finished(progress: Progress) -> bool {
+  match progress {
+    Progress::Bytes { done, total: Some(total) } => done >= total,
+    Progress::Bytes { .. } => false,
+    Progress::Percent(percent) => percent >= 100.0
+  }
+}
+
+impl Reporter for LogReporter {
+  fn log(&self, tag: &Tag, level: LogLevel, message: &str) {
+    let level = match level {
+      LogLevel::Error => Level::Error,
+      LogLevel::Warn => Level::Warn,
+      LogLevel::Info => Level::Info,
+      LogLevel::Debug => Level::Debug,
+      LogLevel::Trace => Level::Trace
+    };
+    log!(level, "{} {}", format_tag(tag), message);
+  }
+
+  fn progress(&self, tag: &Tag, progress: Progress) {
+    let now = Instant::now();
+    let mut last_progress = self.last_progress.lock().unwrap();
+    let finished = is_finished(progress);
+    let due = last_progress.get(tag)
+      .map(|v| now.duration_since(*v) >= PROGRESS_INTERVAL)
+      .unwrap_or(true);
+    if !due && !finished {
+      return;
+    }
+    if finished {
+      last_progress.remove(tag);
+    } else {
+      last_progress.insert(tag.clone(), now);
+    }
+    info!("{} {}", format_tag(tag), format_progress(progress));
+  }
+}
\ No newline at end of file
@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use orirocks_api_v3::{split_resource, Value, ValueType};
+use crate::build::Project;
+use crate::expand::expand_steps_with;
+use crate::model::BuildDoc;
+use crate::params::spec_type;
+use crate::plugins::PluginHive;
+use crate::util::{Located, ORError, ORResult, YamlLocation};
+use crate::vars::interpolate_options;
+
+/// Resource locations with this scheme refer to files relative to the project root
+pub const SOURCE_SCHEME: &str = "src";
+
+pub type TypedValueVisitor<'a> = dyn FnMut(&ValueType, &Value, &YamlLocation) -> ORResult<()> + 'a;
+
+/// Visits every value in an artifact whose type is known, together with its type.
+/// Types come from plugin schemas (environment and action options) and from function parameter specs.
+/// Values are visited after project variables and parameters have been substituted.
+pub fn walk_typed_values(project: &Project, artifact: &Located<BuildDoc>, plugins: &PluginHive, f: &mut TypedValueVisitor) -> ORResult<()> {
+  let mut loc = Located::location(artifact).clone();
+  for (i, env) in artifact.envs.iter().enumerate() {
+    loc.push_item(env.name.to_string(), "envs", i);
+    let provider = plugins.environment(&env.name);
+    if let Some(schema) = provider.and_then(|v| v.options_schema()) {
+      f(&schema, &Value::Dict(interpolate_options(project, artifact, &env.parameters, &loc)?), &loc)?;
+    }
+    let steps = expand_steps_with(project, artifact, &env.steps, &loc, &mut |function, params, loc| {
+      f(&spec_type(&function.parameter_spec), &Value::Dict(params.clone()), loc)
+    })?;
+    for inlined in steps {
+      if provider.is_some_and(|v| !v.supports_action(&inlined.step.action)) {
+        return Err(ORError::UnsupportedAction(inlined.loc, env.name.to_string(), inlined.step.action.to_string()));
+      }
+      if let Some(schema) = provider.and_then(|v| v.action_schema(&inlined.step.action)) {
+        f(&schema, &Value::Dict(inlined.step.parameters), &inlined.loc)?;
+      }
+    }
+    loc.pop();
+  }
+  Ok(())
+}
+
+/// Collects the resource locations that an artifact refers to
+pub fn collect_resources(project: &Project, artifact: &Located<BuildDoc>, plugins: &PluginHive) -> ORResult<BTreeSet<String>> {
+  let mut resources = BTreeSet::new();
+  walk_typed_values(project, artifact, plugins, &mut |ty, value, _| {
+    resources.extend(ty.resources(value).into_iter().map(String::from));
+    Ok(())
+  })?;
+  Ok(resources)
+}
+
+/// Returns the real path of a `src:` resource, or `None` if the resource is not a project file.
+/// The file must exist inside the project root.
+pub fn source_path(project: &Project, location: &str, loc: &YamlLocation) -> ORResult<Option<PathBuf>> {
+  let Some((SOURCE_SCHEME, path)) = split_resource(location) else {
+    return Ok(None);
+  };
+  let error = |v| ORError::ResourceError(loc.clone(), location.to_string(), v);
+  // an empty root, as when files are given on the command line, is the current directory
+  let root = match project.root.as_os_str().is_empty() {
+    true => Path::new("."),
+    false => project.root.as_path()
+  };
+  let root = root.canonicalize().map_err(error)?;
+  let path = root.join(path).canonicalize().map_err(error)?;
+  if !path.starts_with(&root) {
+    return Err(ORError::ResourceOutsideProject(loc.clone(), location.to_string()));
+  }
+  Ok(Some(path))
+}
\ No newline at end of file
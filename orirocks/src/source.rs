@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use yaml_rust2::parser::{MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+use yaml_rust2::Event;
+use crate::diagnostics::Severity;
+use crate::util::ORError;
+
+/// Position of a YAML node. Lines and columns start at 1.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span {
+  pub line: usize,
+  pub column: usize
+}
+
+/// Keys leading to a node from the root of its document, such as `envs`, `0`, `steps`, `3`
+type NodePath = Vec<String>;
+
+/// Project files along with the position of every node in them, used to show source snippets in errors
+#[derive(Default, Debug)]
+pub struct SourceMap {
+  files: HashMap<String, SourceFile>
+}
+
+#[derive(Debug)]
+struct SourceFile {
+  text: String,
+  /// Position of every node, by document
+  documents: Vec<HashMap<NodePath, Span>>
+}
+
+impl SourceMap {
+  /// Indexes the nodes of a file. Files that are not valid YAML are indexed up to the first syntax error.
+  pub fn add(&mut self, file: String, text: String) {
+    let mut indexer = Indexer::default();
+    let _ = Parser::new_from_str(&text).load(&mut indexer, true);
+    self.files.insert(file, SourceFile { text, documents: indexer.documents });
+  }
+
+  /// Returns the position of a node, or of its closest ancestor that is known
+  pub fn span(&self, file: &str, document_id: usize, keys: &[String]) -> Option<Span> {
+    let nodes = self.files.get(file)?.documents.get(document_id)?;
+    (0..=keys.len()).rev()
+      .find_map(|i| nodes.get(&keys[..i]))
+      .copied()
+  }
+
+  /// Returns the file and position an error refers to, if it is known
+  pub fn locate<'a>(&self, err: &'a ORError) -> Option<(&'a str, Span)> {
+    if let ORError::YamlError(loc, err) = err {
+      let location = err.location()?;
+      return Some((&loc.file, Span { line: location.line(), column: location.column() }));
+    }
+    let (file, document_id, mut keys) = err.location()?.node();
+    keys.extend(err.keys());
+    Some((file, self.span(file, document_id, &keys)?))
+  }
+
+  /// Formats an error along with the line of source it refers to.
+  /// `color` highlights the output with ANSI escapes, which should only be used on terminals.
+  pub fn render(&self, err: &ORError, severity: Severity, color: bool) -> String {
+    let paint = |code: &str, s: &str| if color { format!("\x1b[{}m{}\x1b[0m", code, s) } else { s.to_string() };
+    let highlight = match severity {
+      Severity::Error => "1;31",
+      Severity::Warning => "1;33"
+    };
+    let mut out = format!("{}: {}\n", paint(highlight, &severity.to_string()), err);
+    let (file, span) = match self.locate(err) {
+      Some(v) => v,
+      None => return out
+    };
+    let line = self.files.get(file)
+      .and_then(|v| v.text.lines().nth(span.line - 1))
+      .unwrap_or_default()
+      .trim_end();
+    let gutter = " ".repeat(span.line.to_string().len());
+    let indent = line.chars().take(span.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+    let marker = "^".repeat(line.chars().count().saturating_sub(span.column - 1).max(1));
+    out += &format!("{}{} {}:{}:{}\n", gutter, paint("1;34", "-->"), file, span.line, span.column);
+    out += &format!("{} {}\n", gutter, paint("1;34", "|"));
+    out += &format!("{} {} {}\n", paint("1;34", &span.line.to_string()), paint("1;34", "|"), line);
+    out += &format!("{} {} {}{}\n", gutter, paint("1;34", "|"), indent, paint(highlight, &marker));
+    out
+  }
+}
+
+enum Container {
+  Sequence { next: usize },
+  /// `key` is set while the value of a key is expected, along with the position of the key
+  Mapping { key: Option<(String, Marker)> }
+}
+
+/// Records the position of every node while a file is parsed.
+/// Nodes are indexed by the keys that lead to them in the text, so the nodes of an anchored collection are only indexed
+/// under the anchor, and nodes reached through an alias fall back to the position of the alias. Merge keys (`<<`) are
+/// not expanded when projects are parsed either, so they are indexed like any other key.
+#[derive(Default)]
+struct Indexer {
+  documents: Vec<HashMap<NodePath, Span>>,
+  /// Open collections, with their path. The path is `None` inside complex keys, which are not indexed.
+  stack: Vec<(Container, Option<NodePath>)>,
+  /// Collections in sequences that were opened since the last scalar, with their start.
+  /// The parser marks mappings after their first key, so collections take the position of their key,
+  /// or of their first scalar in sequences.
+  pending: Vec<(Option<NodePath>, Marker)>
+}
+
+impl Indexer {
+  /// Works out the path of a node that starts at `mark`, along with the position of its key if it is a mapping value.
+  /// Returns `Err` if the node is a mapping key.
+  fn enter(&mut self, scalar: Option<&str>, mark: Marker) -> Result<(Option<NodePath>, Option<Marker>), ()> {
+    let (container, path) = match self.stack.last_mut() {
+      Some(v) => v,
+      None => return Ok((Some(vec![]), None))
+    };
+    let (key, key_mark) = match container {
+      Container::Sequence { next } => {
+        *next += 1;
+        ((*next - 1).to_string(), None)
+      }
+      Container::Mapping { key } => match key.take() {
+        Some((key, key_mark)) => (key, Some(key_mark)),
+        None => {
+          *key = Some((scalar.unwrap_or_default().to_string(), mark));
+          return Err(());
+        }
+      }
+    };
+    let path = path.as_ref().map(|v| {
+      let mut path = v.clone();
+      path.push(key);
+      path
+    });
+    Ok((path, key_mark))
+  }
+
+  fn record(&mut self, path: &Option<NodePath>, mark: Marker) {
+    if let (Some(path), Some(nodes)) = (path, self.documents.last_mut()) {
+      nodes.insert(path.clone(), Span { line: mark.line(), column: mark.col() + 1 });
+    }
+  }
+}
+
+impl MarkedEventReceiver for Indexer {
+  fn on_event(&mut self, ev: Event, mark: Marker) {
+    if !matches!(ev, Event::SequenceStart(..) | Event::MappingStart(..)) {
+      let empty = matches!(ev, Event::SequenceEnd | Event::MappingEnd);
+      for (path, start) in std::mem::take(&mut self.pending) {
+        self.record(&path, if empty { start } else { mark });
+      }
+    }
+    match ev {
+      Event::DocumentStart => self.documents.push(HashMap::new()),
+      Event::Scalar(value, ..) => {
+        if let Ok((path, _)) = self.enter(Some(&value), mark) {
+          self.record(&path, mark);
+        }
+      }
+      Event::Alias(_) => {
+        if let Ok((path, _)) = self.enter(None, mark) {
+          self.record(&path, mark);
+        }
+      }
+      Event::SequenceStart(..) | Event::MappingStart(..) => {
+        let (path, key_mark) = self.enter(None, mark).unwrap_or((None, None));
+        match key_mark {
+          Some(key_mark) => self.record(&path, key_mark),
+          None => self.pending.push((path.clone(), mark))
+        }
+        let container = match ev {
+          Event::SequenceStart(..) => Container::Sequence { next: 0 },
+          _ => Container::Mapping { key: None }
+        };
+        self.stack.push((container, path));
+      }
+      Event::SequenceEnd | Event::MappingEnd => {
+        self.stack.pop();
+      }
+      _ => {}
+    }
+  }
+}
\ No newline at end of file
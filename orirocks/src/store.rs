@@ -0,0 +1,216 @@
+//! The artifact store keeps the outputs of builds in the build directory, addressed by their digest:
+//!
+//! ```text
+//! store/sha256/9f86d0...                 output of a build that is a file
+//! store/tree-sha256/4e07b4...            output of a build that is a directory
+//! store/by-name/app[arch=x86_64] -> ../sha256/9f86d0...
+//! ```
+//!
+//! Digests are those of `orirocks_api_v3::digest::artifact_digest`, which the deployment providers use as well.
+//!
+//! Artifacts are built into a staging path in the work directory, and only moved into the store and linked by name
+//! once their build succeeded, so a failed build never replaces the last good output.
+//! Outputs with the same contents are stored once. Outputs in the store must not be modified.
+//! `store/index.yaml` records the outputs every artifact had and when outputs were last used, for `orirocks gc`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use orirocks_api_v3::digest::{artifact_digest, FILE_DIGEST, TREE_DIGEST};
+
+const BY_NAME: &str = "by-name";
+const INDEX: &str = "index.yaml";
+
+/// What the store knows about its outputs besides their contents
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
+pub struct StoreIndex {
+  /// Digests of the outputs of every artifact, oldest first
+  #[serde(default)]
+  pub history: BTreeMap<String, Vec<String>>,
+  /// When outputs were last built or used, in seconds since the Unix epoch, by digest
+  #[serde(default)]
+  pub last_used: BTreeMap<String, u64>
+}
+
+pub struct ArtifactStore {
+  root: PathBuf
+}
+
+impl ArtifactStore {
+  pub fn new(build_dir: &str) -> Self {
+    ArtifactStore { root: Path::new(build_dir).join("store") }
+  }
+
+  /// Path of the latest output of an artifact, a link into the store that exists once the artifact has been built
+  pub fn path(&self, name: &str) -> PathBuf {
+    self.root.join(BY_NAME).join(name)
+  }
+
+  /// Path of the output with a digest such as `sha256:9f86d0...`
+  pub fn object_path(&self, digest: &str) -> PathBuf {
+    let (algorithm, hex) = digest.split_once(':').unwrap_or((FILE_DIGEST, digest));
+    self.root.join(algorithm).join(hex)
+  }
+
+  /// Digest of the latest output of an artifact, if it has been built
+  pub fn digest(&self, name: &str) -> io::Result<Option<String>> {
+    match fs::read_link(self.path(name)) {
+      Ok(target) => {
+        let hex = target.file_name().map(|v| v.to_string_lossy());
+        let algorithm = target.parent().and_then(Path::file_name).map(|v| v.to_string_lossy());
+        Ok(hex.zip(algorithm).map(|(hex, algorithm)| format!("{}:{}", algorithm, hex)))
+      }
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err)
+    }
+  }
+
+  /// Moves a finished output into the store and links it as the latest output of the artifact.
+  /// If the store already holds the same contents, the output is removed instead. Returns the digest of the output.
+  pub fn insert(&self, name: &str, output: &Path) -> io::Result<String> {
+    let digest = artifact_digest(output)?;
+    self.insert_digest(name, output, &digest)?;
+    Ok(digest)
+  }
+
+  /// Like `insert`, for an output whose digest has already been computed.
+  /// The name must be a single path component, as links in `by-name` point one directory up.
+  pub fn insert_digest(&self, name: &str, output: &Path, digest: &str) -> io::Result<()> {
+    if !matches!(Path::new(name).components().collect::<Vec<_>>()[..], [Component::Normal(_)]) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` cannot be linked in the store", name)));
+    }
+    let object = self.object_path(digest);
+    let algorithm = object.parent().unwrap();
+    fs::create_dir_all(algorithm)?;
+    if fs::symlink_metadata(&object).is_ok() {
+      remove_output(output)?;
+    } else {
+      fs::rename(output, &object)?;
+    }
+    // the link is replaced by renaming, so that it always points at a complete output
+    let link = self.path(name);
+    let temp = link.with_file_name(format!(".{}.tmp", name));
+    fs::create_dir_all(self.root.join(BY_NAME))?;
+    let _ = fs::remove_file(&temp);
+    let target = Path::new("..").join(algorithm.file_name().unwrap()).join(object.file_name().unwrap());
+    symlink(&target, &temp, object.is_dir())?;
+    fs::rename(&temp, &link)?;
+    let mut index = self.index()?;
+    let history = index.history.entry(name.to_string()).or_default();
+    history.retain(|v| v != digest);
+    history.push(digest.to_string());
+    index.last_used.insert(digest.to_string(), now());
+    self.save_index(&index)
+  }
+
+  /// Records that the latest outputs of artifacts were used, so that garbage collection evicts them last
+  pub fn touch<S: AsRef<str>>(&self, names: impl IntoIterator<Item = S>) -> io::Result<()> {
+    let mut index = self.index()?;
+    for name in names {
+      if let Some(digest) = self.digest(name.as_ref())? {
+        index.last_used.insert(digest, now());
+      }
+    }
+    self.save_index(&index)
+  }
+
+  /// Reads the index. It is empty if nothing has been stored yet.
+  pub fn index(&self) -> io::Result<StoreIndex> {
+    match File::open(self.root.join(INDEX)) {
+      Ok(file) => serde_yaml::from_reader(file).map_err(|v| io::Error::new(io::ErrorKind::InvalidData, v)),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(StoreIndex::default()),
+      Err(err) => Err(err)
+    }
+  }
+
+  /// Replaces the index by renaming, so that an interrupted write does not lose it
+  pub fn save_index(&self, index: &StoreIndex) -> io::Result<()> {
+    fs::create_dir_all(&self.root)?;
+    let temp = self.root.join(format!(".{}.tmp", INDEX));
+    serde_yaml::to_writer(File::create(&temp)?, index).map_err(|v| io::Error::new(io::ErrorKind::InvalidData, v))?;
+    fs::rename(&temp, self.root.join(INDEX))
+  }
+
+  /// Digests of every output in the store
+  pub fn objects(&self) -> io::Result<Vec<String>> {
+    let mut objects = vec![];
+    for algorithm in [FILE_DIGEST, TREE_DIGEST] {
+      objects.extend(list_dir(&self.root.join(algorithm))?.into_iter().map(|v| format!("{}:{}", algorithm, v)));
+    }
+    Ok(objects)
+  }
+
+  /// Names of the artifacts that are linked to an output
+  pub fn names(&self) -> io::Result<Vec<String>> {
+    list_dir(&self.root.join(BY_NAME))
+  }
+
+  /// Removes the link to the latest output of an artifact, leaving the output in the store
+  pub fn unlink(&self, name: &str) -> io::Result<()> {
+    match fs::remove_file(self.path(name)) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+      _ => Ok(())
+    }
+  }
+}
+
+/// Names in a directory, without temporary files. Empty if the directory does not exist.
+fn list_dir(dir: &Path) -> io::Result<Vec<String>> {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(err) => return Err(err)
+  };
+  let mut names = vec![];
+  for entry in entries {
+    let name = entry?.file_name().to_string_lossy().into_owned();
+    if !name.starts_with('.') {
+      names.push(name);
+    }
+  }
+  names.sort();
+  Ok(names)
+}
+
+fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_secs()).unwrap_or_default()
+}
+
+/// Space taken by the files of an output, which may be a file or a directory
+pub fn disk_size(path: &Path) -> io::Result<u64> {
+  let metadata = fs::symlink_metadata(path)?;
+  if !metadata.is_dir() {
+    return Ok(metadata.len());
+  }
+  let mut size = 0;
+  for entry in fs::read_dir(path)? {
+    size += disk_size(&entry?.path())?;
+  }
+  Ok(size)
+}
+
+/// Removes an output or a partial output, which may be a file or a directory
+pub fn remove_output(path: &Path) -> io::Result<()> {
+  match fs::symlink_metadata(path) {
+    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+    Ok(_) => fs::remove_file(path),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+    Err(err) => Err(err)
+  }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+  std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+  match is_dir {
+    true => std::os::windows::fs::symlink_dir(target, link),
+    false => std::os::windows::fs::symlink_file(target, link)
+  }
+}
\ No newline at end of file
@@ -0,0 +1,190 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use orirocks_api_v3::{CancellationToken, LogLevel, Progress, Reporter, Tag};
+use crate::build::{build, BuildCache, BuildOptions, Project};
+use super::{build_options, mock_hive, parse, temp_dir, Events};
+use crate::resources::source_path;
+use crate::util::{ORError, YamlLocation};
+
+#[derive(Default)]
+struct RecordingReporter {
+  lines: Mutex<Vec<String>>
+}
+
+impl Reporter for RecordingReporter {
+  fn log(&self, tag: &Tag, level: LogLevel, message: &str) {
+    self.lines.lock().unwrap().push(format!("{:?} {} {}", level, tag.artifact, message));
+  }
+
+  fn progress(&self, tag: &Tag, progress: Progress) {
+    self.lines.lock().unwrap().push(format!("{:?} {:?}", tag.step, progress));
+  }
+}
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: base
+  envs:
+  - name: test/mock
+    steps:
+    - action: first
+---
+!build
+  name: derived
+  from: base
+  envs:
+  - name: test/mock
+    steps:
+    - action: second
+    - action: cancel
+    - action: never_reached
+";
+
+#[test]
+fn build_passes_context_to_plugins() {
+  let events = Events::default();
+  let reporter = RecordingReporter::default();
+  let project = parse(&PROJECT.replace("    - action: cancel\n", ""));
+  let opts = build_options("orirocks-test-build-context", None);
+  let build_dir = opts.build_dir.clone();
+  build(&project, None, &opts, &mock_hive(&events, vec![]), &reporter).unwrap();
+  assert_eq!(*events.lock().unwrap(), vec![
+    "create base from ``".to_string(),
+    "action step #0 (first)".into(),
+    "finish base".into(),
+    format!("create derived from `{}/store/by-name/base`", build_dir),
+    "action step #0 (second)".into(),
+    "action step #1 (never_reached)".into(),
+    "finish derived".into()
+  ]);
+  let lines = reporter.lines.lock().unwrap();
+  assert!(lines.contains(&"Info base creating".to_string()));
+  assert!(lines.contains(&"Some(\"step #0 (second)\") Percent(100.0)".to_string()));
+}
+
+#[test]
+fn build_stops_when_cancelled() {
+  let events = Events::default();
+  let project = parse(PROJECT);
+  let opts = build_options("orirocks-test-build-cancel", None);
+  let result = build(&project, None, &opts, &mock_hive(&events, vec![]), &RecordingReporter::default());
+  assert!(matches!(result, Err(ORError::Cancelled)));
+  let events = events.lock().unwrap();
+  assert_eq!(events.last().unwrap(), "action step #1 (cancel)");
+  assert!(!events.contains(&"finish derived".to_string()));
+}
+
+#[test]
+fn cancellation_token_is_shared_between_clones() {
+  let token = CancellationToken::new();
+  let clone = token.clone();
+  assert!(token.check().is_ok());
+  clone.cancel();
+  assert!(token.is_cancelled());
+  assert_eq!(token.check(), Err("operation cancelled".to_string()));
+}
+
+#[test]
+fn cancellation_token_times_out() {
+  let token = CancellationToken::with_timeout(std::time::Duration::ZERO);
+  assert!(token.is_cancelled());
+  assert_eq!(token.check(), Err("operation timed out".to_string()));
+}
+
+
+#[test]
+fn build_tracks_source_files() {
+  let root = temp_dir("orirocks-test-build-sources");
+  fs::create_dir_all(root.join("assets")).unwrap();
+  fs::write(root.join("assets/script.js"), "one").unwrap();
+  let mut project = parse("
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: scripted
+  envs:
+  - name: test/mock
+    steps:
+    - action: copy_file
+      source: src:assets/script.js
+      dest: vm:/root/script.js
+");
+  project.root = root.clone();
+  let events = Events::default();
+  let opts = BuildOptions { rebuild: false, build_dir: root.join("build").to_string_lossy().into_owned(), cancel: CancellationToken::new(), remote_cache: None };
+  let run = |cache: Option<BuildCache>| {
+    events.lock().unwrap().clear();
+    let cache = build(&project, cache, &opts, &mock_hive(&events, vec![]), &RecordingReporter::default()).unwrap();
+    (cache, events.lock().unwrap().clone())
+  };
+
+  let (cache, first) = run(None);
+  assert_eq!(first[1], format!("dependency src:assets/script.js = {}", root.join("assets/script.js").display()));
+  let (cache, second) = run(Some(cache));
+  assert!(second.is_empty());
+  fs::write(root.join("assets/script.js"), "two").unwrap();
+  let (_, third) = run(Some(cache));
+  assert_eq!(third.len(), first.len());
+}
+
+#[test]
+fn source_files_default_to_the_current_directory() {
+  let project = Project::default();
+  let loc = YamlLocation::new("test.yaml".into(), 1, vec![]);
+  assert_eq!(project.root, Path::new(""));
+  assert_eq!(source_path(&project, "src:Cargo.toml", &loc).unwrap(), Some(Path::new("Cargo.toml").canonicalize().unwrap()));
+}
+
+#[test]
+fn build_confines_source_files_to_the_project() {
+  let dir = temp_dir("orirocks-test-build-confined");
+  let root = dir.join("project");
+  fs::create_dir_all(&root).unwrap();
+  fs::write(dir.join("orirocks-test-build-secret"), "secret").unwrap();
+  let mut project = parse("
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: scripted
+  envs:
+  - name: test/mock
+    steps:
+    - action: copy_file
+      source: src:../orirocks-test-build-secret
+      dest: vm:/root/secret
+");
+  project.root = root.clone();
+  let opts = BuildOptions { rebuild: false, build_dir: root.join("build").to_string_lossy().into_owned(), cancel: CancellationToken::new(), remote_cache: None };
+  let err = build(&project, None, &opts, &mock_hive(&Events::default(), vec![]), &RecordingReporter::default()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1`: resource `src:../orirocks-test-build-secret` is outside the project directory");
+}
+
+#[test]
+fn build_checks_action_schemas() {
+  let project = parse("
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: scripted
+  envs:
+  - name: test/mock
+    steps:
+    - action: copy_file
+      source: assets/script.js
+      dest: vm:/root/script.js
+");
+  let opts = build_options("orirocks-test-build-schema", None);
+  let err = build(&project, None, &opts, &mock_hive(&Events::default(), vec![]), &RecordingReporter::default()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: test/mock/step #0`: type mismatch: `source`: expected resource location like `src:path/to/file`, found `assets/script.js`");
+}
\ No newline at end of file
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use orirocks_api_v3::{Context, DeploymentProvider, Value, ValueType};
+use crate::build::{build, Project};
+use crate::deploy::{build_required, deployment_status, required_artifacts, run_deployments, select_deployments, DeployResult, DeploymentLedger, Drift};
+use crate::plugins::PluginHive;
+use crate::report::LogReporter;
+use crate::store::ArtifactStore;
+use super::{build_options, mock_hive, parse, try_parse, validate, Events};
+
+/// Records the deployments it runs. Deployments with the option `fail` fail.
+/// Deployments take a bucket, the number of retries, and optionally regions, tags and a file to upload along.
+struct UploadProvider {
+  events: Events
+}
+
+impl DeploymentProvider for UploadProvider {
+  fn name(&self) -> &str {
+    "upload"
+  }
+
+  fn options_schema(&self) -> Option<ValueType> {
+    let optional = |v| ValueType::Optional { inner: Box::new(v) };
+    Some(ValueType::Record {
+      fields: BTreeMap::from([
+        ("bucket".into(), ValueType::String),
+        ("retries".into(), ValueType::Integer),
+        ("regions".into(), optional(ValueType::Array { inner: Box::new(ValueType::String) })),
+        ("tags".into(), optional(ValueType::Dict { inner: Box::new(ValueType::String) })),
+        ("readme".into(), optional(ValueType::Path)),
+        ("fail".into(), optional(ValueType::Bool))
+      ])
+    })
+  }
+
+  fn deploy(&self, ctx: &Context, dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<(), String> {
+    let mut dependencies = dependencies.into_iter().collect::<Vec<_>>();
+    let mut options = options.into_iter().collect::<Vec<_>>();
+    dependencies.sort();
+    options.sort_by(|a, b| a.0.cmp(&b.0));
+    self.events.lock().unwrap().push(format!("deploy {} {:?} {:?}", ctx.tag().artifact, dependencies, options));
+    match options.iter().any(|v| v.0 == "fail") {
+      true => Err("upload failed".into()),
+      false => Ok(())
+    }
+  }
+}
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!vars
+  bucket:
+    type: string
+    default: images
+---
+!build
+  name: base
+  envs:
+  - name: test/mock
+    steps: []
+---
+!build
+  name: app
+  from: base
+  matrix:
+    arch: [x86_64, aarch64]
+  envs:
+  - name: test/mock
+    steps: []
+---
+!build
+  name: unrelated
+  envs:
+  - name: test/mock
+    steps: []
+---
+!deploy
+  name: release
+  provider: test/upload
+  artifacts: [app]
+  bucket: ${vars.bucket}
+  retries: 3
+";
+
+fn upload_hive(events: &Events) -> PluginHive {
+  mock_hive(events, vec![Box::new(UploadProvider { events: events.clone() })])
+}
+
+/// Artifacts that were built and deployments that ran, in order
+fn recorded(events: &Events) -> Vec<String> {
+  events.lock().unwrap().iter().filter(|v| v.starts_with("finish ") || v.starts_with("deploy ")).cloned().collect()
+}
+
+#[test]
+fn deploy_builds_required_artifacts() {
+  let project = parse(PROJECT);
+  validate(&project).unwrap();
+  let deploys = select_deployments(&project, &[]).unwrap();
+  assert_eq!(deploys, ["release"]);
+  assert_eq!(required_artifacts(&project, &deploys).into_iter().collect::<Vec<_>>(), ["app[arch=aarch64]", "app[arch=x86_64]", "base"]);
+  let events = Events::default();
+  let (plugins, opts) = (upload_hive(&events), build_options("orirocks-test-deploy", None));
+  let cache = build_required(&project, &deploys, None, &opts, &plugins, &LogReporter::new()).unwrap();
+  run_deployments(&project, &deploys, &opts, false, &plugins, &LogReporter::new(), &mut DeploymentLedger::default()).unwrap();
+  let artifacts = |name: &str| format!("{}/store/by-name/{}", opts.build_dir, name);
+  assert_eq!(recorded(&events), [
+    "finish base".to_string(),
+    "finish app[arch=aarch64]".to_string(),
+    "finish app[arch=x86_64]".to_string(),
+    format!("deploy release {:?} {:?}",
+      [("artifact:app[arch=aarch64]".to_string(), artifacts("app[arch=aarch64]")), ("artifact:app[arch=x86_64]".to_string(), artifacts("app[arch=x86_64]"))],
+      [("bucket".to_string(), Value::String("images".into())), ("retries".to_string(), Value::Integer(3))])
+  ]);
+
+  // the artifacts that were left out are still dirty, the deployed ones are not
+  events.lock().unwrap().clear();
+  let cache = build(&project, Some(cache), &opts, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(recorded(&events), ["finish unrelated"]);
+
+  // a plugin update seen while deploying still dirties the artifacts that the deployment left out
+  let project = parse(&PROJECT.replace("version: 0.1", "version: 0.2"));
+  let cache = build_required(&project, &deploys, Some(cache), &opts, &plugins, &LogReporter::new()).unwrap();
+  events.lock().unwrap().clear();
+  let cache = build(&project, Some(cache), &opts, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(recorded(&events), ["finish unrelated"]);
+
+  // rebuilding for a deployment keeps what the cache knows about the artifacts that the deployment left out
+  let mut opts = opts;
+  opts.rebuild = true;
+  events.lock().unwrap().clear();
+  let cache = build_required(&project, &deploys, Some(cache), &opts, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(recorded(&events).len(), 3);
+  events.lock().unwrap().clear();
+  opts.rebuild = false;
+  build(&project, Some(cache), &opts, &plugins, &LogReporter::new()).unwrap();
+  assert!(recorded(&events).is_empty());
+}
+
+#[test]
+fn build_does_not_deploy() {
+  let events = Events::default();
+  build(&parse(PROJECT), None, &build_options("orirocks-test-deploy-build", None), &upload_hive(&events), &LogReporter::new()).unwrap();
+  assert!(recorded(&events).iter().all(|v| v.starts_with("finish ")));
+}
+
+#[test]
+fn validate_deployments() {
+  let validate = |yaml: &str| validate(&parse(yaml)).unwrap_err().to_string();
+  assert_eq!(validate(&PROJECT.replace("provider: test/upload", "provider: tset/upload")),
+    "in `test.yaml: document #5: provider`: import `tset` not found, did you mean `test`?");
+  assert_eq!(validate(&PROJECT.replace("artifacts: [app]", "artifacts: [base, ap]")),
+    "in `test.yaml: document #5: artifacts #1`: artifact `ap` not found, did you mean `app`?");
+  assert_eq!(validate(&PROJECT.replace("${vars.bucket}", "${vars.buckt}")),
+    "in `test.yaml: document #5`: interpolation error: `bucket`: unknown variable `buckt`");
+  let project = parse(PROJECT);
+  assert_eq!(select_deployments(&project, &["relase".into()]).unwrap_err().to_string(), "deployment `relase` not found, did you mean `release`?");
+  let err = try_parse(&format!("{}---\n!deploy\n  name: release\n  provider: test/upload\n  artifacts: []\n", PROJECT));
+  assert_eq!(err.unwrap_err().to_string(), "in `test.yaml: document #6`: duplicate `deployment` `release`");
+}
+
+#[test]
+fn skip_unchanged_deployments() {
+  let project = parse(PROJECT);
+  let events = Events::default();
+  let (plugins, opts) = (upload_hive(&events), build_options("orirocks-test-deploy-ledger", None));
+  let deploys = vec!["release".to_string()];
+  let deployed = |project: &Project, force: bool| {
+    events.lock().unwrap().clear();
+    let mut ledger = DeploymentLedger::load(&opts.build_dir).unwrap();
+    build_required(project, &deploys, None, &opts, &plugins, &LogReporter::new()).unwrap();
+    let result = run_deployments(project, &deploys, &opts, force, &plugins, &LogReporter::new(), &mut ledger);
+    ledger.save(&opts.build_dir).unwrap();
+    result.map(|_| events.lock().unwrap().iter().any(|v| v.starts_with("deploy ")))
+  };
+  assert!(deployed(&project, false).unwrap());
+  assert!(!deployed(&project, false).unwrap());
+  assert!(deployed(&project, true).unwrap());
+  let changed = parse(&PROJECT.replace("retries: 3", "retries: 4"));
+  assert_eq!(deployment_status(&changed, &deploys, &opts, &plugins, &DeploymentLedger::load(&opts.build_dir).unwrap()).unwrap(),
+    [("release".to_string(), vec![Drift::OptionsChanged])]);
+  assert!(deployed(&changed, false).unwrap());
+  // the ledger is saved through a temporary file
+  assert!(Path::new(&opts.build_dir).join("deployments.yaml").is_file());
+  assert!(!Path::new(&opts.build_dir).join(".deployments.yaml.tmp").exists());
+
+  // artifacts are compared by their contents
+  let ledger = DeploymentLedger::load(&opts.build_dir).unwrap();
+  assert_eq!(deployment_status(&changed, &deploys, &opts, &plugins, &ledger).unwrap(), [("release".to_string(), vec![])]);
+  let rebuilt = format!("{}/rebuilt", opts.build_dir);
+  std::fs::write(&rebuilt, "rebuilt").unwrap();
+  ArtifactStore::new(&opts.build_dir).insert("app[arch=x86_64]", Path::new(&rebuilt)).unwrap();
+  std::fs::remove_file(format!("{}/store/by-name/app[arch=aarch64]", opts.build_dir)).unwrap();
+  assert_eq!(deployment_status(&changed, &deploys, &opts, &plugins, &ledger).unwrap(), [("release".to_string(), vec![
+    Drift::ArtifactNotBuilt("app[arch=aarch64]".into()),
+    Drift::ArtifactChanged("app[arch=x86_64]".into())
+  ])]);
+}
+
+#[test]
+fn record_failed_deployments() {
+  let project = parse(&format!("{}  fail: true\n", PROJECT));
+  let events = Events::default();
+  let (plugins, opts) = (upload_hive(&events), build_options("orirocks-test-deploy-failed", None));
+  let deploys = vec!["release".to_string()];
+  let mut ledger = DeploymentLedger::default();
+  build_required(&project, &deploys, None, &opts, &plugins, &LogReporter::new()).unwrap();
+  let err = run_deployments(&project, &deploys, &opts, false, &plugins, &LogReporter::new(), &mut ledger).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #5`: plugin error: upload failed");
+  assert_eq!(ledger.get("release").unwrap().result, DeployResult::Failed { error: "upload failed".into() });
+  assert_eq!(deployment_status(&project, &deploys, &opts, &plugins, &ledger).unwrap(),
+    [("release".to_string(), vec![Drift::LastFailed("upload failed".into())])]);
+  assert_eq!(deployment_status(&project, &deploys, &opts, &plugins, &DeploymentLedger::default()).unwrap(),
+    [("release".to_string(), vec![Drift::NeverDeployed])]);
+}
+
+#[test]
+fn typed_options() {
+  let mut project = parse(&format!("{}{}", PROJECT, "  regions: [eu-west-1, us-east-1]\n  tags:\n    team: images\n  readme: src:README.md\n"));
+  let events = Events::default();
+  let (plugins, opts) = (upload_hive(&events), build_options("orirocks-test-deploy-typed", None));
+  project.root = std::path::PathBuf::from(&opts.build_dir).join("project");
+  std::fs::create_dir_all(&project.root).unwrap();
+  std::fs::write(project.root.join("README.md"), "readme").unwrap();
+  let deploys = vec!["release".to_string()];
+  build_required(&project, &deploys, None, &opts, &plugins, &LogReporter::new()).unwrap();
+  events.lock().unwrap().clear();
+  let mut ledger = DeploymentLedger::default();
+  run_deployments(&project, &deploys, &opts, false, &plugins, &LogReporter::new(), &mut ledger).unwrap();
+  let event = recorded(&events)[0].clone();
+  let readme = project.root.canonicalize().unwrap().join("README.md");
+  assert!(event.contains(&format!("(\"src:README.md\", {:?})", readme.to_string_lossy())), "{}", event);
+  assert!(event.contains(r#"("regions", Array([String("eu-west-1"), String("us-east-1")]))"#), "{}", event);
+  assert!(event.contains(r#"("tags", Dict({"team": String("images")}))"#), "{}", event);
+  // files that the options refer to are compared by their contents
+  assert_eq!(deployment_status(&project, &deploys, &opts, &plugins, &ledger).unwrap(), [("release".to_string(), vec![])]);
+  std::fs::write(project.root.join("README.md"), "changed").unwrap();
+  assert_eq!(deployment_status(&project, &deploys, &opts, &plugins, &ledger).unwrap(),
+    [("release".to_string(), vec![Drift::OptionsChanged])]);
+
+  // options are checked before anything is built
+  let project = parse(&PROJECT.replace("retries: 3", "retries: often"));
+  let err = build_required(&project, &deploys, None, &opts, &plugins, &LogReporter::new()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #5`: type mismatch: `retries`: expected integer, found string");
+}
\ No newline at end of file
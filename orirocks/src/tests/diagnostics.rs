@@ -0,0 +1,117 @@
+use std::io::{Cursor, Read};
+use crate::build::{check_project, collect_project};
+use crate::diagnostics::{Diagnostics, Severity};
+use crate::source::SourceMap;
+use crate::util::ORError;
+use super::first_error;
+
+const PROJECT: &str = "!vars
+  unused:
+    type: string
+    default: x
+---
+!function
+  name: helper
+  parameter_spec: {}
+  steps:
+  - action: run
+    command: ${nope}
+---
+!build
+  name: img
+  envs:
+  - name: qemu/vm
+    steps:
+    - invoke_fn: missing
+    - action: run
+      when: ${x}
+---
+!import
+- require: qemu
+  version: 0.1.0
+";
+
+fn check(files: &[(&str, &str)]) -> (Diagnostics, SourceMap) {
+  let mut sources = SourceMap::default();
+  let mut diagnostics = Diagnostics::default();
+  let files = files.iter()
+    .map(|(name, yaml)| {
+      sources.add(name.to_string(), yaml.to_string());
+      (name.to_string(), Box::new(Cursor::new(yaml.to_string())) as Box<dyn Read>)
+    })
+    .collect();
+  let project = collect_project(files, &mut diagnostics);
+  if !diagnostics.has_errors() {
+    check_project(&project, &mut diagnostics);
+  }
+  diagnostics.sort(&sources);
+  (diagnostics, sources)
+}
+
+fn summary(diagnostics: &Diagnostics) -> Vec<(Severity, String)> {
+  diagnostics.items().iter().map(|v| (v.severity, v.error.to_string())).collect()
+}
+
+#[test]
+fn report_every_problem_in_order() {
+  let (diagnostics, _) = check(&[("b.yaml", "!build\n  name: b\n"), ("a.yaml", "!build\n  name: a\n")]);
+  assert_eq!(summary(&diagnostics), vec![
+    (Severity::Error, "in `a.yaml: document #0`: syntax error: `missing field `envs``".to_string()),
+    (Severity::Error, "in `b.yaml: document #0`: syntax error: `missing field `envs``".to_string())
+  ]);
+  let (diagnostics, sources) = check(&[("test.yaml", PROJECT)]);
+  assert_eq!(summary(&diagnostics), vec![
+    (Severity::Warning, "in `test.yaml: document #0`: variable `unused` is never used".to_string()),
+    (Severity::Warning, "in `test.yaml: document #1`: function `helper` is never called".to_string()),
+    (Severity::Error, "in `test.yaml: document #1: step #0`: interpolation error: `command`: unknown parameter `nope`".to_string()),
+    (Severity::Error, "in `test.yaml: document #2: qemu/vm/step #0`: function `missing` not found".to_string()),
+    (Severity::Error, "in `test.yaml: document #2: qemu/vm/step #1`: interpolation error: `when`: unknown parameter `x`".to_string())
+  ]);
+  assert!(diagnostics.render(&sources, false).ends_with("\n3 errors, 2 warnings\n"));
+}
+
+#[test]
+fn warnings_are_not_errors() {
+  let (diagnostics, _) = check(&[("test.yaml", "!function\n  name: helper\n  parameter_spec: {}\n  steps: []\n")]);
+  assert_eq!(diagnostics.items().len(), 1);
+  assert!(!diagnostics.has_errors());
+  assert!(first_error(diagnostics).is_ok());
+}
+
+#[test]
+fn parsing_continues_after_invalid_documents() {
+  let (diagnostics, _) = check(&[("test.yaml", "!build\n  name: a\n  envs: 3\n---\n!build\n  name: b\n---\n!build\n  name: a\n  envs: []\n")]);
+  assert_eq!(summary(&diagnostics).len(), 2);
+  assert!(summary(&diagnostics)[0].1.contains("document #0"));
+  assert!(summary(&diagnostics)[1].1.contains("document #1"));
+}
+
+#[test]
+fn format_json() {
+  let (diagnostics, sources) = check(&[("test.yaml", PROJECT)]);
+  let json: serde_json::Value = serde_json::from_str(&diagnostics.to_json(&sources)).unwrap();
+  assert_eq!(json[3], serde_json::json!({
+    "severity": "error",
+    "message": "function `missing` not found",
+    "file": "test.yaml",
+    "document": 2,
+    "path": "qemu/vm/step #0",
+    "line": 18,
+    "column": 18
+  }));
+}
+#[test]
+fn format_json_without_location() {
+  let mut diagnostics = Diagnostics::default();
+  diagnostics.error(ORError::ManifestNotFound("/project".into()));
+  let json: serde_json::Value = serde_json::from_str(&diagnostics.to_json(&SourceMap::default())).unwrap();
+  assert_eq!(json, serde_json::json!([{
+    "severity": "error",
+    "message": "could not find `orirocks.yaml` in `/project` or any parent directory",
+    "file": null,
+    "document": null,
+    "path": null,
+    "line": null,
+    "column": null
+  }]));
+}
\ No newline at end of file
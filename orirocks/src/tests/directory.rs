@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use orirocks_api_v3::{CancellationToken, Context, DeploymentProvider, Value};
+use orirocks_api_v3::digest::sha256_hex;
+use orirocks_fs::DirectoryDeploymentProvider;
+use crate::report::LogReporter;
+use super::temp_dir;
+
+fn deploy(artifacts: &Path, target: &Path, options: &[(&str, Value)]) -> Result<(), String> {
+  let dependencies = fs::read_dir(artifacts).unwrap()
+    .map(|v| v.unwrap())
+    .map(|v| (format!("artifact:{}", v.file_name().to_string_lossy()), v.path().to_string_lossy().into_owned()))
+    .collect();
+  let mut options = options.iter().map(|(k, v)| (k.to_string(), v.clone())).collect::<HashMap<_, _>>();
+  options.insert("path".into(), Value::String(target.to_string_lossy().into_owned()));
+  let reporter = LogReporter::new();
+  DirectoryDeploymentProvider.deploy(&Context::new("share".into(), &reporter, CancellationToken::new()), dependencies, options)
+}
+
+fn list(dir: &Path) -> Vec<String> {
+  let mut names = fs::read_dir(dir).unwrap().map(|v| v.unwrap().file_name().to_string_lossy().into_owned()).collect::<Vec<_>>();
+  names.sort();
+  names
+}
+
+#[test]
+fn publish_with_layout() {
+  let dir = temp_dir("orirocks-test-directory-layout");
+  let (artifacts, target) = (dir.join("artifacts"), dir.join("target"));
+  fs::create_dir_all(artifacts.join("docs/html")).unwrap();
+  fs::write(artifacts.join("disk"), "disk").unwrap();
+  fs::write(artifacts.join("docs/html/index.html"), "index").unwrap();
+  deploy(&artifacts, &target, &[]).unwrap();
+  let disk = target.join("disk").join(sha256_hex(b"disk"));
+  assert_eq!(fs::read_to_string(disk.join("disk")).unwrap(), "disk");
+  assert_eq!(fs::read_to_string(disk.join("SHA256SUMS")).unwrap(), format!("{}  disk\n", sha256_hex(b"disk")));
+  let sums = format!("{}  html/index.html\n", sha256_hex(b"index"));
+  let docs = target.join("docs").join(sha256_hex(sums.as_bytes()));
+  assert_eq!(fs::read_to_string(docs.join("html/index.html")).unwrap(), "index");
+  assert_eq!(fs::read_to_string(docs.join("SHA256SUMS")).unwrap(), sums);
+
+  // files of several versions can share a directory
+  let layout = |v: &str| [("layout", Value::String(v.into()))];
+  deploy(&artifacts, &target, &layout("{deployment}/{artifact}-{short_digest}.{file}")).unwrap();
+  fs::write(artifacts.join("disk"), "disk 2").unwrap();
+  deploy(&artifacts, &target, &layout("{deployment}/{artifact}-{short_digest}.{file}")).unwrap();
+  let short = |v: &str| sha256_hex(v.as_bytes())[..12].to_string();
+  assert_eq!(list(&target.join("share")), [
+    ".orirocks-deployed".to_string(),
+    "SHA256SUMS".to_string(),
+    format!("disk-{}.disk", short("disk")),
+    format!("disk-{}.disk", short("disk 2")),
+    format!("docs-{}.html", short(&sums))
+  ]);
+  assert_eq!(fs::read_to_string(target.join("share/SHA256SUMS")).unwrap(), format!("{}  disk-{}.disk\n{}  disk-{}.disk\n{}  docs-{}.html/index.html\n",
+    sha256_hex(b"disk"), short("disk"), sha256_hex(b"disk 2"), short("disk 2"), sha256_hex(b"index"), short(&sums)));
+  // files that are gone are no longer listed
+  fs::remove_file(target.join("share").join(format!("disk-{}.disk", short("disk")))).unwrap();
+  deploy(&artifacts, &target, &layout("{deployment}/{artifact}-{short_digest}.{file}")).unwrap();
+  assert!(!fs::read_to_string(target.join("share/SHA256SUMS")).unwrap().contains(&short("disk")));
+
+  assert_eq!(deploy(&artifacts, &target, &layout("../{file}")).unwrap_err(),
+    "`layout`: `../{file}` must be a relative path without empty, `.` or `..` components");
+  assert_eq!(deploy(&artifacts, &target, &layout("{artifact}/{dgest}/{file}")).unwrap_err(), "`layout`: unknown placeholder `{dgest}`");
+  assert_eq!(deploy(&artifacts, &target, &layout("{file}/{artifact}")).unwrap_err(),
+    "`layout`: `{file}/{artifact}` must contain `{file}` in its last component only");
+  assert_eq!(deploy(&artifacts, &target, &[("layout", Value::String("{artifact}/{file}".into())), ("retention", Value::Integer(2))]).unwrap_err(),
+    "`retention`: the last directory of the layout must contain `{digest}` or `{short_digest}`");
+}
+
+#[test]
+fn prune_old_versions() {
+  let dir = temp_dir("orirocks-test-directory-retention");
+  let (artifacts, target) = (dir.join("artifacts"), dir.join("target"));
+  fs::create_dir_all(&artifacts).unwrap();
+  fs::create_dir_all(target.join("disk/unrelated")).unwrap();
+  let retention = [("retention", Value::Integer(2))];
+  for contents in ["1", "2", "3", "2"] {
+    fs::write(artifacts.join("disk"), contents).unwrap();
+    deploy(&artifacts, &target, &retention).unwrap();
+  }
+  let mut expected = vec![sha256_hex(b"2"), sha256_hex(b"3"), "unrelated".to_string()];
+  expected.sort();
+  assert_eq!(list(&target.join("disk")), expected);
+  assert_eq!(list(&target.join("disk").join(sha256_hex(b"2"))), [".orirocks-deployed", "SHA256SUMS", "disk"]);
+
+  // versions are ordered by when they were deployed, not by when their directories changed
+  fs::write(target.join("disk").join(sha256_hex(b"2")).join(".orirocks-deployed"), "1").unwrap();
+  fs::write(artifacts.join("disk"), "4").unwrap();
+  deploy(&artifacts, &target, &retention).unwrap();
+  let mut expected = vec![sha256_hex(b"3"), sha256_hex(b"4"), "unrelated".to_string()];
+  expected.sort();
+  assert_eq!(list(&target.join("disk")), expected);
+}
+
+#[test]
+fn prune_versions_of_each_artifact() {
+  let dir = temp_dir("orirocks-test-directory-retention-artifacts");
+  let (artifacts, target) = (dir.join("artifacts"), dir.join("target"));
+  fs::create_dir_all(&artifacts).unwrap();
+  fs::write(artifacts.join("a"), "a").unwrap();
+  fs::write(artifacts.join("b"), "b").unwrap();
+  let retention = |layout: &str| [("layout", Value::String(layout.into())), ("retention", Value::Integer(1))];
+  deploy(&artifacts, &target, &retention("{deployment}/{artifact}-{digest}/{file}")).unwrap();
+  fs::write(artifacts.join("b"), "b 2").unwrap();
+  deploy(&artifacts, &target, &retention("{deployment}/{artifact}-{digest}/{file}")).unwrap();
+  let mut expected = vec![format!("a-{}", sha256_hex(b"a")), format!("b-{}", sha256_hex(b"b 2"))];
+  expected.sort();
+  assert_eq!(list(&target.join("share")), expected);
+
+  // without `{artifact}`, the versions of every artifact would share a directory
+  assert_eq!(deploy(&artifacts, &target, &retention("releases/{digest}/{file}")).unwrap_err(),
+    "`retention`: the directories of the layout must contain `{artifact}`");
+}
\ No newline at end of file
@@ -0,0 +1,82 @@
+use orirocks_api_v3::Value;
+use crate::build::build;
+use crate::expand::expand_steps;
+use crate::plugins::PluginHive;
+use crate::report::LogReporter;
+use crate::util::{Located, ORError};
+use super::{build_options, parse, validate};
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!function
+  name: outer
+  parameter_spec:
+    name:
+      type: string
+  steps:
+  - action: before
+  - invoke_fn: inner
+    name: ${name}
+  - action: after
+---
+!function
+  name: inner
+  parameter_spec:
+    name:
+      type: string
+  steps:
+  - action: greet
+    message: hello ${name}
+---
+!build
+  name: image
+  envs:
+  - name: test/mock
+    steps:
+    - action: first
+    - invoke_fn: outer
+      name: world
+";
+
+#[test]
+fn expand_inlines_nested_calls() {
+  let project = parse(PROJECT);
+  validate(&project).unwrap();
+  let artifact = &project.builds["image"];
+  let steps = expand_steps(&project, artifact, &artifact.envs[0].steps, Located::location(artifact)).unwrap();
+  let actions = steps.iter().map(|v| v.step.action.as_str()).collect::<Vec<_>>();
+  assert_eq!(actions, vec!["first", "before", "greet", "after"]);
+  let greet = &steps[2];
+  assert_eq!(greet.step.parameters["message"], Value::from("hello world"));
+  assert_eq!(greet.call_stack, vec!["outer".to_string(), "inner".into()]);
+  assert_eq!(greet.loc.to_string(), "test.yaml: document #3: step #1/function outer/step #1/function inner/step #0");
+}
+
+#[test]
+fn expand_reports_recursion() {
+  let project = parse(&PROJECT.replace("  - action: greet\n    message: hello ${name}", "  - invoke_fn: outer\n    name: ${name}"));
+  let artifact = &project.builds["image"];
+  let err = expand_steps(&project, artifact, &artifact.envs[0].steps, Located::location(artifact)).unwrap_err();
+  assert!(matches!(err, ORError::RecursiveFunction(..)));
+  assert_eq!(err.to_string(), "in `test.yaml: document #3: step #1/function outer/step #1/function inner/step #0`: recursive function call: outer -> inner -> outer");
+  let err = validate(&project).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: step #1`: recursive function call: inner -> outer -> inner");
+}
+
+#[test]
+fn validate_reports_recursion_in_uncalled_functions() {
+  let project = parse(&PROJECT.replace("    - invoke_fn: outer\n      name: world\n", "").replace("  - action: greet\n    message: hello ${name}", "  - invoke_fn: inner\n    name: ${name}"));
+  let err = validate(&project).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2: step #0`: recursive function call: inner -> inner");
+}
+
+#[test]
+fn build_reports_missing_functions() {
+  let project = parse(&PROJECT.replace("- invoke_fn: inner", "- invoke_fn: missing"));
+  let opts = build_options("orirocks-test-expand-missing", None);
+  let err = build(&project, None, &opts, &PluginHive::from_providers((vec![], vec![])), &LogReporter::new()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #3: test/mock/step #1/function outer/step #1`: function `missing` not found");
+}
\ No newline at end of file
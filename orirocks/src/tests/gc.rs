@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+use crate::gc::{collect_garbage, format_size, parse_size, GcOptions, Reason};
+use crate::store::ArtifactStore;
+use super::temp_dir;
+
+fn insert(store: &ArtifactStore, build_dir: &str, name: &str, content: &str) -> String {
+  let output = Path::new(build_dir).join("output");
+  fs::write(&output, content).unwrap();
+  store.insert(name, &output).unwrap()
+}
+
+fn live(names: &[&str]) -> BTreeSet<String> {
+  names.iter().map(|v| v.to_string()).collect()
+}
+
+#[test]
+fn remove_unreferenced_and_old_outputs() {
+  let build_dir = temp_dir("orirocks-test-gc").to_string_lossy().into_owned();
+  let store = ArtifactStore::new(&build_dir);
+  let v1 = insert(&store, &build_dir, "base", "v1");
+  let v2 = insert(&store, &build_dir, "base", "v2");
+  let v3 = insert(&store, &build_dir, "base", "v3");
+  let gone = insert(&store, &build_dir, "gone", "gone");
+
+  let opts = GcOptions { keep_last: 2, max_size: None, dry_run: true };
+  let report = collect_garbage(&build_dir, &live(&["base"]), &opts).unwrap();
+  let removed = report.removed.iter().map(|v| (v.digest.as_str(), v.reason)).collect::<HashSet<_>>();
+  assert_eq!(removed, HashSet::from([(v1.as_str(), Reason::Superseded), (gone.as_str(), Reason::Unreferenced)]));
+  assert_eq!(report.freed(), 6);
+  assert_eq!(report.kept_size, 4);
+  assert!(store.object_path(&v1).exists());
+  assert!(store.path("gone").exists());
+
+  let opts = GcOptions { dry_run: false, ..opts };
+  let report = collect_garbage(&build_dir, &live(&["base"]), &opts).unwrap();
+  assert_eq!(report.removed.len(), 2);
+  assert!(report.evicted_artifacts.is_empty());
+  assert_eq!(store.objects().unwrap().into_iter().collect::<BTreeSet<_>>(), BTreeSet::from([v2.clone(), v3.clone()]));
+  assert_eq!(store.names().unwrap(), vec!["base"]);
+  assert_eq!(store.digest("base").unwrap(), Some(v3.clone()));
+  let index = store.index().unwrap();
+  assert_eq!(index.history.keys().collect::<Vec<_>>(), vec!["base"]);
+  assert_eq!(index.history["base"], vec![v2.clone(), v3.clone()]);
+
+  // outputs are kept as long as any artifact keeps them
+  let copy = insert(&store, &build_dir, "copy", "v2");
+  assert_eq!(copy, v2);
+  let opts = GcOptions { keep_last: 1, ..opts };
+  let report = collect_garbage(&build_dir, &live(&["base", "copy"]), &opts).unwrap();
+  assert!(report.removed.is_empty());
+  let report = collect_garbage(&build_dir, &live(&["base"]), &opts).unwrap();
+  assert_eq!(report.removed.iter().map(|v| &v.digest).collect::<Vec<_>>(), vec![&v2]);
+  assert_eq!(store.names().unwrap(), vec!["base"]);
+}
+
+#[test]
+fn evict_least_recently_used_outputs() {
+  let build_dir = temp_dir("orirocks-test-gc-evict").to_string_lossy().into_owned();
+  let store = ArtifactStore::new(&build_dir);
+  let old = insert(&store, &build_dir, "app", "old app");
+  let base = insert(&store, &build_dir, "base", "base");
+  let app = insert(&store, &build_dir, "app", "app");
+  let mut index = store.index().unwrap();
+  index.last_used.insert(old.clone(), 30);
+  index.last_used.insert(app.clone(), 20);
+  index.last_used.insert(base.clone(), 10);
+  store.save_index(&index).unwrap();
+
+  let opts = GcOptions { keep_last: 2, max_size: Some(7), dry_run: false };
+  let report = collect_garbage(&build_dir, &live(&["app", "base"]), &opts).unwrap();
+  let removed = report.removed.iter().map(|v| (v.digest.clone(), v.reason)).collect::<Vec<_>>();
+  assert_eq!(removed, vec![(base.clone(), Reason::Evicted), (app.clone(), Reason::Evicted)]);
+  assert_eq!(report.evicted_artifacts, vec!["base", "app"]);
+  assert_eq!(report.kept_size, 7);
+  assert_eq!(store.objects().unwrap(), vec![old.clone()]);
+  assert!(store.names().unwrap().is_empty());
+
+  // using an output makes it the last to be evicted
+  let base = insert(&store, &build_dir, "base", "base");
+  let app = insert(&store, &build_dir, "app", "app");
+  let mut index = store.index().unwrap();
+  index.last_used.insert(base.clone(), 0);
+  index.last_used.insert(app.clone(), 0);
+  store.save_index(&index).unwrap();
+  store.touch(["base"]).unwrap();
+  let opts = GcOptions { keep_last: 1, max_size: Some(4), dry_run: false };
+  let report = collect_garbage(&build_dir, &live(&["app", "base"]), &opts).unwrap();
+  assert_eq!(report.evicted_artifacts, vec!["app"]);
+  assert_eq!(store.digest("base").unwrap(), Some(base));
+}
+
+#[test]
+fn parse_sizes() {
+  assert_eq!(parse_size("512"), Ok(512));
+  assert_eq!(parse_size("10K"), Ok(10 * 1024));
+  assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+  assert_eq!(parse_size("20GiB"), Ok(20 << 30));
+  assert_eq!(parse_size("2 tb"), Ok(2 << 40));
+  assert!(parse_size("10X").is_err());
+  assert!(parse_size("G").is_err());
+  assert_eq!(format_size(512), "512 B");
+  assert_eq!(format_size(3 << 29), "1.5 GiB");
+}
\ No newline at end of file
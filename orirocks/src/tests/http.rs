@@ -0,0 +1,92 @@
+//! A stand-in HTTP server for the tests of the remote cache and of the deployment providers
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Response, Server};
+
+/// A request as a handler sees it
+pub struct Request {
+  pub method: String,
+  pub path: String,
+  pub query: String,
+  /// Values by lowercase name
+  pub headers: HashMap<String, String>,
+  pub body: Vec<u8>
+}
+
+/// Status, headers and body of a response
+pub type Reply = (u16, Vec<(String, String)>, Vec<u8>);
+
+#[cfg(any(feature = "plugin-oci", feature = "plugin-s3"))]
+type Options = Vec<(&'static str, orirocks_api_v3::Value)>;
+
+/// Serves requests on a local port with a handler that gets the state of the server, and records every request
+pub struct TestServer<S> {
+  /// Such as `http://127.0.0.1:8080`
+  pub url: String,
+  pub state: Arc<Mutex<S>>,
+  requests: Arc<Mutex<Vec<String>>>,
+  #[cfg(any(feature = "plugin-oci", feature = "plugin-s3"))]
+  options: Options
+}
+
+impl<S: Default + Send + 'static> TestServer<S> {
+  /// Starts the server. The handler also gets the URL of the server.
+  pub fn start(handler: impl Fn(&mut S, &str, Request) -> Reply + Send + 'static) -> TestServer<S> {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+    let (state, requests) = (Arc::<Mutex<S>>::default(), Arc::<Mutex<Vec<String>>>::default());
+    let (thread_url, thread_state, thread_requests) = (url.clone(), state.clone(), requests.clone());
+    std::thread::spawn(move || {
+      for mut request in server.incoming_requests() {
+        let (path, query) = request.url().split_once('?')
+          .map(|(p, q)| (p.to_string(), q.to_string()))
+          .unwrap_or((request.url().to_string(), String::new()));
+        let headers = request.headers().iter()
+          .map(|v| (v.field.as_str().as_str().to_ascii_lowercase(), v.value.to_string()))
+          .collect();
+        let mut body = vec![];
+        request.as_reader().read_to_end(&mut body).unwrap();
+        let method = request.method().as_str().to_string();
+        thread_requests.lock().unwrap().push(match query.is_empty() {
+          true => format!("{} {}", method, path),
+          false => format!("{} {}?{}", method, path, query)
+        });
+        let received = Request { method, path, query, headers, body };
+        let (status, headers, data) = handler(&mut thread_state.lock().unwrap(), &thread_url, received);
+        let mut response = Response::from_data(data).with_status_code(status);
+        for (k, v) in headers {
+          response.add_header(Header::from_bytes(k.as_bytes(), v.as_bytes()).unwrap());
+        }
+        let _ = request.respond(response);
+      }
+    });
+    TestServer {
+      url,
+      state,
+      requests,
+      #[cfg(any(feature = "plugin-oci", feature = "plugin-s3"))]
+      options: vec![]
+    }
+  }
+
+  /// Takes the method, path and query of the requests received since the last call
+  pub fn requests(&self) -> Vec<String> {
+    std::mem::take(&mut self.requests.lock().unwrap())
+  }
+}
+
+/// Deployment providers are configured with options that name the server
+#[cfg(any(feature = "plugin-oci", feature = "plugin-s3"))]
+impl<S> TestServer<S> {
+  /// Sets the options that `options` starts with, from the URL of the server
+  pub fn with_options(mut self, options: impl FnOnce(&str) -> Options) -> TestServer<S> {
+    self.options = options(&self.url);
+    self
+  }
+
+  /// Options of a provider that talks to this server, followed by `extra`
+  pub fn options(&self, extra: &[(&'static str, orirocks_api_v3::Value)]) -> Options {
+    self.options.iter().chain(extra).cloned().collect()
+  }
+}
\ No newline at end of file
@@ -0,0 +1,87 @@
+use crate::ident::{ArtifactName, FunctionRef, Ident, ImportRef};
+use crate::model::Step;
+use super::{parse, try_parse, validate};
+
+#[test]
+fn grammar() {
+  assert!(Ident::try_from("base_image2").is_ok());
+  assert!(Ident::try_from("my-img").is_err());
+  assert!(Ident::try_from("").is_err());
+  assert!(ImportRef::try_from("qemu").is_ok());
+  assert!(ImportRef::try_from("example/my-plugin").is_ok());
+  assert!(ImportRef::try_from("a/b/c").is_err());
+  assert!(ImportRef::try_from("/vm").is_err());
+  assert!(FunctionRef::try_from("docker::install").is_ok());
+  assert!(FunctionRef::try_from("docker::").is_err());
+  assert!(FunctionRef::try_from("a::b::c").is_err());
+  assert_eq!(FunctionRef::try_from("docker::install").unwrap().library(), Some("docker"));
+  assert!(ArtifactName::try_from("base[arch=aarch64,os=linux-6.1]").is_ok());
+  assert!(ArtifactName::try_from("base[arch]").is_err());
+  assert!(ArtifactName::try_from("base[arch=x").is_err());
+  assert!(ArtifactName::try_from("base[ver=3.17]").is_ok());
+  assert!(ArtifactName::try_from("base[arch=../x]").is_err());
+  assert!(ArtifactName::try_from("base[arch=]").is_err());
+  assert_eq!(
+    Ident::try_from("my-img").unwrap_err(),
+    "invalid identifier `my-img`, expected letters, digits and `_`"
+  );
+  let import = ImportRef::try_from("example/plugin").unwrap();
+  assert_eq!((import.prefix(), import.name()), (Some("example"), "plugin"));
+  let import = ImportRef::try_from("qemu").unwrap();
+  assert_eq!((import.prefix(), import.name()), (None, "qemu"));
+}
+
+#[test]
+fn parse_names() {
+  let project = parse("
+!import
+- require: example/plugin
+  version: 0.1
+---
+!build
+  name: base
+  matrix:
+    arch: [x86_64, aarch64]
+  envs:
+  - name: qemu
+    steps: []
+---
+!build
+  name: derived
+  from: base[arch=aarch64]
+  envs:
+  - name: qemu
+    steps:
+    - invoke_fn: setup
+---
+!function
+  name: setup
+  parameter_spec: {}
+  steps: []
+");
+  assert_eq!(project.imports[0].require, "example/plugin");
+  assert!(matches!(&project.functions["setup"].steps[..], []));
+  validate(&project).unwrap();
+}
+
+#[test]
+fn reject_invalid_names() {
+  let err = try_parse("!build\n  name: my-img\n  envs: []\n").unwrap_err().to_string();
+  assert!(err.contains("invalid artifact name `my-img`"), "{}", err);
+  let err = try_parse("!import\n- require: a/b/c\n  version: 0.1\n").unwrap_err().to_string();
+  assert!(err.contains("invalid import reference `a/b/c`"), "{}", err);
+  let err = try_parse("!build\n  name: x\n  envs:\n  - name: qemu\n    steps:\n    - action: \"\"\n").unwrap_err().to_string();
+  assert!(err.contains("invalid identifier ``"), "{}", err);
+  let err = try_parse("!build\n  name: x\n  envs:\n  - name: qemu\n    steps:\n    - invoke_fn: a-b\n").unwrap_err().to_string();
+  assert!(err.contains("invalid function reference `a-b`"), "{}", err);
+}
+
+#[test]
+fn deserialize_steps() {
+  let step: Step = serde_yaml::from_str("action: copy\nsrc: a").unwrap();
+  assert!(matches!(step, Step::EnvironmentStep(v) if v.action == "copy"));
+  let step: Step = serde_yaml::from_str("invoke_fn: setup\nwhen: true").unwrap();
+  assert!(matches!(step, Step::InvokeFunctionStep(v) if v.invoke_fn == "setup" && v.control.when.is_some()));
+  let step: Step = serde_yaml::from_str("~").unwrap();
+  assert_eq!(step, Step::Null);
+}
\ No newline at end of file
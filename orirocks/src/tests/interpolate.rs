@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use orirocks_api_v3::Value;
+use crate::interpolate::{interpolate, parse_template, Segment};
+use crate::model::{BuildDoc, InvokeFunctionStep, Step, StepControl};
+use crate::expand::expand_steps;
+use crate::util::YamlLocation;
+use super::{parse, validate};
+
+fn value(yaml: &str) -> Value {
+  serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn parse_templates() {
+  assert_eq!(parse_template("docker-${version}.deb").unwrap(), vec![
+    Segment::Literal("docker-".into()),
+    Segment::Reference("version".into()),
+    Segment::Literal(".deb".into())
+  ]);
+  assert_eq!(parse_template("$${version} costs $5").unwrap(), vec![Segment::Literal("${version} costs $5".into())]);
+  assert_eq!(parse_template("${version").unwrap_err().to_string(), "unterminated reference in `${version`");
+  assert_eq!(parse_template("${a-b}").unwrap_err().to_string(), "invalid reference `${a-b}`");
+}
+
+#[test]
+fn interpolate_keeps_types_of_whole_references() {
+  let params = BTreeMap::from([
+    ("packages".into(), value("[curl, git]")),
+    ("size".into(), value("8")),
+    ("name".into(), value("web"))
+  ]);
+  assert_eq!(interpolate(&value("${packages}"), &params).unwrap(), value("[curl, git]"));
+  assert_eq!(interpolate(&value("{ disk: '${name}-${size}G', size: '${size}' }"), &params).unwrap(), value("{ disk: web-8G, size: 8 }"));
+  assert_eq!(interpolate(&value("[a, 'pkgs: ${packages}']"), &params).unwrap_err().to_string(),
+    "`[1]`: parameter `packages` is array and cannot be embedded in a string");
+  assert_eq!(interpolate(&value("${missing}"), &params).unwrap_err().to_string(), "unknown parameter `missing`");
+}
+
+const FUNCTIONS: &str = "
+!function
+  name: install_docker
+  parameter_spec:
+    version:
+      type: string
+    packages:
+      type: !array
+        inner: string
+      default: []
+  steps:
+  - action: run
+    command: apt-get install docker-ce=${version}
+    extra: ${packages}
+  - invoke_fn: install_packages
+    packages: ${packages}
+---
+!function
+  name: install_packages
+  parameter_spec:
+    packages:
+      type: !array
+        inner: string
+  steps:
+  - action: install
+    packages: ${packages}
+";
+
+#[test]
+fn expand_substitutes_parameters() {
+  let project = parse(FUNCTIONS);
+  validate(&project).unwrap();
+  let call = Step::InvokeFunctionStep(InvokeFunctionStep {
+    invoke_fn: "install_docker".try_into().unwrap(),
+    control: StepControl::default(),
+    parameters: BTreeMap::from([("version".into(), value("\"20.10\""))])
+  });
+  let steps = expand_steps(&project, &BuildDoc::default(), &[call], &YamlLocation::default()).unwrap();
+  let steps = steps.iter().map(|v| &v.step).collect::<Vec<_>>();
+  assert_eq!(steps.len(), 2);
+  assert_eq!(steps[0].parameters["command"], value("apt-get install docker-ce=20.10"));
+  assert_eq!(steps[0].parameters["extra"], value("[]"));
+  assert_eq!(steps[1].parameters["packages"], value("[]"));
+}
+
+#[test]
+fn validate_references_in_functions() {
+  let err = validate(&parse(&FUNCTIONS.replace("docker-ce=${version}", "docker-ce=${vers}"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #0: step #0`: interpolation error: `command`: unknown parameter `vers`");
+  let err = validate(&parse(&FUNCTIONS.replace("extra: ${packages}", "extra: x${packages}"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #0: step #0`: interpolation error: `extra`: parameter `packages` is array of string and cannot be embedded in a string");
+  let err = validate(&parse(&FUNCTIONS.replace("    packages: ${packages}\n---", "    packages: ${version}\n---"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #0: step #1`: type mismatch: `packages`: expected array of string, found parameter of type string");
+}
\ No newline at end of file
@@ -0,0 +1,133 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::build::{update_cache, BuildCache, Project};
+use crate::diagnostics::Diagnostics;
+use crate::library::{digest, load_libraries, read_library};
+use crate::plugins::PluginHive;
+use crate::source::SourceMap;
+use super::{parse, temp_dir, try_parse, validate};
+
+const LIBRARY: &[(&str, &str)] = &[
+  ("library.yaml", "version: 1.2.0\n"),
+  ("docker.yaml", "
+!function
+  name: install
+  parameter_spec: {}
+  steps:
+  - invoke_fn: configure
+---
+!function
+  name: configure
+  parameter_spec: {}
+  steps:
+  - action: write_daemon_config
+")
+];
+
+const PROJECT: &str = "
+!library
+- name: docker
+  path: docker
+  version: 1.2.0
+---
+!build
+  name: image
+  envs:
+  - name: qemu
+    steps:
+    - invoke_fn: docker::install
+";
+
+fn create_library(root: &Path, files: &[(&str, &str)]) {
+  let _ = fs::remove_dir_all(root);
+  for (path, contents) in files {
+    fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+    fs::write(root.join(path), contents).unwrap();
+  }
+}
+
+/// Parses a project in `root` and loads its libraries, returning the errors
+fn load(root: &Path, yaml: &str) -> (Project, Vec<String>) {
+  let mut project = parse(yaml);
+  project.root = root.to_path_buf();
+  let mut diagnostics = Diagnostics::default();
+  load_libraries(&mut project, &mut SourceMap::default(), &mut diagnostics);
+  (project, diagnostics.items().iter().map(|v| v.error.to_string()).collect())
+}
+
+fn project_root(name: &str) -> PathBuf {
+  let root = temp_dir(name);
+  create_library(&root.join("docker"), LIBRARY);
+  root
+}
+
+#[test]
+fn load_functions_from_library() {
+  let root = project_root("orirocks-test-library-load");
+  let (project, errors) = load(&root, PROJECT);
+  assert!(errors.is_empty(), "{:?}", errors);
+  let mut functions = project.functions.keys().collect::<Vec<_>>();
+  functions.sort();
+  assert_eq!(functions, ["docker::configure", "docker::install"]);
+  assert_eq!(project.library_digests["docker"], digest(&read_library(&root.join("docker")).unwrap()));
+  let mut diagnostics = Diagnostics::default();
+  crate::build::check_project(&project, &mut diagnostics);
+  // unused library functions are not worth a warning
+  assert!(diagnostics.items().is_empty(), "{:?}", diagnostics.items());
+  let err = validate(&load(&root, &PROJECT.replace("docker::install", "docker::instal")).0).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: qemu/step #0`: function `docker::instal` not found, did you mean `docker::install`?");
+}
+
+#[test]
+fn archives_have_the_same_digest() {
+  let root = project_root("orirocks-test-library-archive");
+  fs::write(root.join("docker/.draft.yaml"), "!function\n").unwrap();
+  fs::create_dir_all(root.join("docker/.git")).unwrap();
+  fs::write(root.join("docker/.git/config.yaml"), "").unwrap();
+  let mut builder = tar::Builder::new(GzEncoder::new(File::create(root.join("docker.tar.gz")).unwrap(), Compression::default()));
+  builder.append_dir_all("docker-1.2.0", root.join("docker")).unwrap();
+  builder.into_inner().unwrap().finish().unwrap();
+  let files = read_library(&root.join("docker.tar.gz")).unwrap();
+  assert_eq!(files, read_library(&root.join("docker")).unwrap());
+  let hash = digest(&files);
+  assert_eq!(hash, digest(&read_library(&root.join("docker")).unwrap()));
+  let (project, errors) = load(&root, &PROJECT.replace("path: docker", "path: docker.tar.gz").replace("version: 1.2.0", &format!("hash: {}", hash)));
+  assert!(errors.is_empty(), "{:?}", errors);
+  assert!(project.functions.contains_key("docker::install"));
+}
+
+#[test]
+fn libraries_must_match_their_pins() {
+  let root = project_root("orirocks-test-library-pins");
+  let (_, errors) = load(&root, &PROJECT.replace("version: 1.2.0", "version: 1.3.0"));
+  assert_eq!(errors, ["in `test.yaml: document #0: docker`: library `docker`: version is `1.2.0`, but `1.3.0` is required"]);
+  let (_, errors) = load(&root, &PROJECT.replace("version: 1.2.0", "hash: sha256:00"));
+  assert!(errors[0].contains("library `docker`: hash is `sha256:"), "{:?}", errors);
+  let (_, errors) = load(&root, &PROJECT.replace("  version: 1.2.0\n", ""));
+  assert_eq!(errors, ["in `test.yaml: document #0: docker`: library `docker`: must be pinned by `version` or `hash`"]);
+}
+
+#[test]
+fn report_collisions() {
+  let root = project_root("orirocks-test-library-collisions");
+  fs::write(root.join("docker/more.yaml"), "!function\n  name: install\n  parameter_spec: {}\n  steps: []\n").unwrap();
+  let (_, errors) = load(&root, PROJECT);
+  assert_eq!(errors, ["in `docker/more.yaml: document #0`: duplicate `function` `docker::install`"]);
+  let project = try_parse(&format!("{}---\n!library\n- name: docker\n  path: other\n  version: 1.0\n", PROJECT));
+  assert_eq!(project.unwrap_err().to_string(), "in `test.yaml: document #2: docker`: duplicate `library` `docker`");
+}
+
+#[test]
+fn library_changes_dirty_artifacts() {
+  let root = project_root("orirocks-test-library-cache");
+  let plugins = PluginHive::from_providers((vec![], vec![]));
+  let mut cache = BuildCache::default();
+  let dirty = |project: &Project, cache: &mut BuildCache| update_cache(project, &plugins, cache).unwrap().artifacts.len();
+  assert_eq!(dirty(&load(&root, PROJECT).0, &mut cache), 1);
+  assert_eq!(dirty(&load(&root, PROJECT).0, &mut cache), 0);
+  // a new release that does not change the functions that are called still dirties the artifact
+  fs::write(root.join("docker/library.yaml"), "version: 1.2.1\n").unwrap();
+  assert_eq!(dirty(&load(&root, &PROJECT.replace("1.2.0", "1.2.1")).0, &mut cache), 1);
+}
\ No newline at end of file
@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::manifest::{Workspace, MANIFEST_FILE};
+use crate::source::SourceMap;
+use super::temp_dir;
+
+/// Creates a fresh directory with the given files
+fn create_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+  let root = temp_dir(name);
+  for (path, contents) in files {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+  }
+  root
+}
+
+#[test]
+fn discover_from_subdirectory() {
+  let root = create_tree("orirocks-test-manifest-discover", &[
+    (MANIFEST_FILE, "build_dir: out\n"),
+    ("images/base.yaml", ""),
+    ("images/nested/derived.yaml", ""),
+    ("common.yaml", ""),
+    ("notes.txt", ""),
+    ("out/cache.yaml", ""),
+    (".git/config.yaml", "")
+  ]);
+  let workspace = Workspace::discover(&root.join("images/nested"), &mut SourceMap::default()).unwrap();
+  assert_eq!(workspace.root, root);
+  assert_eq!(workspace.build_dir(), root.join("out"));
+  assert_eq!(workspace.files(&workspace.build_dir()).unwrap(), ["common.yaml", "images/base.yaml", "images/nested/derived.yaml"]);
+  // a build directory given on the command line replaces the one of the manifest
+  assert_eq!(workspace.files(&root.join("images/nested")).unwrap(), ["common.yaml", "images/base.yaml", "out/cache.yaml"]);
+}
+
+#[test]
+fn include_and_exclude() {
+  let root = create_tree("orirocks-test-manifest-globs", &[
+    ("config/orirocks.yaml", "root: ..\ninclude: [functions.yaml, 'images/*.yaml', '**/*.yaml']\nexclude: ['images/old*']\n"),
+    ("functions.yaml", ""),
+    ("images/a.yaml", ""),
+    ("images/old.yaml", ""),
+    ("build/cache.yaml", "")
+  ]);
+  let workspace = Workspace::load(&root.join("config").join(MANIFEST_FILE), &mut SourceMap::default()).unwrap();
+  assert_eq!(workspace.root, root.join("config/.."));
+  assert_eq!(workspace.files(&workspace.build_dir()).unwrap(), ["functions.yaml", "images/a.yaml"]);
+}
+
+#[test]
+fn manifest_errors() {
+  let root = create_tree("orirocks-test-manifest-errors", &[
+    (MANIFEST_FILE, "incude: ['*.yaml']\n"),
+    ("nested/orirocks.yaml", "include: ['[']\n"),
+    ("plugins/orirocks.yaml", "plugin_path: [plugins]\n")
+  ]);
+  let err = Workspace::load(&root.join(MANIFEST_FILE), &mut SourceMap::default()).unwrap_err().to_string();
+  assert!(err.contains("unknown field `incude`"), "{}", err);
+  let workspace = Workspace::discover(&root.join("nested"), &mut SourceMap::default()).unwrap();
+  let err = workspace.files(&workspace.build_dir()).unwrap_err().to_string();
+  assert!(err.contains("invalid glob `[`"), "{}", err);
+  let err = Workspace::discover(&root.join("plugins"), &mut SourceMap::default()).unwrap_err().to_string();
+  // only built-in plugins are supported, so there is no plugin search path to set
+  assert!(err.contains("unknown field `plugin_path`"), "{}", err);
+  let err = Workspace::discover(Path::new("/"), &mut SourceMap::default()).unwrap_err().to_string();
+  assert_eq!(err, "could not find `orirocks.yaml` in `/` or any parent directory");
+}
\ No newline at end of file
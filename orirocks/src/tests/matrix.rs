@@ -0,0 +1,94 @@
+use crate::build::{plan_build, update_cache, BuildCache};
+use crate::plugins::PluginHive;
+use super::{parse, try_parse, validate};
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: base
+  matrix:
+    arch: [x86_64, aarch64]
+    ver: ['3.17', '3.18']
+  envs:
+  - name: test/mock
+    steps:
+    - action: bootstrap
+      arch: ${matrix.arch}
+      release: v${matrix.ver}
+---
+!build
+  name: web
+  from: base
+  matrix:
+    arch: [aarch64]
+    ver: ['3.18']
+  envs:
+  - name: test/mock
+    steps:
+    - action: install
+---
+!build
+  name: bundle
+  from: base[arch=x86_64,ver=3.17]
+  depends: [base]
+  envs:
+  - name: test/mock
+    steps:
+    - action: pack
+";
+
+fn no_plugins() -> PluginHive {
+  PluginHive::from_providers((vec![], vec![]))
+}
+
+#[test]
+fn matrix_expands_into_variants() {
+  let project = parse(PROJECT);
+  validate(&project).unwrap();
+  assert_eq!(project.matrices["base"], vec![
+    "base[arch=x86_64,ver=3.17]".to_string(),
+    "base[arch=x86_64,ver=3.18]".into(),
+    "base[arch=aarch64,ver=3.17]".into(),
+    "base[arch=aarch64,ver=3.18]".into()
+  ]);
+  let web = &project.builds["web[arch=aarch64,ver=3.18]"];
+  assert_eq!(web.from.as_deref(), Some("base[arch=aarch64,ver=3.18]"));
+  let bundle = &project.builds["bundle"];
+  assert_eq!(bundle.from.as_deref(), Some("base[arch=x86_64,ver=3.17]"));
+  assert_eq!(bundle.depends.as_ref().unwrap().len(), 4);
+  let (plan, _) = plan_build(&project, None, false, &no_plugins()).unwrap();
+  let plan = plan.to_string();
+  assert!(plan.contains("base[arch=aarch64,ver=3.17]\n  test/mock\n    #0 bootstrap arch=aarch64 release=v3.17\n"));
+  assert!(plan.contains("web[arch=aarch64,ver=3.18] (after base[arch=aarch64,ver=3.18])\n"));
+}
+
+#[test]
+fn matrix_variants_have_own_cache_entries() {
+  let mut cache = BuildCache::default();
+  let project = parse(PROJECT);
+  assert_eq!(update_cache(&project, &no_plugins(), &mut cache).unwrap().artifacts.len(), 6);
+  let project = parse(&PROJECT.replace("ver: ['3.17', '3.18']", "ver: ['3.17', '3.18', '3.19']"));
+  let dirty = update_cache(&project, &no_plugins(), &mut cache).unwrap().artifacts.into_iter().map(|v| v.0).collect::<Vec<_>>();
+  assert_eq!(dirty, vec!["base[arch=aarch64,ver=3.19]".to_string(), "base[arch=x86_64,ver=3.19]".into(), "bundle".into()]);
+}
+
+#[test]
+fn matrix_references_must_select_variants() {
+  let err = try_parse(&PROJECT.replace("from: base[arch=x86_64,ver=3.17]", "from: base[arch=x86_64]")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #3`: invalid matrix reference: `from: base[arch=x86_64]` selects 2 variants, select one like `base[arch=x86_64,ver=3.17]`");
+  let err = try_parse(&PROJECT.replace("depends: [base]", "depends: ['base[os=linux]']")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #3`: invalid matrix reference: matrix `base` has no axis `os`");
+  let err = try_parse(&PROJECT.replace("arch: [aarch64]", "arch: [riscv64]")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2: web[arch=riscv64,ver=3.18]`: invalid matrix reference: no variant of `base` matches `base`");
+  let err = try_parse(&PROJECT.replace("arch: [aarch64]", "arch: [[aarch64]]")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2`: invalid matrix: `arch[0]`: expected string, integer or bool, found array");
+  let err = try_parse(&PROJECT.replace("ver: ['3.18']", "ver: [3.10]")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2`: invalid matrix: `ver[0]`: expected string, integer or bool, found float");
+  let err = try_parse(&PROJECT.replace("ver: ['3.18']", "ver: [1, '1']")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2`: invalid matrix: `ver[1]`: value `1` appears twice, so two variants would have the same name");
+  let err = try_parse(&PROJECT.replace("arch: [aarch64]", "arch: [aarch64, ../etc]")).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2`: invalid matrix: `arch[1]`: invalid value `../etc`, expected letters, digits, `_`, `-` and `.`");
+}
\ No newline at end of file
@@ -1,2 +1,138 @@
 mod model;
-mod float;
\ No newline at end of file
+mod float;
+mod build;
+mod value;
+mod params;
+mod interpolate;
+mod expand;
+mod vars;
+mod plan;
+mod matrix;
+mod source;
+mod diagnostics;
+mod references;
+mod ident;
+mod manifest;
+mod library;
+mod deploy;
+#[cfg(feature = "plugin-qemu")]
+mod qemu;
+#[cfg(feature = "plugin-fs")]
+mod directory;
+#[cfg(feature = "plugin-oci")]
+mod oci;
+#[cfg(feature = "plugin-s3")]
+mod s3;
+mod store;
+mod gc;
+mod remote;
+mod http;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use orirocks_api_v3::{CancellationToken, Context, DeploymentProvider, Environment, EnvironmentProvider, Progress, Value, ValueType};
+use crate::build::{check_project, collect_project, BuildOptions, Project};
+use crate::diagnostics::{Diagnostics, Severity};
+use crate::plugins::PluginHive;
+use crate::remote::RemoteCache;
+use crate::util::ORResult;
+
+/// Returns the first error that was recorded, for tests that stop at the first problem
+pub fn first_error(diagnostics: Diagnostics) -> ORResult<()> {
+  match diagnostics.items.into_iter().find(|v| v.severity == Severity::Error) {
+    Some(v) => Err(v.error),
+    None => Ok(())
+  }
+}
+
+/// Parses a project made of a single file, `test.yaml`
+pub fn try_parse(yaml: &str) -> ORResult<Project> {
+  let mut diagnostics = Diagnostics::default();
+  let project = collect_project(vec![("test.yaml".into(), Box::new(Cursor::new(yaml.to_string())) as Box<dyn Read>)], &mut diagnostics);
+  first_error(diagnostics).map(|_| project)
+}
+
+pub fn parse(yaml: &str) -> Project {
+  try_parse(yaml).unwrap()
+}
+
+/// Checks a project like `orirocks check`, returning the first error
+pub fn validate(project: &Project) -> ORResult<()> {
+  let mut diagnostics = Diagnostics::default();
+  check_project(project, &mut diagnostics);
+  first_error(diagnostics)
+}
+
+static TEMP_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates an empty directory named after `name`, the process and a counter, so that tests never share one
+pub fn temp_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("{}-{}-{}", name, std::process::id(), TEMP_DIRS.fetch_add(1, Ordering::SeqCst)));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+/// Options of a build into a fresh build directory
+pub fn build_options(name: &str, remote_cache: Option<RemoteCache>) -> BuildOptions {
+  BuildOptions { rebuild: false, build_dir: temp_dir(name).to_string_lossy().into_owned(), cancel: CancellationToken::new(), remote_cache }
+}
+
+pub type Events = Arc<Mutex<Vec<String>>>;
+
+/// Records every call it receives, and cancels the build when it sees the `cancel` action.
+/// `copy_file` takes a `source` resource.
+pub struct MockProvider {
+  pub events: Events
+}
+
+struct MockEnvironment {
+  events: Events
+}
+
+impl EnvironmentProvider for MockProvider {
+  fn name(&self) -> &str {
+    "mock"
+  }
+
+  fn action_schema(&self, name: &str) -> Option<ValueType> {
+    (name == "copy_file").then(|| ValueType::Record {
+      fields: BTreeMap::from([("source".into(), ValueType::Path), ("dest".into(), ValueType::String)])
+    })
+  }
+
+  fn create(&self, ctx: &Context, base: String, dependencies: HashMap<String, String>, _options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String> {
+    ctx.info("creating");
+    let mut events = self.events.lock().unwrap();
+    events.push(format!("create {} from `{}`", ctx.tag().artifact, base));
+    let mut sources = dependencies.into_iter().filter(|v| v.0.starts_with("src:")).collect::<Vec<_>>();
+    sources.sort();
+    events.extend(sources.into_iter().map(|(k, v)| format!("dependency {} = {}", k, v)));
+    Ok(Box::new(MockEnvironment { events: self.events.clone() }))
+  }
+}
+
+impl Environment for MockEnvironment {
+  fn action(&mut self, ctx: &Context, name: &str, _options: HashMap<String, Value>) -> Result<(), String> {
+    self.events.lock().unwrap().push(format!("action {}", ctx.tag().step.as_ref().unwrap()));
+    if name == "cancel" {
+      ctx.cancellation().cancel();
+    }
+    ctx.progress(Progress::Percent(100.0));
+    ctx.check_cancelled()
+  }
+
+  fn finish(self: Box<Self>, ctx: &Context, path: &str) -> Result<(), String> {
+    self.events.lock().unwrap().push(format!("finish {}", ctx.tag().artifact));
+    fs::write(path, &ctx.tag().artifact).map_err(|v| v.to_string())
+  }
+}
+
+/// Plugins with the `mock` environment and the given deployment providers
+pub fn mock_hive(events: &Events, deployments: Vec<Box<dyn DeploymentProvider>>) -> PluginHive {
+  PluginHive::from_providers((vec![Box::new(MockProvider { events: events.clone() })], deployments))
+}
\ No newline at end of file
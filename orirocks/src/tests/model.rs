@@ -1,6 +1,6 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use orirocks_api_v3::{Value, ValueType};
-use crate::model::{BuildDoc, Document, Environment, EnvironmentStep, FunctionDoc, Import, InvokeFunctionStep, Parameter, Step};
+use crate::model::{BuildDoc, Document, Environment, EnvironmentStep, FunctionDoc, Import, InvokeFunctionStep, Parameter, Step, StepControl};
 
 #[test]
 fn parse_valid_import_1() {
@@ -16,11 +16,11 @@ fn parse_valid_import_1() {
   let parsed_obj: Document = serde_yaml::from_str(yaml).unwrap();
   let expected_obj = Document::Import(vec![
     Import {
-      require: "example/plugin".into(),
+      require: "example/plugin".try_into().unwrap(),
       version: "0.7.27".into()
     },
     Import {
-      require: "example/other_plugin".into(),
+      require: "example/other_plugin".try_into().unwrap(),
       version: "0.1".into()
     }
   ]);
@@ -64,7 +64,7 @@ fn parse_valid_function_1() {
 ";
   let parsed_obj: Document = serde_yaml::from_str(yaml).unwrap();
   let expected_obj = Document::Function(FunctionDoc {
-    name: "my_function".into(),
+    name: "my_function".try_into().unwrap(),
     parameter_spec: BTreeMap::from([
       ("param1".into(), Parameter { type_: ValueType::Integer, default: None }),
       ("param2".into(), Parameter { type_: ValueType::Bool, default: Some(Value::Bool(true)) }),
@@ -75,14 +75,16 @@ fn parse_valid_function_1() {
     ]),
     steps: vec![
       Step::EnvironmentStep(EnvironmentStep {
-        action: "copy_file".into(),
+        action: "copy_file".try_into().unwrap(),
+        control: StepControl::default(),
         parameters: BTreeMap::from([
           ("source".into(), Value::String("src:assets/script.js".into())),
           ("dest".into(), Value::String("vm:/root/script.js".into()))
         ])
       }),
       Step::InvokeFunctionStep(InvokeFunctionStep {
-        invoke_fn: "install_docker".into(),
+        invoke_fn: "install_docker".try_into().unwrap(),
+        control: StepControl::default(),
         parameters: BTreeMap::from([
           ("version".into(), Value::String("20.10.23".into()))
         ])
@@ -112,32 +114,124 @@ fn parse_valid_build_1() {
 ";
   let parsed_obj: Document = serde_yaml::from_str(yaml).unwrap();
   let expected_obj = Document::Build(BuildDoc {
-    name: "my_image".into(),
-    from: Some("alpine_317_virt".to_string()),
+    name: "my_image".try_into().unwrap(),
+    from: Some("alpine_317_virt".try_into().unwrap()),
     depends: None,
+    matrix: None,
     envs: vec![
       Environment {
-        name: "qemu".into(),
+        name: "qemu".try_into().unwrap(),
         parameters: BTreeMap::from([
           ("foo".into(), Value::Integer(2))
         ]),
         steps: vec![
           Step::EnvironmentStep(EnvironmentStep {
-            action: "copy_file".into(),
+            action: "copy_file".try_into().unwrap(),
+            control: StepControl::default(),
             parameters: BTreeMap::from([
               ("source".into(), Value::String("src:assets/script.js".into())),
               ("dest".into(), Value::String("vm:/root/script.js".into()))
             ])
           }),
           Step::InvokeFunctionStep(InvokeFunctionStep {
-            invoke_fn: "install_docker".into(),
+            invoke_fn: "install_docker".try_into().unwrap(),
+            control: StepControl::default(),
             parameters: BTreeMap::from([
               ("version".into(), Value::String("20.10.23".into()))
             ])
           })
         ],
       }
-    ]
+    ],
+    variant: None
+  });
+  assert_eq!(parsed_obj, expected_obj);
+}
+
+#[test]
+fn parse_valid_function_2() {
+  let yaml = "
+!function
+  name: convert_image
+  parameter_spec:
+    image:
+      type: path
+    format:
+      type:
+        !enum
+          values: [qcow2, raw]
+      default: qcow2
+    label:
+      type:
+        !optional
+          inner: string
+    disk:
+      type:
+        !record
+          fields:
+            size: integer
+            compress:
+              !optional
+                inner: bool
+  steps: []
+";
+  let parsed_obj: Document = serde_yaml::from_str(yaml).unwrap();
+  let expected_obj = Document::Function(FunctionDoc {
+    name: "convert_image".try_into().unwrap(),
+    parameter_spec: BTreeMap::from([
+      ("image".into(), Parameter { type_: ValueType::Path, default: None }),
+      ("format".into(), Parameter { type_: ValueType::Enum { values: vec!["qcow2".into(), "raw".into()] }, default: Some(Value::String("qcow2".into())) }),
+      ("label".into(), Parameter { type_: ValueType::Optional { inner: Box::new(ValueType::String) }, default: None }),
+      ("disk".into(), Parameter { type_: ValueType::Record { fields: BTreeMap::from([
+        ("size".into(), ValueType::Integer),
+        ("compress".into(), ValueType::Optional { inner: Box::new(ValueType::Bool) })
+      ]) }, default: None })
+    ]),
+    steps: vec![]
+  });
+  assert_eq!(parsed_obj, expected_obj);
+}
+
+
+#[test]
+fn parse_step_with_null_parameter() {
+  let yaml = "
+action: copy_file
+source: src:assets/script.js
+mode:
+";
+  let parsed_obj: Step = serde_yaml::from_str(yaml).unwrap();
+  let expected_obj = Step::EnvironmentStep(EnvironmentStep {
+    action: "copy_file".try_into().unwrap(),
+    control: StepControl::default(),
+    parameters: BTreeMap::from([
+      ("source".into(), Value::String("src:assets/script.js".into())),
+      ("mode".into(), Value::Null)
+    ])
+  });
+  assert_eq!(parsed_obj, expected_obj);
+}
+
+#[test]
+fn parse_step_with_control() {
+  let yaml = "
+invoke_fn: install_package
+when: ${vars.with_tools}
+for_each: [curl, git]
+as: package
+name: ${package}
+";
+  let parsed_obj: Step = serde_yaml::from_str(yaml).unwrap();
+  let expected_obj = Step::InvokeFunctionStep(InvokeFunctionStep {
+    invoke_fn: "install_package".try_into().unwrap(),
+    control: StepControl {
+      when: Some(Value::String("${vars.with_tools}".into())),
+      for_each: Some(Value::Array(vec![Value::String("curl".into()), Value::String("git".into())])),
+      as_: Some("package".try_into().unwrap())
+    },
+    parameters: BTreeMap::from([
+      ("name".into(), Value::String("${package}".into()))
+    ])
   });
   assert_eq!(parsed_obj, expected_obj);
 }
\ No newline at end of file
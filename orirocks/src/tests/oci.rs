@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use orirocks_api_v3::{CancellationToken, Context, DeploymentProvider, Value};
+use orirocks_api_v3::digest::sha256_hex;
+use orirocks_oci::OciDeploymentProvider;
+use crate::report::LogReporter;
+use super::http::{Reply, Request, TestServer};
+use super::temp_dir;
+
+/// Token that the registry accepts when started with auth, given out for the credentials `user:pass`
+const TOKEN: &str = "secret";
+
+/// Contents of an in-memory registry
+#[derive(Default)]
+struct State {
+  blobs: HashMap<String, Vec<u8>>,
+  uploads: HashMap<String, Vec<u8>>,
+  /// Media type and contents of manifests, by `repository:reference`
+  manifests: HashMap<String, (String, Vec<u8>)>
+}
+
+type Registry = TestServer<State>;
+
+/// Starts a registry that serves repositories under `images/`
+fn start_registry(auth: bool) -> Registry {
+  TestServer::start(move |state, url, request| handle(state, url, auth, request)).with_options(|url| vec![
+    ("registry", Value::String(url.to_string())),
+    ("repository", Value::String("images/{name}".into()))
+  ])
+}
+
+impl Registry {
+  fn manifest(&self, reference: &str) -> Option<(String, serde_json::Value)> {
+    let state = self.state.lock().unwrap();
+    let (media_type, bytes) = state.manifests.get(reference)?;
+    Some((media_type.clone(), serde_json::from_slice(bytes).unwrap()))
+  }
+}
+
+fn digest(bytes: &[u8]) -> String {
+  format!("sha256:{}", sha256_hex(bytes))
+}
+
+fn handle(state: &mut State, url: &str, auth: bool, request: Request) -> Reply {
+  let Request { method, path, query, headers: request_headers, body } = request;
+  let header = |name: &str| request_headers.get(name).cloned();
+  let (authorization, range, content_type) = (header("authorization"), header("content-range"), header("content-type"));
+
+  let mut headers = vec![];
+  let (status, data) = if path == "/token" {
+    match authorization.as_deref() == Some("Basic dXNlcjpwYXNz") {
+      true => (200, format!("{{\"token\":\"{}\"}}", TOKEN).into_bytes()),
+      false => (401, vec![])
+    }
+  } else if auth && authorization != Some(format!("Bearer {}", TOKEN)) {
+    let challenge = format!("Bearer realm=\"{}/token\",service=\"test\",scope=\"repository:images/disk:pull,push\"", url);
+    headers.push(("WWW-Authenticate".to_string(), challenge));
+    (401, vec![])
+  } else {
+    let rest = path.strip_prefix("/v2/").unwrap_or_default();
+    if let Some((_, id)) = rest.split_once("/blobs/uploads/") {
+      match method.as_str() {
+        "POST" => {
+          let id = state.uploads.len().to_string();
+          state.uploads.insert(id.clone(), vec![]);
+          headers.push(("Location".to_string(), format!("{}{}", path, id)));
+          (202, vec![])
+        }
+        "PATCH" => {
+          let upload = state.uploads.get_mut(id).unwrap();
+          assert_eq!(range.unwrap(), format!("{}-{}", upload.len(), upload.len() + body.len() - 1));
+          upload.extend(body);
+          headers.push(("Location".to_string(), path.clone()));
+          (202, vec![])
+        }
+        _ => {
+          let data = state.uploads.remove(id).unwrap();
+          match query.strip_prefix("digest=") == Some(&digest(&data)) {
+            true => {
+              state.blobs.insert(digest(&data), data);
+              (201, vec![])
+            }
+            false => (400, b"digest mismatch".to_vec())
+          }
+        }
+      }
+    } else if let Some((_, digest)) = rest.split_once("/blobs/") {
+      (if state.blobs.contains_key(digest) { 200 } else { 404 }, vec![])
+    } else if let Some((repository, reference)) = rest.split_once("/manifests/") {
+      state.manifests.insert(format!("{}:{}", repository, reference), (content_type.unwrap(), body));
+      (201, vec![])
+    } else {
+      (404, vec![])
+    }
+  };
+  (status, headers, data)
+}
+
+fn deploy(artifact: &str, path: &Path, options: &[(&str, Value)]) -> Result<(), String> {
+  let dependencies = HashMap::from([(format!("artifact:{}", artifact), path.to_string_lossy().into_owned())]);
+  let options = options.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+  let reporter = LogReporter::new();
+  OciDeploymentProvider.deploy(&Context::new("registry".into(), &reporter, CancellationToken::new()), dependencies, options)
+}
+
+fn tags(tags: &[&str]) -> Value {
+  Value::Array(tags.iter().map(|v| Value::String(v.to_string())).collect())
+}
+
+#[test]
+fn push_disk_image() {
+  let registry = start_registry(false);
+  let dir = temp_dir("orirocks-test-oci-disk");
+  fs::write(dir.join("disk"), "0123456789").unwrap();
+  let options = registry.options(&[("tags", tags(&["1.0-{variant}", "latest"])), ("chunk_size", Value::Integer(4))]);
+  deploy("disk[arch=x86_64]", &dir.join("disk"), &options).unwrap();
+  let disk = digest(b"0123456789");
+  let requests = registry.requests();
+  // three chunks of the disk, and the empty config
+  assert_eq!(requests.iter().filter(|v| v.starts_with("PATCH")).count(), 4);
+  assert!(requests.contains(&format!("HEAD /v2/images/disk/blobs/{}", disk)));
+  assert_eq!(registry.state.lock().unwrap().blobs[&disk], b"0123456789");
+
+  let (media_type, manifest) = registry.manifest("images/disk:1.0-x86_64").unwrap();
+  assert_eq!(media_type, "application/vnd.oci.image.manifest.v1+json");
+  assert_eq!(manifest["artifactType"], "application/vnd.orirocks.disk.v1");
+  assert_eq!(manifest["config"]["digest"], digest(b"{}"));
+  assert_eq!(manifest["layers"][0]["digest"], disk);
+  assert_eq!(manifest["layers"][0]["size"], 10);
+  assert_eq!(registry.manifest("images/disk:latest").unwrap().1, manifest);
+
+  // blobs the registry has are not uploaded again
+  deploy("disk[arch=x86_64]", &dir.join("disk"), &options).unwrap();
+  assert!(registry.requests().iter().all(|v| v.starts_with("HEAD") || v.contains("/manifests/")));
+
+  assert_eq!(deploy("disk[arch=x86_64]", &dir.join("disk"), &[options[0].clone(), ("repository", Value::String("Images".into()))]).unwrap_err(),
+    "`repository`: `Images` is not a valid repository name");
+  assert_eq!(deploy("disk", &dir.join("disk"), &[options[0].clone(), options[1].clone(), ("tags", tags(&["-{name}"]))]).unwrap_err(),
+    "`tags`: `-disk` is not a valid tag");
+}
+
+#[test]
+fn bearer_auth() {
+  let registry = start_registry(true);
+  let dir = temp_dir("orirocks-test-oci-auth");
+  fs::write(dir.join("disk"), "disk").unwrap();
+  let credentials = |password: &str| registry.options(&[("username", Value::String("user".into())), ("password", Value::String(password.into()))]);
+  deploy("disk", &dir.join("disk"), &credentials("pass")).unwrap();
+  let requests = registry.requests();
+  assert_eq!(requests.iter().filter(|v| v.starts_with("GET /token?")).count(), 1);
+  assert!(registry.manifest("images/disk:latest").is_some());
+
+  let err = deploy("disk", &dir.join("disk"), &credentials("wrong")).unwrap_err();
+  assert!(err.starts_with(&format!("could not get a token from `{}/token`", registry.url)), "{}", err);
+
+  registry.requests();
+  deploy("disk", &dir.join("disk"), &registry.options(&[("token", Value::String(TOKEN.into()))])).unwrap();
+  assert!(!registry.requests().iter().any(|v| v.starts_with("GET /token?")));
+  let err = deploy("disk", &dir.join("disk"), &registry.options(&[("token", Value::String("expired".into()))])).unwrap_err();
+  assert!(err.contains("status 401"), "{}", err);
+}
+
+#[test]
+fn push_image_layout() {
+  let registry = start_registry(false);
+  let layout = temp_dir("orirocks-test-oci-layout");
+  let blobs = layout.join("blobs/sha256");
+  fs::create_dir_all(&blobs).unwrap();
+  let write_blob = |bytes: &[u8]| {
+    let digest = digest(bytes);
+    fs::write(blobs.join(digest.strip_prefix("sha256:").unwrap()), bytes).unwrap();
+    digest
+  };
+  let (config, layer) = (write_blob(b"{\"architecture\":\"amd64\"}"), write_blob(b"layer"));
+  let manifest = format!(
+    "{{\"schemaVersion\":2,\"mediaType\":\"application/vnd.oci.image.manifest.v1+json\",\"config\":{{\"mediaType\":\"application/vnd.oci.image.config.v1+json\",\"digest\":\"{}\",\"size\":24}},\"layers\":[{{\"mediaType\":\"application/vnd.oci.image.layer.v1.tar\",\"digest\":\"{}\",\"size\":5}}]}}",
+    config, layer);
+  let manifest_digest = write_blob(manifest.as_bytes());
+  fs::write(layout.join("oci-layout"), "{\"imageLayoutVersion\":\"1.0.0\"}").unwrap();
+  fs::write(layout.join("index.json"), format!(
+    "{{\"schemaVersion\":2,\"manifests\":[{{\"mediaType\":\"application/vnd.oci.image.manifest.v1+json\",\"digest\":\"{}\",\"size\":{}}}]}}",
+    manifest_digest, manifest.len())).unwrap();
+
+  deploy("app", &layout, &registry.options(&[("tags", tags(&["1.0"]))])).unwrap();
+  let state = registry.state.lock().unwrap();
+  assert!(state.blobs.contains_key(&config) && state.blobs.contains_key(&layer));
+  assert_eq!(state.manifests[&format!("images/app:{}", manifest_digest)].1, manifest.as_bytes());
+  assert_eq!(state.manifests["images/app:1.0"], ("application/vnd.oci.image.manifest.v1+json".to_string(), manifest.into_bytes()));
+}
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use orirocks_api_v3::{Value, ValueType};
+use crate::model::{Parameter, ParameterSpec};
+use crate::params::{resolve_parameters, spec_type};
+use crate::util::{ORError, YamlLocation};
+use super::{parse, validate};
+
+fn value(yaml: &str) -> Value {
+  serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn check_nested_types() {
+  let ty = ValueType::Dict { inner: Box::new(ValueType::Array { inner: Box::new(ValueType::Float) }) };
+  assert_eq!(ty.check(&value("{ a: [1, 2.5], b: [] }")), Ok(()));
+  let err = ty.check(&value("{ a: [1, 2.5], b: [3, foo] }")).unwrap_err();
+  assert_eq!(err.path, "b[1]");
+  assert_eq!(err.to_string(), "`b[1]`: expected float, found string");
+  let err = ty.check(&value("[1]")).unwrap_err();
+  assert_eq!(err.to_string(), "expected dict of array of float, found array");
+}
+
+#[test]
+fn check_rich_types() {
+  let format = ValueType::Enum { values: vec!["qcow2".into(), "raw".into()] };
+  assert_eq!(format.check(&value("raw")), Ok(()));
+  assert_eq!(format.check(&value("vmdk")).unwrap_err().to_string(), "expected one of `qcow2`, `raw`, found `vmdk`");
+  let disk = ValueType::Record { fields: BTreeMap::from([
+    ("image".into(), ValueType::Path),
+    ("format".into(), format),
+    ("label".into(), ValueType::Optional { inner: Box::new(ValueType::String) })
+  ]) };
+  assert_eq!(disk.check(&value("{ image: src:disk.img, format: raw }")), Ok(()));
+  assert_eq!(disk.check(&value("{ image: src:disk.img, format: raw, size: 3 }")).unwrap_err().to_string(), "`size`: unknown field");
+  assert_eq!(disk.check(&value("{ image: src:disk.img }")).unwrap_err().to_string(), "`format`: missing required value");
+  assert_eq!(disk.check(&value("{ image: disk.img, format: raw }")).unwrap_err().to_string(),
+    "`image`: expected resource location like `src:path/to/file`, found `disk.img`");
+  let disks = ValueType::Array { inner: Box::new(disk) };
+  assert_eq!(disks.resources(&value("[{ image: src:a.img, format: raw }, { image: vm:/b.img, format: raw, label: src:c }]")),
+    vec!["src:a.img", "vm:/b.img"]);
+}
+
+fn spec() -> ParameterSpec {
+  BTreeMap::from([
+    ("version".into(), Parameter { type_: ValueType::String, default: None }),
+    ("packages".into(), Parameter { type_: ValueType::Array { inner: Box::new(ValueType::String) }, default: Some(value("[]")) })
+  ])
+}
+
+#[test]
+fn resolve_fills_defaults() {
+  let loc = YamlLocation::default();
+  let params = BTreeMap::from([("version".into(), value("\"20.10.23\""))]);
+  assert_eq!(resolve_parameters(&spec(), &params, &loc).unwrap(), BTreeMap::from([
+    ("version".into(), value("\"20.10.23\"")),
+    ("packages".into(), value("[]"))
+  ]));
+}
+
+#[test]
+fn resolve_skips_optional_parameters() {
+  let mut spec = spec();
+  spec.insert("label".into(), Parameter { type_: ValueType::Optional { inner: Box::new(ValueType::String) }, default: None });
+  let params = BTreeMap::from([("version".into(), value("foo"))]);
+  let resolved = resolve_parameters(&spec, &params, &YamlLocation::default()).unwrap();
+  assert!(!resolved.contains_key("label"));
+  assert_eq!(spec_type(&spec).check(&Value::Dict(resolved)), Ok(()));
+}
+
+#[test]
+fn resolve_reports_errors() {
+  let loc = YamlLocation::default();
+  let err = resolve_parameters(&spec(), &BTreeMap::new(), &loc).unwrap_err();
+  assert!(matches!(err, ORError::MissingParameter(_, name) if name == "version"));
+  let params = BTreeMap::from([("version".into(), value("foo")), ("verison".into(), value("foo"))]);
+  let err = resolve_parameters(&spec(), &params, &loc).unwrap_err();
+  assert!(matches!(err, ORError::UnknownParameter(_, name) if name == "verison"));
+  let params = BTreeMap::from([("version".into(), value("foo")), ("packages".into(), value("[docker, 3]"))]);
+  let err = resolve_parameters(&spec(), &params, &loc).unwrap_err();
+  assert!(matches!(err, ORError::TypeMismatch(_, err) if err.path == "packages[1]"));
+}
+
+#[test]
+fn validate_function_calls() {
+  let yaml = "
+!function
+  name: install_docker
+  parameter_spec:
+    version:
+      type: string
+  steps: []
+---
+!function
+  name: setup
+  parameter_spec: {}
+  steps:
+  - invoke_fn: install_docker
+    version: 20
+";
+  let project = parse(yaml);
+  let err = validate(&project).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: step #0`: type mismatch: `version`: expected string, found integer");
+}
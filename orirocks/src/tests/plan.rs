@@ -0,0 +1,118 @@
+use orirocks_api_v3::Value;
+use crate::build::{plan_build, Project};
+use crate::expand::evaluate_condition;
+use crate::plugins::PluginHive;
+use crate::vars::{resolve_vars, VarOverride};
+use super::{parse, validate};
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!vars
+  arch:
+    type: string
+    default: x86_64
+  with_tools:
+    type: bool
+    default: false
+---
+!function
+  name: install
+  parameter_spec:
+    packages:
+      type: !array
+        inner: string
+    update:
+      type: !optional
+        inner: bool
+  steps:
+  - action: apk_update
+    when: ${update}
+  - action: apk_add
+    for_each: ${packages}
+    as: package
+    name: ${package}
+---
+!build
+  name: base
+  envs:
+  - name: test/mock
+    steps:
+    - invoke_fn: install
+      packages: [curl, git]
+      update: true
+    - invoke_fn: install
+      when: ${vars.with_tools}
+      packages: [vim]
+    - action: enable_kvm
+      when: ${vars.arch} == x86_64
+    - action: write
+      for_each: [1, 2]
+      path: /etc/motd.${item}
+---
+!build
+  name: derived
+  from: base
+  envs:
+  - name: test/mock
+    steps:
+    - action: noop
+";
+
+fn plan_output(project: &Project) -> String {
+  validate(project).unwrap();
+  let (plan, _) = plan_build(project, None, false, &PluginHive::from_providers((vec![], vec![]))).unwrap();
+  plan.to_string()
+}
+
+#[test]
+fn plan_shows_expanded_steps() {
+  assert_eq!(plan_output(&parse(PROJECT)), "\
+base
+  test/mock
+    #0 apk_update  [install]
+    #1 apk_add name=curl  [install]
+    #2 apk_add name=git  [install]
+    #3 enable_kvm
+    #4 write path=/etc/motd.1
+    #5 write path=/etc/motd.2
+derived (after base)
+  test/mock
+    #0 noop
+");
+}
+
+#[test]
+fn plan_evaluates_conditions_on_vars() {
+  let mut project = parse(PROJECT);
+  resolve_vars(&mut project, vec![
+    VarOverride::from_arg("with_tools=true").unwrap(),
+    VarOverride::from_arg("arch=aarch64").unwrap()
+  ]).unwrap();
+  let plan = plan_output(&project);
+  assert!(plan.contains("#3 apk_add name=vim  [install]\n    #4 write"));
+  assert!(!plan.contains("enable_kvm"));
+}
+
+#[test]
+fn evaluate_conditions() {
+  assert_eq!(evaluate_condition(&true.into()), Ok(true));
+  assert_eq!(evaluate_condition(&Value::Null), Ok(false));
+  assert_eq!(evaluate_condition(&"3.17 != 3.18".into()), Ok(true));
+  assert_eq!(evaluate_condition(&"a == b".into()), Ok(false));
+  assert_eq!(evaluate_condition(&"yes".into()).unwrap_err().to_string(), "expected bool or comparison like `a == b`, found `yes`");
+}
+
+#[test]
+fn validate_conditions_and_loops() {
+  let err = validate(&parse(&PROJECT.replace("when: ${update}", "when: ${packages}"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2: step #0`: type mismatch: `when`: expected bool, found parameter of type array of string");
+  let err = validate(&parse(&PROJECT.replace("for_each: ${packages}", "for_each: ${update}"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2: step #1`: type mismatch: `for_each`: expected array, found parameter of type optional bool");
+  let err = validate(&parse(&PROJECT.replace("name: ${package}", "name: ${item}"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2: step #1`: interpolation error: `name`: unknown parameter `item`");
+  let err = validate(&parse(&PROJECT.replace("packages: [vim]", "packages: [vim, 3]"))).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #3: test/mock/step #1`: type mismatch: `packages[1]`: expected string, found integer");
+}
\ No newline at end of file
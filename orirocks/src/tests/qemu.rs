@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use orirocks_api_v3::{CancellationToken, Context, EnvironmentProvider, Value};
+use orirocks_qemu::QemuEnvironmentProvider;
+use crate::build::check_plugin_options;
+use crate::plugins::PluginHive;
+use crate::report::LogReporter;
+use super::parse;
+
+#[test]
+fn qemu_rejects_steps_before_building() {
+  let project = parse("
+!import
+- require: vm
+  version: 0.1
+---
+!build
+  name: base
+  envs:
+  - name: vm/qemu
+    steps:
+    - action: run
+      command: true
+");
+  let err = check_plugin_options(&project, &PluginHive::new()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: vm/qemu/step #0`: environment `vm/qemu` does not support the action `run`");
+}
+
+#[test]
+fn qemu_requires_positive_disk_size() {
+  let provider = QemuEnvironmentProvider;
+  let reporter = LogReporter::new();
+  let ctx = Context::new("base".into(), &reporter, CancellationToken::new());
+  let options = HashMap::from([("disk_size".to_string(), Value::from(0))]);
+  let err = provider.create(&ctx, String::new(), HashMap::new(), options).err().unwrap();
+  assert_eq!(err, "`disk_size` must be a positive integer");
+  // and `orirocks check` reports it before anything is built
+  let project = parse("
+!import
+- require: vm
+  version: 0.1
+---
+!build
+  name: base
+  envs:
+  - name: vm/qemu
+    disk_size: 0
+    steps: []
+");
+  let err = check_plugin_options(&project, &PluginHive::new()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: vm/qemu`: plugin error: `disk_size` must be a positive integer");
+}
\ No newline at end of file
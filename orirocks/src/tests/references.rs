@@ -0,0 +1,62 @@
+use crate::build::{update_cache, BuildCache};
+use crate::plugins::PluginHive;
+use crate::util::{edit_distance, Suggestion};
+use super::{parse, validate};
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: base_image
+  envs:
+  - name: test/mock
+    steps: []
+---
+!build
+  name: derived
+  from: base_image
+  depends: [base_image]
+  envs:
+  - name: test/mock
+    steps: []
+";
+
+fn validation_error(yaml: &str) -> String {
+  validate(&parse(yaml)).unwrap_err().to_string()
+}
+
+#[test]
+fn suggest_close_names() {
+  assert_eq!(edit_distance("base_image", "base_imgae"), 1);
+  assert_eq!(edit_distance("", "abc"), 3);
+  assert_eq!(Suggestion::closest("tset", ["test", "qemu"]).to_string(), ", did you mean `test`?");
+  assert_eq!(Suggestion::closest("docker", ["test", "qemu"]), Suggestion(None));
+}
+
+#[test]
+fn validate_artifact_references() {
+  validate(&parse(PROJECT)).unwrap();
+  assert_eq!(validation_error(&PROJECT.replace("from: base_image", "from: base_imag")),
+    "in `test.yaml: document #2: from`: artifact `base_imag` not found, did you mean `base_image`?");
+  assert_eq!(validation_error(&PROJECT.replace("depends: [base_image]", "depends: [base_image, other]")),
+    "in `test.yaml: document #2: depends #1`: artifact `other` not found");
+}
+
+#[test]
+fn validate_environment_imports() {
+  assert_eq!(validation_error(&PROJECT.replace("- name: test/mock\n    steps: []\n---", "- name: tset/mock\n    steps: []\n---")),
+    "in `test.yaml: document #1: tset/mock`: import `tset` not found, did you mean `test`?");
+}
+
+#[test]
+fn update_cache_reports_missing_references() {
+  // without validation, missing references are errors rather than panics
+  let project = parse(&PROJECT.replace("depends: [base_image]", "depends: [other]"));
+  let err = update_cache(&project, &PluginHive::from_providers((vec![], vec![])), &mut BuildCache::default()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #2`: artifact `other` not found");
+  let project = parse(&PROJECT.replace("require: test", "require: other"));
+  let err = update_cache(&project, &PluginHive::from_providers((vec![], vec![])), &mut BuildCache::default()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: test/mock`: import `test` not found");
+}
\ No newline at end of file
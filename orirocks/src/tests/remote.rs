@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use orirocks_api_v3::{Context, Environment, EnvironmentProvider, OptionsExt, Value};
+use crate::build::{artifact_path, build, Project};
+use crate::plugins::PluginHive;
+use crate::remote::RemoteCache;
+use crate::report::LogReporter;
+use crate::store::ArtifactStore;
+use super::http::{Reply, Request, TestServer};
+use super::{build_options, parse};
+
+/// Token that the server requires for uploads
+const TOKEN: &str = "secret";
+
+/// Contents of an in-memory file server that accepts `PUT`
+#[derive(Default)]
+struct State {
+  files: HashMap<String, Vec<u8>>
+}
+
+type FileServer = TestServer<State>;
+
+fn handle(state: &mut State, request: Request) -> Reply {
+  assert!(request.query.is_empty(), "unexpected query `{}`", request.query);
+  let (status, data) = match request.method.as_str() {
+    "PUT" if request.headers.get("authorization") != Some(&format!("Bearer {}", TOKEN)) => (401, vec![]),
+    "PUT" => {
+      state.files.insert(request.path, request.body);
+      (201, vec![])
+    }
+    _ => match state.files.get(&request.path) {
+      Some(data) => (200, data.clone()),
+      None => (404, vec![])
+    }
+  };
+  (status, vec![], data)
+}
+
+/// URL of the cache on a server
+fn cache_url(server: &FileServer) -> String {
+  format!("{}/cache", server.url)
+}
+
+/// Writes its `content` option as the output, or as `file` in an output directory if `directory` is set.
+/// Counts how often it ran.
+#[derive(Clone, Default)]
+struct WritingProvider {
+  builds: Arc<AtomicUsize>
+}
+
+struct WritingEnvironment {
+  content: String,
+  directory: bool
+}
+
+impl EnvironmentProvider for WritingProvider {
+  fn name(&self) -> &str {
+    "writer"
+  }
+
+  fn create(&self, _ctx: &Context, _base: String, _dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String> {
+    self.builds.fetch_add(1, Ordering::SeqCst);
+    Ok(Box::new(WritingEnvironment {
+      content: options.get_str("content")?.to_string(),
+      directory: options.get_opt::<bool>("directory")?.unwrap_or_default()
+    }))
+  }
+}
+
+impl Environment for WritingEnvironment {
+  fn action(&mut self, _ctx: &Context, _name: &str, _options: HashMap<String, Value>) -> Result<(), String> {
+    Ok(())
+  }
+
+  fn finish(self: Box<Self>, _ctx: &Context, path: &str) -> Result<(), String> {
+    let result = match self.directory {
+      true => fs::create_dir_all(format!("{}/files", path)).and_then(|_| fs::write(format!("{}/files/file", path), &self.content)),
+      false => fs::write(path, &self.content)
+    };
+    result.map_err(|v| v.to_string())
+  }
+}
+
+fn project(content: &str, directory: bool) -> Project {
+  let yaml = format!("
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: base
+  envs:
+  - name: test/writer
+    content: {}
+    directory: {}
+    steps: []
+", content, directory);
+  parse(&yaml)
+}
+
+#[test]
+fn share_outputs_through_remote_cache() {
+  let server = FileServer::start(|state, _, request| handle(state, request));
+  let provider = WritingProvider::default();
+  let plugins = PluginHive::from_providers((vec![Box::new(provider.clone())], vec![]));
+  let ci = build_options("orirocks-test-remote-ci", Some(RemoteCache::new(&cache_url(&server), true, Some(TOKEN.into()))));
+  build(&project("disk", false), None, &ci, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 1);
+  let digest = ArtifactStore::new(&ci.build_dir).digest("base").unwrap().unwrap();
+  let requests = server.requests();
+  assert_eq!(requests.len(), 4);
+  assert!(requests[0].starts_with("GET /cache/keys/"));
+  assert_eq!(requests[1], format!("HEAD /cache/{}", digest.replace(':', "/")));
+  assert_eq!(requests[2], format!("PUT /cache/{}", digest.replace(':', "/")));
+  assert_eq!(requests[3], requests[0].replace("GET", "PUT"));
+
+  // another machine downloads the output instead of building it
+  let laptop = build_options("orirocks-test-remote-laptop", Some(RemoteCache::new(&cache_url(&server), false, None)));
+  build(&project("disk", false), None, &laptop, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 1);
+  assert_eq!(fs::read_to_string(artifact_path(&laptop, "base")).unwrap(), "disk");
+  assert_eq!(ArtifactStore::new(&laptop.build_dir).digest("base").unwrap(), Some(digest.clone()));
+  assert!(server.requests().iter().all(|v| v.starts_with("GET ")));
+
+  // keys do not depend on the order of options
+  let reordered = parse("
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: base
+  envs:
+  - name: test/writer
+    directory: false
+    content: disk
+    steps: []
+");
+  let other = build_options("orirocks-test-remote-reordered", Some(RemoteCache::new(&cache_url(&server), false, None)));
+  build(&reordered, None, &other, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 1);
+  assert_eq!(server.requests()[0], requests[0]);
+
+  // changed inputs miss the cache, and are only uploaded if allowed
+  build(&project("changed", false), None, &laptop, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 2);
+  assert_eq!(fs::read_to_string(artifact_path(&laptop, "base")).unwrap(), "changed");
+  assert_eq!(server.requests().len(), 1);
+
+  // uploads that the server refuses do not fail the build
+  let refused = build_options("orirocks-test-remote-refused", Some(RemoteCache::new(&cache_url(&server), true, None)));
+  build(&project("refused", false), None, &refused, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 3);
+  assert_eq!(fs::read_to_string(artifact_path(&refused, "base")).unwrap(), "refused");
+}
+
+#[test]
+fn share_directory_outputs() {
+  let server = FileServer::start(|state, _, request| handle(state, request));
+  let provider = WritingProvider::default();
+  let plugins = PluginHive::from_providers((vec![Box::new(provider.clone())], vec![]));
+  let ci = build_options("orirocks-test-remote-dir-ci", Some(RemoteCache::new(&cache_url(&server), true, Some(TOKEN.into()))));
+  build(&project("disk", true), None, &ci, &plugins, &LogReporter::new()).unwrap();
+  let digest = ArtifactStore::new(&ci.build_dir).digest("base").unwrap().unwrap();
+  assert!(digest.starts_with("tree-sha256:"));
+  assert!(server.requests().contains(&format!("PUT /cache/{}", digest.replace(':', "/"))));
+
+  let laptop = build_options("orirocks-test-remote-dir-laptop", Some(RemoteCache::new(&cache_url(&server), false, None)));
+  build(&project("disk", true), None, &laptop, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 1);
+  assert_eq!(fs::read_to_string(artifact_path(&laptop, "base") + "/files/file").unwrap(), "disk");
+  assert_eq!(ArtifactStore::new(&laptop.build_dir).digest("base").unwrap(), Some(digest));
+}
+
+#[test]
+fn build_when_download_does_not_match_digest() {
+  let server = FileServer::start(|state, _, request| handle(state, request));
+  let provider = WritingProvider::default();
+  let plugins = PluginHive::from_providers((vec![Box::new(provider.clone())], vec![]));
+  let ci = build_options("orirocks-test-remote-corrupt-ci", Some(RemoteCache::new(&cache_url(&server), true, Some(TOKEN.into()))));
+  build(&project("disk", false), None, &ci, &plugins, &LogReporter::new()).unwrap();
+  let digest = ArtifactStore::new(&ci.build_dir).digest("base").unwrap().unwrap();
+  let path = format!("/cache/{}", digest.replace(':', "/"));
+  server.state.lock().unwrap().files.insert(path, b"tampered".to_vec());
+
+  let laptop = build_options("orirocks-test-remote-corrupt-laptop", Some(RemoteCache::new(&cache_url(&server), false, None)));
+  build(&project("disk", false), None, &laptop, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(provider.builds.load(Ordering::SeqCst), 2);
+  assert_eq!(fs::read_to_string(artifact_path(&laptop, "base")).unwrap(), "disk");
+  assert_eq!(ArtifactStore::new(&laptop.build_dir).digest("base").unwrap(), Some(digest));
+}
\ No newline at end of file
@@ -0,0 +1,112 @@
+use crate::diagnostics::Severity;
+use crate::source::{SourceMap, Span};
+use crate::util::YamlLocation;
+use super::{try_parse, validate};
+
+const PROJECT: &str = "!function
+  name: install_docker
+  parameter_spec:
+    version:
+      type: string
+  steps:
+  - action: run
+    command: apt-get install docker-ce=${vers}
+---
+!build
+  name: img
+  envs:
+  - name: qemu/vm
+    steps:
+    - invoke_fn: install_dockr
+---
+!import
+- require: qemu
+  version: 0.1.0
+";
+
+fn sources(yaml: &str) -> SourceMap {
+  let mut sources = SourceMap::default();
+  sources.add("test.yaml".into(), yaml.into());
+  sources
+}
+
+fn parse_error(yaml: &str) -> String {
+  let err = try_parse(yaml)
+    .and_then(|v| validate(&v))
+    .unwrap_err();
+  sources(yaml).render(&err, Severity::Error, false)
+}
+
+#[test]
+fn index_nodes() {
+  let sources = sources(PROJECT);
+  let keys = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+  assert_eq!(sources.span("test.yaml", 0, &keys(&["steps", "0", "command"])), Some(Span { line: 8, column: 14 }));
+  assert_eq!(sources.span("test.yaml", 1, &keys(&["envs", "0", "steps", "0"])), Some(Span { line: 15, column: 7 }));
+  // unknown keys fall back to the closest ancestor
+  assert_eq!(sources.span("test.yaml", 1, &keys(&["envs", "0", "options"])), Some(Span { line: 13, column: 5 }));
+  assert_eq!(sources.span("test.yaml", 3, &[]), None);
+}
+
+#[test]
+fn index_aliases() {
+  let sources = sources("\
+defaults: &defaults
+  image: debian
+  steps:
+  - run: a
+envs:
+- *defaults
+- <<: *defaults
+  image: alpine
+");
+  let keys = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+  assert_eq!(sources.span("test.yaml", 0, &keys(&["defaults", "steps", "0", "run"])), Some(Span { line: 4, column: 10 }));
+  // nodes inside an alias are found at the alias
+  assert_eq!(sources.span("test.yaml", 0, &keys(&["envs", "0", "steps", "0", "run"])), Some(Span { line: 6, column: 3 }));
+  // merge keys are ordinary keys, and the keys they would bring in fall back to the mapping
+  assert_eq!(sources.span("test.yaml", 0, &keys(&["envs", "1", "<<"])), Some(Span { line: 7, column: 7 }));
+  assert_eq!(sources.span("test.yaml", 0, &keys(&["envs", "1", "image"])), Some(Span { line: 8, column: 10 }));
+  assert_eq!(sources.span("test.yaml", 0, &keys(&["envs", "1", "steps"])), Some(Span { line: 7, column: 3 }));
+}
+
+#[test]
+fn locations_follow_function_calls() {
+  let function = YamlLocation::new("lib.yaml".into(), 2, vec![]);
+  let mut loc = YamlLocation::new("test.yaml".into(), 0, vec![]);
+  loc.push_item("qemu/vm".into(), "envs", 1);
+  loc.push_item("step #0".into(), "steps", 0);
+  loc.push_document("function f".into(), &function);
+  loc.push_item("step #3".into(), "steps", 3);
+  loc.push("item #0".into());
+  assert_eq!(loc.node(), ("lib.yaml", 2, vec!["steps".to_string(), "3".to_string()]));
+  loc.pop();
+  loc.pop();
+  loc.pop();
+  assert_eq!(loc.node(), ("test.yaml", 0, vec!["envs".to_string(), "1".to_string(), "steps".to_string(), "0".to_string()]));
+}
+
+#[test]
+fn render_snippets() {
+  assert_eq!(parse_error(PROJECT), "\
+error: in `test.yaml: document #0: step #0`: interpolation error: `command`: unknown parameter `vers`
+ --> test.yaml:8:14
+  |
+8 |     command: apt-get install docker-ce=${vers}
+  |              ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+");
+  assert_eq!(parse_error(&PROJECT.replace("${vers}", "${version}")), "\
+error: in `test.yaml: document #1: qemu/vm/step #0`: function `install_dockr` not found, did you mean `install_docker`?
+  --> test.yaml:15:18
+   |
+15 |     - invoke_fn: install_dockr
+   |                  ^^^^^^^^^^^^^
+");
+  assert_eq!(parse_error("!build\n  name: b\n  envs: 3\n"), "\
+error: in `test.yaml: document #0`: syntax error: `envs: invalid type: integer `3`, expected a sequence at line 3 column 9`
+ --> test.yaml:3:9
+  |
+3 |   envs: 3
+  |         ^
+");
+}
\ No newline at end of file
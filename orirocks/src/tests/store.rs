@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use orirocks_api_v3::{Context, Environment, EnvironmentProvider, OptionsExt, Value};
+use crate::build::{artifact_path, build, Project};
+use crate::plugins::PluginHive;
+use crate::report::LogReporter;
+use crate::store::ArtifactStore;
+use orirocks_api_v3::digest::{sha256_hex, tree_digest};
+use super::{build_options, parse, temp_dir};
+
+#[test]
+fn store_outputs_by_digest() {
+  let build_dir = temp_dir("orirocks-test-store").to_string_lossy().into_owned();
+  let store = ArtifactStore::new(&build_dir);
+  assert_eq!(store.digest("base").unwrap(), None);
+  let output = Path::new(&build_dir).join("output");
+  fs::write(&output, "disk").unwrap();
+  let digest = format!("sha256:{}", sha256_hex(b"disk"));
+  assert_eq!(store.insert("base", &output).unwrap(), digest);
+  assert!(!output.exists());
+  assert_eq!(store.digest("base").unwrap(), Some(digest.clone()));
+  assert_eq!(fs::read_to_string(store.path("base")).unwrap(), "disk");
+  assert_eq!(fs::read_link(store.path("base")).unwrap(), Path::new("../sha256").join(digest.trim_start_matches("sha256:")));
+
+  // equal outputs are stored once
+  fs::write(&output, "disk").unwrap();
+  assert_eq!(store.insert("copy", &output).unwrap(), digest);
+  assert!(!output.exists());
+  assert_eq!(fs::read_dir(Path::new(&build_dir).join("store/sha256")).unwrap().count(), 1);
+
+  fs::create_dir_all(output.join("files")).unwrap();
+  fs::write(output.join("files/a"), "a").unwrap();
+  let digest = store.insert("base", &output).unwrap();
+  assert_eq!(digest, tree_digest(&[("files/a".to_string(), sha256_hex(b"a"))].into()));
+  assert_eq!(store.digest("base").unwrap(), Some(digest.clone()));
+  assert_eq!(fs::read_to_string(store.path("base").join("files/a")).unwrap(), "a");
+  assert!(store.object_path(&digest).is_dir());
+  assert_eq!(fs::read_to_string(store.path("copy")).unwrap(), "disk");
+  assert_eq!(fs::read_link(store.path("base")).unwrap(), Path::new("../tree-sha256").join(digest.trim_start_matches("tree-sha256:")));
+  assert_eq!(store.objects().unwrap(), [format!("sha256:{}", sha256_hex(b"disk")), digest]);
+
+  // links are only made for names that are a single path component
+  fs::write(&output, "nested").unwrap();
+  let err = store.insert("nested/base", &output).unwrap_err();
+  assert_eq!(err.to_string(), "`nested/base` cannot be linked in the store");
+}
+
+/// Writes its `content` option as the output, failing if it is `fail` and writing nothing if it is `none`
+struct WritingProvider;
+
+struct WritingEnvironment {
+  content: String
+}
+
+impl EnvironmentProvider for WritingProvider {
+  fn name(&self) -> &str {
+    "writer"
+  }
+
+  fn create(&self, _ctx: &Context, _base: String, _dependencies: HashMap<String, String>, options: HashMap<String, Value>) -> Result<Box<dyn Environment>, String> {
+    Ok(Box::new(WritingEnvironment { content: options.get_str("content")?.to_string() }))
+  }
+}
+
+impl Environment for WritingEnvironment {
+  fn action(&mut self, _ctx: &Context, _name: &str, _options: HashMap<String, Value>) -> Result<(), String> {
+    Ok(())
+  }
+
+  fn finish(self: Box<Self>, _ctx: &Context, path: &str) -> Result<(), String> {
+    match self.content.as_str() {
+      "none" => Ok(()),
+      content => {
+        fs::write(path, content).map_err(|v| v.to_string())?;
+        if content == "fail" { Err("could not save".into()) } else { Ok(()) }
+      }
+    }
+  }
+}
+
+fn project(content: &str) -> Project {
+  let yaml = format!("
+!import
+- require: test
+  version: 0.1
+---
+!build
+  name: base
+  envs:
+  - name: test/writer
+    content: {}
+    steps: []
+", content);
+  parse(&yaml)
+}
+
+#[test]
+fn failed_build_keeps_last_output() {
+  let opts = build_options("orirocks-test-store-failed", None);
+  let plugins = PluginHive::from_providers((vec![Box::new(WritingProvider)], vec![]));
+  build(&project("good"), None, &opts, &plugins, &LogReporter::new()).unwrap();
+  assert_eq!(fs::read_to_string(artifact_path(&opts, "base")).unwrap(), "good");
+  let err = build(&project("fail"), None, &opts, &plugins, &LogReporter::new()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: test/writer`: plugin error: could not save");
+  assert_eq!(fs::read_to_string(artifact_path(&opts, "base")).unwrap(), "good");
+  let err = build(&project("none"), None, &opts, &plugins, &LogReporter::new()).unwrap_err();
+  assert_eq!(err.to_string(), "in `test.yaml: document #1: test/writer`: the environment did not produce an output for `base`");
+  assert_eq!(fs::read_to_string(artifact_path(&opts, "base")).unwrap(), "good");
+}
\ No newline at end of file
@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use serde::Deserialize;
+use orirocks_api_v3::{from_options, from_value, CmpFloat, OptionsExt, Value, ValueError};
+
+fn options(yaml: &str) -> HashMap<String, Value> {
+  serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn convert_rust_types() {
+  assert_eq!(Value::from(3), Value::Integer(3));
+  assert_eq!(Value::from("foo"), Value::String("foo".into()));
+  assert_eq!(Value::from(vec![1.5, 2.0]), Value::Array(vec![Value::Float(CmpFloat::new(1.5)), Value::Float(CmpFloat::new(2.0))]));
+  assert_eq!(Value::from(BTreeMap::from([("a".to_string(), true)])), Value::Dict(BTreeMap::from([("a".into(), Value::Bool(true))])));
+  assert_eq!(u32::try_from(Value::Integer(7)), Ok(7));
+  assert_eq!(f64::try_from(Value::Integer(7)), Ok(7.0));
+  assert_eq!(PathBuf::try_from(Value::from("a/b")), Ok(PathBuf::from("a/b")));
+  assert_eq!(u32::try_from(Value::Integer(-1)).unwrap_err().to_string(), "integer -1 is out of range for u32");
+  assert_eq!(String::try_from(Value::Bool(true)).unwrap_err().to_string(), "expected string, found bool");
+}
+
+#[test]
+fn options_accessors() {
+  let opts = options("
+source: src:assets/script.js
+retries: 3
+tags:
+  env: prod
+regions: [us, eu]
+");
+  assert_eq!(opts.get_str("source"), Ok("src:assets/script.js"));
+  assert_eq!(opts.get_int("retries"), Ok(3));
+  assert_eq!(opts.get_as::<Vec<String>>("regions"), Ok(vec!["us".to_string(), "eu".into()]));
+  assert_eq!(opts.get_opt::<u64>("disk_size"), Ok(None));
+  assert_eq!(opts.get_str("dest").unwrap_err().to_string(), "`dest`: missing required value");
+  assert_eq!(opts.get_int("source").unwrap_err().to_string(), "`source`: expected integer, found string");
+  assert_eq!(opts.get_as::<Vec<u32>>("regions").unwrap_err().path, "regions[0]");
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Disk {
+  size: u64,
+  format: Format
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+  Qcow2,
+  Raw
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct PluginOptions {
+  memory: u32,
+  kernel: Option<PathBuf>,
+  #[serde(default)]
+  disks: Vec<Disk>
+}
+
+#[test]
+fn deserialize_options_into_struct() {
+  let parsed: PluginOptions = from_options(options("
+memory: 2048
+disks:
+- size: 1024
+  format: qcow2
+- size: 2048
+  format: raw
+")).unwrap();
+  assert_eq!(parsed, PluginOptions {
+    memory: 2048,
+    kernel: None,
+    disks: vec![
+      Disk { size: 1024, format: Format::Qcow2 },
+      Disk { size: 2048, format: Format::Raw }
+    ]
+  });
+}
+
+#[test]
+fn deserialize_reports_field_paths() {
+  let err = from_options::<PluginOptions>(options("
+memory: 2048
+disks:
+- size: 1024
+  format: qcow2
+- size: big
+  format: raw
+")).unwrap_err();
+  assert_eq!(err.path, "disks[1].size");
+  assert_eq!(err.to_string(), "`disks[1].size`: invalid type: string \"big\", expected u64");
+
+  let err = from_options::<PluginOptions>(options("
+memory: 2048
+disks:
+- size: 1024
+")).unwrap_err();
+  assert_eq!(err, ValueError { path: "disks[0]".into(), message: "missing field `format`".into() });
+
+  let err = from_value::<PluginOptions>(Value::from(3)).unwrap_err();
+  assert_eq!(err.to_string(), "invalid type: integer `3`, expected struct PluginOptions");
+}
+
+
+#[test]
+fn parse_null() {
+  let opts = options("
+a: ~
+b:
+c: null
+d: 'null'
+");
+  assert_eq!(opts["a"], Value::Null);
+  assert_eq!(opts["b"], Value::Null);
+  assert_eq!(opts["c"], Value::Null);
+  assert_eq!(opts["d"], Value::String("null".into()));
+  assert_eq!(opts.get_opt::<String>("a"), Ok(None));
+  assert_eq!(opts.get_str("a").unwrap_err().to_string(), "`a`: expected string, found null");
+}
+
+#[test]
+fn parse_scalars_deterministically() {
+  let opts = options("
+int: 1
+hex: 0x1f
+float: 1.0
+exp: 1e3
+quoted_int: '1'
+quoted_float: \"1.0\"
+version: 20.10.23
+tagged: !!str 1.0
+yes: yes
+");
+  assert_eq!(opts["int"], Value::Integer(1));
+  assert_eq!(opts["hex"], Value::Integer(31));
+  assert_eq!(opts["float"], Value::Float(CmpFloat::new(1.0)));
+  assert_eq!(opts["exp"], Value::Float(CmpFloat::new(1000.0)));
+  assert_eq!(opts["quoted_int"], Value::String("1".into()));
+  assert_eq!(opts["quoted_float"], Value::String("1.0".into()));
+  assert_eq!(opts["version"], Value::String("20.10.23".into()));
+  assert_eq!(opts["tagged"], Value::String("1.0".into()));
+  assert_eq!(opts["yes"], Value::String("yes".into()));
+}
+
+#[test]
+fn parse_rejects_ambiguous_values() {
+  let err = serde_yaml::from_str::<Value>("!custom foo").unwrap_err();
+  assert!(err.to_string().starts_with("unsupported tag `!custom`"));
+  let err = serde_yaml::from_str::<Value>("18446744073709551615").unwrap_err();
+  assert!(err.to_string().starts_with("integer 18446744073709551615 is too large"));
+}
+
+#[test]
+fn value_round_trip() {
+  let yaml = "\
+a: null
+b: true
+c: 1
+d: 1.5
+e: '1'
+f: '1.0'
+g: 20.10.23
+h: 'true'
+i:
+- x
+- ''
+- '~'
+j:
+  k: -3
+";
+  let parsed: Value = serde_yaml::from_str(yaml).unwrap();
+  assert_eq!(serde_yaml::to_string(&parsed).unwrap(), yaml);
+  let reparsed: Value = serde_yaml::from_str(&serde_yaml::to_string(&parsed).unwrap()).unwrap();
+  assert_eq!(reparsed, parsed);
+}
@@ -0,0 +1,106 @@
+use orirocks_api_v3::Value;
+use crate::build::{update_cache, BuildCache, Project};
+use crate::expand::expand_steps;
+use crate::plugins::PluginHive;
+use crate::util::{Located, ORError};
+use crate::vars::{env_overrides, resolve_vars, VarOverride};
+use super::{parse, validate};
+
+const PROJECT: &str = "
+!import
+- require: test
+  version: 0.1
+---
+!vars
+  alpine_version:
+    type: string
+    default: '3.17'
+  disk_size:
+    type: integer
+    default: 8
+---
+!function
+  name: install
+  parameter_spec:
+    package:
+      type: string
+  steps:
+  - action: apk_add
+    package: ${package}
+    repo: alpine-${vars.alpine_version}
+---
+!build
+  name: alpine
+  envs:
+  - name: test/mock
+    disk_size: ${vars.disk_size}
+    steps:
+    - invoke_fn: install
+      package: curl
+---
+!build
+  name: other
+  envs:
+  - name: test/mock
+    steps:
+    - action: noop
+";
+
+fn var(name: &str, value: &str) -> VarOverride {
+  VarOverride::from_arg(&format!("{}={}", name, value)).unwrap()
+}
+
+#[test]
+fn resolve_applies_overrides_in_order() {
+  let mut project = parse(PROJECT);
+  let env = env_overrides(vec![
+    ("ORIROCKS_VAR_DISK_SIZE".to_string(), "16".to_string()),
+    ("ORIROCKS_VAR_OTHER_PROJECT".to_string(), "1".to_string()),
+    ("HOME".to_string(), "/root".to_string())
+  ]);
+  assert_eq!(env.len(), 2);
+  let mut overrides = env;
+  overrides.push(var("alpine_version", "3.18"));
+  overrides.push(var("disk_size", "32"));
+  resolve_vars(&mut project, overrides).unwrap();
+  validate(&project).unwrap();
+  assert_eq!(project.var_values["alpine_version"], Value::from("3.18"));
+  assert_eq!(project.var_values["disk_size"], Value::from(32));
+  let artifact = &project.builds["alpine"];
+  let steps = expand_steps(&project, artifact, &artifact.envs[0].steps, Located::location(artifact)).unwrap();
+  assert_eq!(steps[0].step.parameters["repo"], Value::from("alpine-3.18"));
+}
+
+#[test]
+fn resolve_reports_errors() {
+  let mut project = parse(PROJECT);
+  let err = resolve_vars(&mut project, vec![var("disk_size", "big")]).unwrap_err();
+  assert_eq!(err.to_string(), "--var: `disk_size`: expected integer, found string");
+  let err = resolve_vars(&mut project, vec![var("disk", "8")]).unwrap_err();
+  assert_eq!(err.to_string(), "--var: `disk`: unknown variable");
+  assert!(VarOverride::from_arg("disk_size").is_err());
+  let project = parse(&PROJECT.replace("    default: 8\n", ""));
+  assert!(matches!(validate(&project), Err(ORError::MissingVariable(_, name)) if name == "disk_size"));
+  let project = parse(&PROJECT.replace("${vars.alpine_version}", "${vars.alpine}"));
+  assert_eq!(validate(&project).unwrap_err().to_string(),
+    "in `test.yaml: document #2: step #0`: interpolation error: `repo`: unknown variable `alpine`");
+}
+
+#[test]
+fn changing_vars_rebuilds_affected_artifacts() {
+  let plugins = PluginHive::from_providers((vec![], vec![]));
+  let dirty = |project: &Project, cache: &mut BuildCache| {
+    update_cache(project, &plugins, cache).unwrap().artifacts.into_iter().map(|v| v.0).collect::<Vec<_>>()
+  };
+  let mut cache = BuildCache::default();
+  let project = parse(PROJECT);
+  assert_eq!(dirty(&project, &mut cache), vec!["alpine".to_string(), "other".into()]);
+  assert!(dirty(&project, &mut cache).is_empty());
+  let mut project = parse(PROJECT);
+  resolve_vars(&mut project, vec![var("alpine_version", "3.18")]).unwrap();
+  assert_eq!(dirty(&project, &mut cache), vec!["alpine".to_string()]);
+  let mut project = parse(PROJECT);
+  resolve_vars(&mut project, vec![var("alpine_version", "3.18"), var("disk_size", "16")]).unwrap();
+  assert_eq!(dirty(&project, &mut cache), vec!["alpine".to_string()]);
+  assert!(dirty(&project, &mut cache).is_empty());
+}
\ No newline at end of file
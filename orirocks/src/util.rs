@@ -1,8 +1,12 @@
-use std::fmt::{Display, Formatter, Write};
+use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
-use std::io;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
 use std::ops::{Deref, DerefMut};
 use thiserror::Error;
+use orirocks_api_v3::ValueError;
+use crate::ident::Ident;
 
 #[derive(Error, Debug)]
 pub enum ORError {
@@ -15,29 +19,155 @@ pub enum ORError {
   #[error("in `{0}`: duplicate `{1}` `{2}`")]
   DuplicateSymbol(YamlLocation, String, String),
 
-  #[error("in `{0}`: invalid character in identifier")]
-  InvalidCharacter(YamlLocation),
-
-  #[error("in `{0}`: invalid environment name")]
-  InvalidEnvironmentName(YamlLocation),
+  #[error("in `{0}`: {1}")]
+  InvalidName(YamlLocation, String),
 
   #[error("in `{0}`: invalid (unknown reason)")]
   GenericInvalid(YamlLocation),
 
-  #[error("in: `{0}`: import `{1}` not found")]
-  ImportNotFound(YamlLocation, String),
+  #[error("in `{0}`: import `{1}` not found{2}")]
+  ImportNotFound(YamlLocation, String, Suggestion),
 
   #[error("circular dependency found: {0}")]
-  CircularDependency(String)
+  CircularDependency(String),
+
+  #[error("in `{0}`: no plugin provides environment `{1}`")]
+  EnvironmentNotFound(YamlLocation, String),
+
+  #[error("in `{0}`: no plugin provides deployment `{1}`")]
+  DeploymentNotFound(YamlLocation, String),
+
+  #[error("deployment `{0}` not found{1}")]
+  UnknownDeployment(String, Suggestion),
+
+  #[error("in `{0}`: plugin error: {1}")]
+  PluginError(YamlLocation, String),
+
+  #[error("build cancelled")]
+  Cancelled,
+
+  #[error("in `{0}`: function `{1}` not found{2}")]
+  FunctionNotFound(YamlLocation, String, Suggestion),
+
+  #[error("in `{0}`: artifact `{1}` not found{2}")]
+  ArtifactNotFound(YamlLocation, String, Suggestion),
+
+  #[error("in `{0}`: missing required parameter `{1}`")]
+  MissingParameter(YamlLocation, String),
+
+  #[error("in `{0}`: unknown parameter `{1}`")]
+  UnknownParameter(YamlLocation, String),
+
+  #[error("in `{0}`: type mismatch: {1}")]
+  TypeMismatch(YamlLocation, ValueError),
+
+  #[error("in `{0}`: recursive function call: {1}")]
+  RecursiveFunction(YamlLocation, String),
+
+  #[error("in `{0}`: interpolation error: {1}")]
+  InterpolationError(YamlLocation, ValueError),
+
+  #[error("{0}: {1}")]
+  VariableError(String, ValueError),
+
+  #[error("in `{0}`: variable `{1}` has no default and is not set")]
+  MissingVariable(YamlLocation, String),
+
+  #[error("in `{0}`: invalid matrix: {1}")]
+  InvalidMatrix(YamlLocation, ValueError),
+
+  #[error("in `{0}`: invalid matrix reference: {1}")]
+  MatrixReference(YamlLocation, String),
+
+  #[error("in `{0}`: could not read resource `{1}`: {2}")]
+  ResourceError(YamlLocation, String, io::Error),
+
+  #[error("in `{0}`: resource `{1}` is outside the project directory")]
+  ResourceOutsideProject(YamlLocation, String),
+
+  #[error("in `{0}`: function `{1}` is never called")]
+  UnusedFunction(YamlLocation, String),
+
+  #[error("in `{0}`: variable `{1}` is never used")]
+  UnusedVariable(YamlLocation, String),
+
+  #[error("in `{0}`: library `{1}`: {2}")]
+  InvalidLibrary(YamlLocation, String, String),
+
+  #[error("in `{0}`: the environment did not produce an output for `{1}`")]
+  MissingOutput(YamlLocation, String),
+
+  #[error("in `{0}`: environment `{1}` does not support the action `{2}`")]
+  UnsupportedAction(YamlLocation, String, String),
+
+  #[error("could not find `orirocks.yaml` in `{0}` or any parent directory")]
+  ManifestNotFound(String),
+
+  #[error("in `{0}`: {1}")]
+  ManifestError(String, String)
 }
 
 pub type ORResult<T> = std::result::Result<T, ORError>;
 
+impl ORError {
+  /// Returns where the error occurred, if it is known
+  pub fn location(&self) -> Option<&YamlLocation> {
+    match self {
+      ORError::YamlError(loc, _) | ORError::DuplicateSymbol(loc, ..) | ORError::InvalidName(loc, _)
+      | ORError::GenericInvalid(loc) | ORError::ImportNotFound(loc, ..)
+      | ORError::EnvironmentNotFound(loc, _) | ORError::DeploymentNotFound(loc, _) | ORError::PluginError(loc, _) | ORError::FunctionNotFound(loc, ..)
+      | ORError::ArtifactNotFound(loc, ..)
+      | ORError::MissingParameter(loc, _) | ORError::UnknownParameter(loc, _) | ORError::TypeMismatch(loc, _)
+      | ORError::RecursiveFunction(loc, _) | ORError::InterpolationError(loc, _) | ORError::MissingVariable(loc, _)
+      | ORError::InvalidMatrix(loc, _) | ORError::MatrixReference(loc, _) | ORError::ResourceError(loc, ..)
+      | ORError::ResourceOutsideProject(loc, _)
+      | ORError::UnusedFunction(loc, _) | ORError::UnusedVariable(loc, _) | ORError::InvalidLibrary(loc, ..)
+      | ORError::MissingOutput(loc, _) | ORError::UnsupportedAction(loc, ..) => Some(loc),
+      ORError::IoError(_) | ORError::CircularDependency(_) | ORError::Cancelled | ORError::VariableError(..)
+      | ORError::ManifestNotFound(_) | ORError::ManifestError(..) | ORError::UnknownDeployment(..) => None
+    }
+  }
+
+  /// Keys leading from the node of `location` to the offending value
+  pub fn keys(&self) -> Vec<String> {
+    match self {
+      ORError::TypeMismatch(_, err) | ORError::InterpolationError(_, err) => value_path(&err.path),
+      ORError::InvalidMatrix(_, err) => [vec!["matrix".to_string()], value_path(&err.path)].concat(),
+      ORError::UnknownParameter(_, name) | ORError::MissingVariable(_, name) | ORError::UnusedVariable(_, name) => vec![name.clone()],
+      ORError::UnusedFunction(..) => vec!["name".to_string()],
+      ORError::FunctionNotFound(..) => vec!["invoke_fn".to_string()],
+      ORError::ImportNotFound(..) => vec!["name".to_string()],
+      ORError::DeploymentNotFound(..) => vec!["provider".to_string()],
+      ORError::UnsupportedAction(..) => vec!["action".to_string()],
+      _ => vec![]
+    }
+  }
+}
+
+/// Splits the path of a `ValueError`, such as `disks[2].size`, into keys
+fn value_path(path: &str) -> Vec<String> {
+  path.split(['.', '['])
+    .map(|v| v.trim_end_matches(']'))
+    .filter(|v| !v.is_empty())
+    .map(String::from)
+    .collect()
+}
+
 #[derive(Default, Clone, Debug, Eq, PartialEq)]
 pub struct YamlLocation {
   pub file: String,
   pub document_id: usize,
-  pub path: Vec<String>
+  path: Vec<PathElement>
+}
+
+/// A named step into the document, such as `step #3`, along with the YAML node it refers to
+#[derive(Default, Clone, Debug, Eq, PartialEq)]
+struct PathElement {
+  name: String,
+  /// Set when the element continues in another document, such as a function body
+  document: Option<(String, usize)>,
+  /// Keys leading to the node from the node of the previous element, or from the root of `document`
+  keys: Vec<String>
 }
 
 impl YamlLocation {
@@ -45,22 +175,60 @@ impl YamlLocation {
     YamlLocation {
       file,
       document_id,
-      path
+      path: path.into_iter().map(|name| PathElement { name, ..Default::default() }).collect()
     }
   }
 
+  /// Pushes a path element that does not refer to a YAML node of its own, such as a loop iteration
   pub fn push(&mut self, path: String) {
-    self.path.push(path);
+    self.path.push(PathElement { name: path, ..Default::default() });
+  }
+
+  /// Pushes a path element that refers to the value of `key`
+  pub fn push_key(&mut self, path: String, key: &str) {
+    self.path.push(PathElement { name: path, document: None, keys: vec![key.to_string()] });
+  }
+
+  /// Pushes a path element that refers to item `index` of the sequence `key`, such as `steps[3]`
+  pub fn push_item(&mut self, path: String, key: &str, index: usize) {
+    self.path.push(PathElement { name: path, document: None, keys: vec![key.to_string(), index.to_string()] });
+  }
+
+  /// Pushes a path element that continues in another document, such as a function call
+  pub fn push_document(&mut self, path: String, document: &YamlLocation) {
+    let (file, document_id, keys) = document.node();
+    self.path.push(PathElement { name: path, document: Some((file.to_string(), document_id)), keys });
   }
 
   pub fn pop(&mut self) {
     self.path.pop();
   }
+
+  /// Returns the path elements joined with `/`, such as `qemu/vm/step #3`
+  pub fn path(&self) -> String {
+    self.path.iter().map(|v| v.name.as_str()).collect::<Vec<_>>().join("/")
+  }
+
+  /// Returns the file, document and keys of the YAML node this location refers to
+  pub fn node(&self) -> (&str, usize, Vec<String>) {
+    let (mut file, mut document_id, mut keys) = (self.file.as_str(), self.document_id, vec![]);
+    for element in &self.path {
+      if let Some((f, d)) = &element.document {
+        (file, document_id) = (f.as_str(), *d);
+        keys.clear();
+      }
+      keys.extend(element.keys.iter().cloned());
+    }
+    (file, document_id, keys)
+  }
 }
 
 impl Display for YamlLocation {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    f.write_str(&format!("{}: document #{}: {}", self.file, self.document_id, self.path.join("/")))
+    match self.path.is_empty() {
+      true => write!(f, "{}: document #{}", self.file, self.document_id),
+      false => write!(f, "{}: document #{}: {}", self.file, self.document_id, self.path())
+    }
   }
 }
 
@@ -97,18 +265,58 @@ impl<T> DerefMut for Located<T> {
   }
 }
 
-/// Validates that the identifier only contains allowed characters.
-/// These are a-zA-Z0-9_
-pub fn validate_identifier(s: &str, traceback: &YamlLocation) -> ORResult<()> {
-  if !s.chars().all(|v| {
-    v.is_ascii_alphanumeric() || v == '_'
-  }) {
-    Err(ORError::InvalidCharacter(traceback.clone()))
-  } else {
-    Ok(())
+/// The closest match for a name that was not found, shown as `, did you mean `x`?`
+#[derive(Default, Clone, Debug, Eq, PartialEq)]
+pub struct Suggestion(pub Option<String>);
+
+impl Suggestion {
+  /// Picks the candidate closest to `name`, if it is close enough to be a likely typo
+  pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Self {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let closest = candidates.into_iter()
+      .map(|v| (edit_distance(name, v), v))
+      .filter(|(distance, _)| *distance <= max_distance)
+      .min();
+    Suggestion(closest.map(|v| v.1.to_string()))
+  }
+}
+
+impl Display for Suggestion {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match &self.0 {
+      Some(v) => write!(f, ", did you mean `{}`?", v),
+      None => Ok(())
+    }
   }
 }
 
+/// Edit distance between two strings, counting insertions, deletions, substitutions
+/// and swaps of adjacent characters as one edit each
+pub fn edit_distance(a: &str, b: &str) -> usize {
+  let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+  let mut d = (0..=a.len())
+    .map(|i| (0..=b.len()).map(|j| if i == 0 { j } else if j == 0 { i } else { 0 }).collect::<Vec<_>>())
+    .collect::<Vec<_>>();
+  for i in 1..=a.len() {
+    for j in 1..=b.len() {
+      let cost = usize::from(a[i - 1] != b[j - 1]);
+      d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+      if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+        d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+      }
+    }
+  }
+  d[a.len()][b.len()]
+}
+
+/// Validates names that are not parsed into an `Ident` while deserializing, such as map keys.
+/// See `ident` for the grammar.
+pub fn validate_identifier(s: &str, traceback: &YamlLocation) -> ORResult<()> {
+  Ident::try_from(s)
+    .map(|_| ())
+    .map_err(|v| ORError::InvalidName(traceback.clone(), v))
+}
+
 pub struct SHA256Hasher {
   ctx: ring::digest::Context
 }
@@ -133,4 +341,24 @@ pub fn sha256_trunc(v: &impl Hash) -> u64 {
   let mut hasher = SHA256Hasher::new();
   v.hash(&mut hasher);
   hasher.finish()
+}
+
+/// Hashes the contents of a file
+pub fn sha256_file(path: &Path) -> io::Result<u64> {
+  let mut file = File::open(path)?;
+  let mut hasher = SHA256Hasher::new();
+  let mut buf = vec![0u8; 64 * 1024];
+  loop {
+    let n = file.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    hasher.write(&buf[..n]);
+  }
+  Ok(hasher.finish())
+}
+
+/// Formats a SHA-256 digest, such as `sha256:9f86d0...`
+pub fn format_digest(digest: ring::digest::Digest) -> String {
+  format!("sha256:{}", orirocks_api_v3::digest::hex(digest.as_ref()))
 }
\ No newline at end of file
@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::path::Path;
+use log::warn;
+use orirocks_api_v3::{Value, ValueError, ValueType};
+use crate::build::Project;
+use crate::interpolate::interpolate;
+use crate::matrix::matrix_params;
+use crate::model::{BuildDoc, Parameter, ParameterSpec, Parameters};
+use crate::util::{ORError, ORResult, YamlLocation};
+
+/// Project variables are referenced as `${vars.name}`
+pub const VARS_PREFIX: &str = "vars.";
+
+/// Environment variables with this prefix override project variables, e.g. `ORIROCKS_VAR_DISK_SIZE`
+pub const ENV_PREFIX: &str = "ORIROCKS_VAR_";
+
+/// A value for a project variable given outside of the project files
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VarOverride {
+  /// Where the value came from, used in error messages
+  pub source: String,
+  pub name: String,
+  pub value: Value,
+  /// True if the value was given as text, which is converted according to the type of the variable
+  pub text: bool,
+  /// False if the project may not have the variable, in which case the value is ignored with a warning
+  pub required: bool
+}
+
+impl VarOverride {
+  fn text(source: impl Into<String>, name: impl Into<String>, value: impl Into<String>) -> Self {
+    VarOverride {
+      source: source.into(),
+      name: name.into(),
+      value: Value::String(value.into()),
+      text: true,
+      required: true
+    }
+  }
+
+  /// Parses a `--var key=value` argument
+  pub fn from_arg(arg: &str) -> Result<Self, String> {
+    let (name, value) = arg.split_once('=')
+      .ok_or_else(|| format!("expected `key=value`, found `{}`", arg))?;
+    Ok(Self::text("--var", name, value))
+  }
+}
+
+/// Reads overrides from a YAML file that maps variable names to values
+pub fn load_vars_file(path: &str) -> ORResult<Vec<VarOverride>> {
+  let file = File::open(Path::new(path)).map_err(ORError::IoError)?;
+  let values: Parameters = serde_yaml::from_reader(file)
+    .map_err(|v| ORError::YamlError(YamlLocation::new(path.to_string(), 0, vec![]), v))?;
+  Ok(values.into_iter()
+    .map(|(name, value)| VarOverride { source: path.to_string(), name, value, text: false, required: true })
+    .collect())
+}
+
+/// Collects overrides from `ORIROCKS_VAR_*` environment variables. Variable names are lowercased.
+/// The environment may be shared with other projects, so variables that the project does not have are ignored.
+pub fn env_overrides(env: impl IntoIterator<Item = (String, String)>) -> Vec<VarOverride> {
+  let mut overrides = env.into_iter()
+    .filter_map(|(k, v)| {
+      let name = k.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
+      Some(VarOverride { required: false, ..VarOverride::text(format!("environment variable `{}`", k), name, v) })
+    })
+    .collect::<Vec<_>>();
+  overrides.sort_by(|a, b| a.name.cmp(&b.name));
+  overrides
+}
+
+/// Converts a value given as text, so that `--var disk_size=16` sets an integer.
+/// Text that is valid for the type as it is, such as `3.17` for a string variable, is kept as a string.
+fn convert_text(ty: &ValueType, text: String) -> Value {
+  let value = Value::String(text);
+  if ty.check(&value).is_ok() {
+    return value;
+  }
+  let text = value.as_str().unwrap_or_default();
+  serde_yaml::from_str(text).unwrap_or(value)
+}
+
+/// Applies overrides in order, so that later ones take precedence
+pub fn resolve_vars(project: &mut Project, overrides: Vec<VarOverride>) -> ORResult<()> {
+  for v in overrides {
+    let Some(var) = project.vars.get(&v.name) else {
+      if !v.required {
+        warn!("ignoring {}: the project has no variable `{}`", v.source, v.name);
+        continue;
+      }
+      return Err(ORError::VariableError(v.source, ValueError::new("unknown variable").at_key(&v.name)));
+    };
+    let value = match v.text {
+      true => convert_text(&var.type_, v.value.as_str().unwrap_or_default().to_string()),
+      false => v.value
+    };
+    var.type_.check(&value)
+      .map_err(|err| ORError::VariableError(v.source.clone(), err.at_key(&v.name)))?;
+    project.var_values.insert(v.name, value);
+  }
+  Ok(())
+}
+
+/// The types of project variables, under the names they are referenced by
+pub fn var_scope(project: &Project) -> ParameterSpec {
+  project.vars.iter()
+    .map(|(k, v)| (format!("{}{}", VARS_PREFIX, k), Parameter { type_: v.type_.clone(), default: None }))
+    .collect()
+}
+
+/// The values of project variables, under the names they are referenced by.
+/// Optional variables without a value are null.
+pub fn var_params(project: &Project) -> Parameters {
+  project.vars.keys()
+    .map(|k| (format!("{}{}", VARS_PREFIX, k), project.var_values.get(k).cloned().unwrap_or(Value::Null)))
+    .collect()
+}
+
+/// Substitutes project variables and the matrix values of `artifact` in the options of an environment
+pub fn interpolate_options(project: &Project, artifact: &BuildDoc, options: &Parameters, loc: &YamlLocation) -> ORResult<Parameters> {
+  let mut vars = var_params(project);
+  vars.extend(matrix_params(artifact));
+  options.iter()
+    .map(|(k, v)| interpolate(v, &vars)
+      .map(|v| (k.clone(), v))
+      .map_err(|v| ORError::InterpolationError(loc.clone(), v.at_key(k))))
+    .collect()
+}
\ No newline at end of file